@@ -0,0 +1,746 @@
+// client.rs - off-chain instruction builders (feature "client"), re-exports the PDA helpers
+// Each builder derives the PDAs the handler expects and lays out the account metas
+// in the order the handler reads them.
+
+use solana_program::{
+    instruction::{AccountMeta, Instruction as SolInstruction},
+    pubkey::Pubkey,
+    sysvar::instructions as sysvar_instructions,
+};
+use solana_system_interface::program as system_program;
+
+use crate::{TOKEN_PROGRAM_ID, ChargerStatus, Instruction, PauseScope, SlashReason, SwapDirection, TariffBand};
+
+pub use crate::pda::*;
+
+// ---------- Instruction builders ----------
+
+/// Fleet paying for a session, needed by StopSession to pass the fleet accounts
+#[derive(Debug, Clone, Copy)]
+pub struct FleetPayment {
+    pub admin: Pubkey,
+    pub points_to_fleet: bool,
+}
+
+/// fee_token_accounts: (payer, admin) token accounts of the fee mint when the registration fee is in SPL tokens
+#[allow(clippy::too_many_arguments)]
+pub fn add_charger(program_id: &Pubkey, payer: &Pubkey, admin: &Pubkey, code: String, name: String, city: String,
+    address: String, latitude: f64, longitude: f64, power_kw: f32, rate_points_per_sec: u64, price_per_sec_lamports: u64,
+    device_pubkey: Pubkey, idle_threshold_secs: u64, idle_fee_per_min_lamports: u64, fee_token_accounts: Option<(Pubkey, Pubkey)>,
+) -> SolInstruction {
+    let charger = find_charger_address(program_id, &code, payer).0;
+    let mut accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new(charger, false),
+        AccountMeta::new(*admin, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+        AccountMeta::new(find_charger_vault_address(program_id, &charger).0, false),
+        AccountMeta::new_readonly(find_fee_waiver_address(program_id, payer).0, false),
+    ];
+    if let Some((payer_token_account, admin_token_account)) = fee_token_accounts {
+        accounts.push(AccountMeta::new(payer_token_account, false));
+        accounts.push(AccountMeta::new(admin_token_account, false));
+        accounts.push(AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false));
+    }
+    new_instruction(program_id, Instruction::AddCharger {
+        code, name, city, address, latitude, longitude, power_kw, rate_points_per_sec, price_per_sec_lamports,
+        device_pubkey, idle_threshold_secs, idle_fee_per_min_lamports,
+    }, accounts)
+}
+
+/// fleet_admin: admin of the fleet paying for this session, if any
+pub fn start_session(program_id: &Pubkey, driver: &Pubkey, charger: &Pubkey, start_ts: i64, delegate: Option<Pubkey>,
+    prepaid_lamports: u64, fleet_admin: Option<&Pubkey>,
+) -> SolInstruction {
+    let driver_pda = find_driver_address(program_id, driver).0;
+    let mut accounts = vec![
+        AccountMeta::new(*driver, true),
+        AccountMeta::new(driver_pda, false),
+        AccountMeta::new(find_session_address(program_id, charger, &driver_pda, start_ts).0, false),
+        AccountMeta::new(*charger, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new(find_reservation_address(program_id, charger).0, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    if let Some(fleet_admin) = fleet_admin {
+        let fleet = find_fleet_address(program_id, fleet_admin).0;
+        accounts.push(AccountMeta::new_readonly(fleet, false));
+        accounts.push(AccountMeta::new_readonly(find_fleet_member_address(program_id, &fleet, driver).0, false));
+    }
+    new_instruction(program_id, Instruction::StartSession { start_ts, delegate, prepaid_lamports }, accounts)
+}
+
+/// signer is the driver or the session delegate, or the charger operator or device once the session
+/// is past the refund window. The transaction must also carry the charger device's Ed25519 signature
+/// over session ‖ energy_wh ‖ end_ts ahead of this instruction.
+#[allow(clippy::too_many_arguments)]
+pub fn stop_session(program_id: &Pubkey, signer: &Pubkey, driver: &Pubkey, charger: &Pubkey, charger_authority: &Pubkey,
+    start_ts: i64, end_ts: i64, energy_wh: u64, fleet: Option<FleetPayment>,
+) -> SolInstruction {
+    let driver_pda = find_driver_address(program_id, driver).0;
+    let mut accounts = vec![
+        AccountMeta::new(*signer, true),
+        AccountMeta::new(find_session_address(program_id, charger, &driver_pda, start_ts).0, false),
+        AccountMeta::new(driver_pda, false),
+        AccountMeta::new(*charger, false),
+        AccountMeta::new(*charger_authority, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(sysvar_instructions::ID, false),
+        AccountMeta::new_readonly(find_stake_address(program_id, driver).0, false),
+        AccountMeta::new(*driver, false),
+        AccountMeta::new(find_state_address(program_id).0, false),
+    ];
+    if let Some(fleet) = fleet {
+        let fleet_pda = find_fleet_address(program_id, &fleet.admin).0;
+        accounts.push(AccountMeta::new_readonly(fleet_pda, false));
+        accounts.push(AccountMeta::new(find_fleet_member_address(program_id, &fleet_pda, driver).0, false));
+        accounts.push(AccountMeta::new(find_fleet_vault_address(program_id, &fleet_pda).0, false));
+        if fleet.points_to_fleet {
+            accounts.push(AccountMeta::new(find_driver_address(program_id, &fleet.admin).0, false));
+        }
+    }
+    new_instruction(program_id, Instruction::StopSession { end_ts, energy_wh }, accounts)
+}
+
+pub fn create_listing(program_id: &Pubkey, seller: &Pubkey, amount_points: u64, price_per_point_lamports: u64,
+    expires_at: Option<i64>,
+) -> SolInstruction {
+    let mut accounts = listing_seller_accounts(program_id, seller);
+    accounts.push(AccountMeta::new_readonly(find_state_address(program_id).0, false));
+    new_instruction(program_id, Instruction::CreateListing { amount_points, price_per_point_lamports, expires_at }, accounts)
+}
+
+pub fn create_auction_listing(program_id: &Pubkey, seller: &Pubkey, amount_points: u64, start_price: u64, floor_price: u64,
+    decay_secs: i64, expires_at: Option<i64>,
+) -> SolInstruction {
+    let mut accounts = listing_seller_accounts(program_id, seller);
+    accounts.push(AccountMeta::new_readonly(find_state_address(program_id).0, false));
+    new_instruction(program_id,
+        Instruction::CreateAuctionListing { amount_points, start_price, floor_price, decay_secs, expires_at }, accounts)
+}
+
+pub fn buy_from_listing(program_id: &Pubkey, buyer: &Pubkey, seller: &Pubkey, buy_amount_points: u64,
+    max_price_per_point_lamports: u64, min_points: Option<u64>,
+) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*buyer, true),
+        AccountMeta::new(find_user_address(program_id, buyer).0, false),
+        AccountMeta::new(find_listing_address(program_id, seller).0, false),
+        AccountMeta::new(*seller, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id,
+        Instruction::BuyFromListing { buy_amount_points, max_price_per_point_lamports, min_points }, accounts)
+}
+
+pub fn cancel_listing(program_id: &Pubkey, seller: &Pubkey) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*seller, true),
+        AccountMeta::new(find_driver_address(program_id, seller).0, false),
+        AccountMeta::new(find_listing_address(program_id, seller).0, false),
+    ];
+    new_instruction(program_id, Instruction::CancelListing {}, accounts)
+}
+
+/// signer is the charger operator or its device key
+pub fn set_charger_status(program_id: &Pubkey, signer: &Pubkey, charger: &Pubkey, status: ChargerStatus) -> SolInstruction {
+    let accounts = vec![AccountMeta::new_readonly(*signer, true), AccountMeta::new(*charger, false)];
+    new_instruction(program_id, Instruction::SetChargerStatus { status }, accounts)
+}
+
+pub fn reserve_charger(program_id: &Pubkey, driver: &Pubkey, charger: &Pubkey, charger_authority: &Pubkey, start: i64,
+    duration: i64,
+) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*driver, true),
+        AccountMeta::new(find_reservation_address(program_id, charger).0, false),
+        AccountMeta::new_readonly(*charger, false),
+        AccountMeta::new(*charger_authority, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::ReserveCharger { start, duration }, accounts)
+}
+
+pub fn report_charging_complete(program_id: &Pubkey, device: &Pubkey, session: &Pubkey, charger: &Pubkey) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*device, true),
+        AccountMeta::new(*session, false),
+        AccountMeta::new_readonly(*charger, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::ReportChargingComplete {}, accounts)
+}
+
+pub fn set_tariff_schedule(program_id: &Pubkey, operator: &Pubkey, charger: &Pubkey, bands: Vec<TariffBand>) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*operator, true),
+        AccountMeta::new(*charger, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::SetTariffSchedule { bands }, accounts)
+}
+
+pub fn stake(program_id: &Pubkey, owner: &Pubkey, amount: u64, tier: u8) -> SolInstruction {
+    let mut accounts = stake_accounts(program_id, owner);
+    accounts.push(AccountMeta::new_readonly(system_program::ID, false));
+    accounts.push(AccountMeta::new_readonly(find_state_address(program_id).0, false));
+    new_instruction(program_id, Instruction::Stake { amount, tier }, accounts)
+}
+
+pub fn unstake(program_id: &Pubkey, owner: &Pubkey) -> SolInstruction {
+    new_instruction(program_id, Instruction::Unstake {}, stake_accounts(program_id, owner))
+}
+
+pub fn initialize(program_id: &Pubkey, payer: &Pubkey, admin: Pubkey) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new(find_state_address(program_id).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(find_program_data_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::Initialize { admin }, accounts)
+}
+
+pub fn slash_charger(program_id: &Pubkey, admin: &Pubkey, charger: &Pubkey, slash_bps: u16, reason: SlashReason) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*admin, true),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+        AccountMeta::new(*charger, false),
+        AccountMeta::new(find_charger_vault_address(program_id, charger).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    new_instruction(program_id, Instruction::SlashCharger { slash_bps, reason }, accounts)
+}
+
+pub fn close_charger(program_id: &Pubkey, operator: &Pubkey, charger: &Pubkey) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*operator, true),
+        AccountMeta::new(*charger, false),
+        AccountMeta::new(find_charger_vault_address(program_id, charger).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    new_instruction(program_id, Instruction::CloseCharger {}, accounts)
+}
+
+pub fn transfer_points(program_id: &Pubkey, sender: &Pubkey, recipient: &Pubkey, amount: u64) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*sender, true),
+        AccountMeta::new(find_driver_address(program_id, sender).0, false),
+        AccountMeta::new(find_driver_address(program_id, recipient).0, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::TransferPoints { amount }, accounts)
+}
+
+pub fn create_fleet(program_id: &Pubkey, admin: &Pubkey, points_to_fleet: bool, max_per_session_lamports: u64) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*admin, true),
+        AccountMeta::new(find_fleet_address(program_id, admin).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::CreateFleet { points_to_fleet, max_per_session_lamports }, accounts)
+}
+
+pub fn fund_fleet(program_id: &Pubkey, funder: &Pubkey, fleet_admin: &Pubkey, amount: u64) -> SolInstruction {
+    let mut accounts = fleet_vault_accounts(program_id, funder, fleet_admin);
+    accounts.push(AccountMeta::new_readonly(find_state_address(program_id).0, false));
+    new_instruction(program_id, Instruction::FundFleet { amount }, accounts)
+}
+
+pub fn withdraw_fleet(program_id: &Pubkey, admin: &Pubkey, amount: u64) -> SolInstruction {
+    new_instruction(program_id, Instruction::WithdrawFleet { amount },
+        fleet_vault_accounts(program_id, admin, admin))
+}
+
+pub fn set_fleet_member(program_id: &Pubkey, admin: &Pubkey, driver: Pubkey, active: bool, spend_limit_lamports: u64) -> SolInstruction {
+    let fleet = find_fleet_address(program_id, admin).0;
+    let accounts = vec![
+        AccountMeta::new(*admin, true),
+        AccountMeta::new_readonly(fleet, false),
+        AccountMeta::new(find_fleet_member_address(program_id, &fleet, &driver).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::SetFleetMember { driver, active, spend_limit_lamports }, accounts)
+}
+
+pub fn create_bid(program_id: &Pubkey, buyer: &Pubkey, amount: u64, price_per_point: u64) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*buyer, true),
+        AccountMeta::new(find_bid_address(program_id, buyer).0, false),
+        AccountMeta::new(find_driver_address(program_id, buyer).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::CreateBid { amount, price_per_point }, accounts)
+}
+
+pub fn fill_bid(program_id: &Pubkey, seller: &Pubkey, buyer: &Pubkey, amount: u64) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*seller, true),
+        AccountMeta::new(find_driver_address(program_id, seller).0, false),
+        AccountMeta::new(find_bid_address(program_id, buyer).0, false),
+        AccountMeta::new(find_driver_address(program_id, buyer).0, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::FillBid { amount }, accounts)
+}
+
+pub fn cancel_bid(program_id: &Pubkey, buyer: &Pubkey) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*buyer, true),
+        AccountMeta::new(find_bid_address(program_id, buyer).0, false),
+    ];
+    new_instruction(program_id, Instruction::CancelBid {}, accounts)
+}
+
+/// sellers must be ordered by their listings' current price, cheapest first
+pub fn market_buy(program_id: &Pubkey, buyer: &Pubkey, sellers: &[Pubkey], max_points: u64, max_price_per_point: u64) -> SolInstruction {
+    let mut accounts = vec![
+        AccountMeta::new(*buyer, true),
+        AccountMeta::new(find_user_address(program_id, buyer).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    for seller in sellers {
+        accounts.push(AccountMeta::new(find_listing_address(program_id, seller).0, false));
+        accounts.push(AccountMeta::new(*seller, false));
+    }
+    new_instruction(program_id, Instruction::MarketBuy { max_points, max_price_per_point }, accounts)
+}
+
+pub fn expire_listing(program_id: &Pubkey, cranker: &Pubkey, seller: &Pubkey) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*cranker, true),
+        AccountMeta::new(find_driver_address(program_id, seller).0, false),
+        AccountMeta::new(find_listing_address(program_id, seller).0, false),
+        AccountMeta::new(*seller, false),
+    ];
+    new_instruction(program_id, Instruction::ExpireListing {}, accounts)
+}
+
+/// payer funds the pool accounts so admin can be a multisig PDA, pass the same key for both otherwise
+pub fn create_pool(program_id: &Pubkey, payer: &Pubkey, admin: &Pubkey, fee_bps: u16) -> SolInstruction {
+    let pool = find_pool_address(program_id).0;
+    let accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+        AccountMeta::new(pool, false),
+        AccountMeta::new(find_pool_vault_address(program_id, &pool).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    new_instruction(program_id, Instruction::CreatePool { fee_bps }, accounts)
+}
+
+pub fn add_liquidity(program_id: &Pubkey, owner: &Pubkey, amp_amount: u64, max_sol_lamports: u64) -> SolInstruction {
+    let mut accounts = liquidity_accounts(program_id, owner);
+    accounts.push(AccountMeta::new_readonly(find_state_address(program_id).0, false));
+    new_instruction(program_id, Instruction::AddLiquidity { amp_amount, max_sol_lamports }, accounts)
+}
+
+pub fn remove_liquidity(program_id: &Pubkey, owner: &Pubkey, shares: u64, min_amp: u64, min_sol_lamports: u64) -> SolInstruction {
+    new_instruction(program_id, Instruction::RemoveLiquidity { shares, min_amp, min_sol_lamports },
+        liquidity_accounts(program_id, owner))
+}
+
+pub fn swap(program_id: &Pubkey, trader: &Pubkey, amount_in: u64, min_amount_out: u64, direction: SwapDirection) -> SolInstruction {
+    let pool = find_pool_address(program_id).0;
+    let accounts = vec![
+        AccountMeta::new(*trader, true),
+        AccountMeta::new(find_driver_address(program_id, trader).0, false),
+        AccountMeta::new(pool, false),
+        AccountMeta::new(find_pool_vault_address(program_id, &pool).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::Swap { amount_in, min_amount_out, direction }, accounts)
+}
+
+pub fn set_pause(program_id: &Pubkey, admin: &Pubkey, scope: PauseScope, paused: bool) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::SetPause { scope, paused }, accounts)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn register_partner(program_id: &Pubkey, payer: &Pubkey, admin: &Pubkey, partner_program: Pubkey, budget_per_period: u64,
+    period_secs: i64, active: bool,
+) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+        AccountMeta::new(find_partner_address(program_id, &partner_program).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    new_instruction(program_id,
+        Instruction::RegisterPartner { partner_program, budget_per_period, period_secs, active }, accounts)
+}
+
+/// The partner program sends this through invoke_signed with its PARTNER_AUTHORITY_SEED
+pub fn award_points(program_id: &Pubkey, partner_program: &Pubkey, driver: &Pubkey, amount: u64, reason: String) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(find_partner_authority_address(partner_program).0, true),
+        AccountMeta::new(find_partner_address(program_id, partner_program).0, false),
+        AccountMeta::new(find_driver_address(program_id, driver).0, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::AwardPoints { amount, reason }, accounts)
+}
+
+pub fn propose_admin(program_id: &Pubkey, admin: &Pubkey, new_admin: Pubkey) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::ProposeAdmin { new_admin }, accounts)
+}
+
+pub fn accept_admin(program_id: &Pubkey, new_admin: &Pubkey) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*new_admin, true),
+        AccountMeta::new(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::AcceptAdmin {}, accounts)
+}
+
+/// fee_mint None charges the fee in lamports
+pub fn set_registration_fee(program_id: &Pubkey, admin: &Pubkey, fee: u64, fee_mint: Option<Pubkey>) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::SetRegistrationFee { fee, fee_mint }, accounts)
+}
+
+pub fn set_fee_waiver(program_id: &Pubkey, payer: &Pubkey, admin: &Pubkey, operator: Pubkey, waived: bool) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+        AccountMeta::new(find_fee_waiver_address(program_id, &operator).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    new_instruction(program_id, Instruction::SetFeeWaiver { operator, waived }, accounts)
+}
+
+pub fn set_auditor(program_id: &Pubkey, payer: &Pubkey, admin: &Pubkey, auditor: Pubkey, active: bool) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+        AccountMeta::new(find_auditor_address(program_id, &auditor).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    new_instruction(program_id, Instruction::SetAuditor { auditor, active }, accounts)
+}
+
+pub fn verify_charger(program_id: &Pubkey, auditor: &Pubkey, charger: &Pubkey, verified: bool) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*auditor, true),
+        AccountMeta::new_readonly(find_auditor_address(program_id, auditor).0, false),
+        AccountMeta::new(*charger, false),
+        AccountMeta::new_readonly(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::VerifyCharger { verified }, accounts)
+}
+
+pub fn set_emission_limits(program_id: &Pubkey, admin: &Pubkey, max_rate_points_per_sec: u64, epoch_secs: i64,
+    epoch_budget: u64,
+) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(find_state_address(program_id).0, false),
+    ];
+    new_instruction(program_id, Instruction::SetEmissionLimits { max_rate_points_per_sec, epoch_secs, epoch_budget }, accounts)
+}
+
+pub fn refund_session(program_id: &Pubkey, driver: &Pubkey, charger: &Pubkey, start_ts: i64) -> SolInstruction {
+    let driver_pda = find_driver_address(program_id, driver).0;
+    let accounts = vec![
+        AccountMeta::new(*driver, true),
+        AccountMeta::new(find_session_address(program_id, charger, &driver_pda, start_ts).0, false),
+        AccountMeta::new_readonly(driver_pda, false),
+        AccountMeta::new(*charger, false),
+    ];
+    new_instruction(program_id, Instruction::RefundSession {}, accounts)
+}
+
+pub fn expire_reservation(program_id: &Pubkey, cranker: &Pubkey, charger: &Pubkey, reserver: &Pubkey) -> SolInstruction {
+    let accounts = vec![
+        AccountMeta::new(*cranker, true),
+        AccountMeta::new(find_reservation_address(program_id, charger).0, false),
+        AccountMeta::new(*reserver, false),
+    ];
+    new_instruction(program_id, Instruction::ExpireReservation {}, accounts)
+}
+
+// ---------- Shared account layouts ----------
+
+fn new_instruction(program_id: &Pubkey, data: Instruction, accounts: Vec<AccountMeta>) -> SolInstruction {
+    SolInstruction::new_with_borsh(*program_id, &data, accounts)
+}
+
+fn listing_seller_accounts(program_id: &Pubkey, seller: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*seller, true),
+        AccountMeta::new(find_driver_address(program_id, seller).0, false),
+        AccountMeta::new(find_listing_address(program_id, seller).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ]
+}
+
+fn stake_accounts(program_id: &Pubkey, owner: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*owner, true),
+        AccountMeta::new(find_driver_address(program_id, owner).0, false),
+        AccountMeta::new(find_stake_address(program_id, owner).0, false),
+    ]
+}
+
+fn fleet_vault_accounts(program_id: &Pubkey, signer: &Pubkey, fleet_admin: &Pubkey) -> Vec<AccountMeta> {
+    let fleet = find_fleet_address(program_id, fleet_admin).0;
+    vec![
+        AccountMeta::new(*signer, true),
+        AccountMeta::new_readonly(fleet, false),
+        AccountMeta::new(find_fleet_vault_address(program_id, &fleet).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ]
+}
+
+fn liquidity_accounts(program_id: &Pubkey, owner: &Pubkey) -> Vec<AccountMeta> {
+    let pool = find_pool_address(program_id).0;
+    vec![
+        AccountMeta::new(*owner, true),
+        AccountMeta::new(find_driver_address(program_id, owner).0, false),
+        AccountMeta::new(pool, false),
+        AccountMeta::new(find_pool_vault_address(program_id, &pool).0, false),
+        AccountMeta::new(find_lp_address(program_id, &pool, owner).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshDeserialize;
+    use solana_ed25519_program::new_ed25519_instruction_with_signature;
+    use solana_program::{clock::Clock, native_token::LAMPORTS_PER_SOL};
+    use solana_program_test::{processor, ProgramTest};
+    use solana_sdk::{account::Account, signature::{Keypair, Signer}, transaction::Transaction};
+
+    use crate::{program_tests::program_data_account, ChargerAccount, DriverAccount, SessionAccount};
+
+    fn position(ix: &SolInstruction, key: &Pubkey) -> Vec<usize> {
+        ix.accounts.iter().enumerate().filter(|(_, meta)| meta.pubkey == *key).map(|(i, _)| i).collect()
+    }
+
+    #[test]
+    fn pausable_builders_pass_the_state_pda_where_the_handler_reads_it() {
+        let program_id = Pubkey::new_unique();
+        let state = find_state_address(&program_id).0;
+        let (user, other, charger) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let cases = [
+            ("add_charger", add_charger(&program_id, &user, &other, "c1".into(), String::new(), String::new(), String::new(),
+                0.0, 0.0, 11.0, 1, 1, Pubkey::new_unique(), 0, 0, None), 4, 7),
+            ("start_session", start_session(&program_id, &user, &charger, 0, None, 0, None), 6, 7),
+            ("create_listing", create_listing(&program_id, &user, 1, 1, None), 4, 5),
+            ("create_auction_listing", create_auction_listing(&program_id, &user, 1, 2, 1, 60, None), 4, 5),
+            ("buy_from_listing", buy_from_listing(&program_id, &user, &other, 1, 1, None), 5, 6),
+            ("reserve_charger", reserve_charger(&program_id, &user, &charger, &other, 0, 60), 5, 6),
+            ("report_charging_complete", report_charging_complete(&program_id, &user, &other, &charger), 3, 4),
+            ("set_tariff_schedule", set_tariff_schedule(&program_id, &user, &charger, vec![]), 2, 3),
+            ("stake", stake(&program_id, &user, 1, 0), 4, 5),
+            ("transfer_points", transfer_points(&program_id, &user, &other, 1), 3, 4),
+            ("create_fleet", create_fleet(&program_id, &user, false, 1), 3, 4),
+            ("fund_fleet", fund_fleet(&program_id, &user, &other, 1), 4, 5),
+            ("set_fleet_member", set_fleet_member(&program_id, &user, other, true, 1), 4, 5),
+            ("create_bid", create_bid(&program_id, &user, 1, 1), 4, 5),
+            ("fill_bid", fill_bid(&program_id, &user, &other, 1), 4, 5),
+            ("market_buy", market_buy(&program_id, &user, &[], 1, 1), 3, 4),
+            ("add_liquidity", add_liquidity(&program_id, &user, 1, 1), 6, 7),
+            ("swap", swap(&program_id, &user, 1, 0, SwapDirection::AmpToSol), 5, 6),
+            ("award_points", award_points(&program_id, &other, &user, 1, "ride".into()), 3, 4),
+            ("verify_charger", verify_charger(&program_id, &user, &charger, true), 3, 4),
+        ];
+        for (name, ix, state_index, len) in cases {
+            assert_eq!(ix.accounts.len(), len, "{name} account count");
+            assert_eq!(position(&ix, &state), vec![state_index], "{name} state position");
+            assert!(!ix.accounts[state_index].is_writable, "{name} state must be readonly");
+        }
+    }
+
+    #[test]
+    fn add_charger_appends_the_fee_token_accounts_after_the_fixed_ones() {
+        let program_id = Pubkey::new_unique();
+        let (payer, admin) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (payer_token, admin_token) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = add_charger(&program_id, &payer, &admin, "c1".into(), String::new(), String::new(), String::new(),
+            0.0, 0.0, 11.0, 1, 1, Pubkey::new_unique(), 0, 0, Some((payer_token, admin_token)));
+        assert_eq!(ix.accounts.len(), 10);
+        assert_eq!(ix.accounts[1].pubkey, find_charger_address(&program_id, "c1", &payer).0);
+        assert_eq!(ix.accounts[6].pubkey, find_fee_waiver_address(&program_id, &payer).0);
+        assert_eq!(ix.accounts[7].pubkey, payer_token);
+        assert_eq!(ix.accounts[8].pubkey, admin_token);
+        assert_eq!(ix.accounts[9].pubkey, TOKEN_PROGRAM_ID);
+    }
+
+    #[test]
+    fn start_session_puts_the_fleet_accounts_after_the_state() {
+        let program_id = Pubkey::new_unique();
+        let (driver, charger, fleet_admin) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = start_session(&program_id, &driver, &charger, 1_700_000_000, None, 0, Some(&fleet_admin));
+        let driver_pda = find_driver_address(&program_id, &driver).0;
+        let fleet = find_fleet_address(&program_id, &fleet_admin).0;
+        assert_eq!(ix.accounts.len(), 9);
+        assert!(ix.accounts[0].is_signer);
+        assert_eq!(ix.accounts[2].pubkey, find_session_address(&program_id, &charger, &driver_pda, 1_700_000_000).0);
+        assert!(ix.accounts[3].is_writable, "charger tracks open sessions");
+        assert_eq!(ix.accounts[5].pubkey, find_reservation_address(&program_id, &charger).0);
+        assert_eq!(ix.accounts[6].pubkey, find_state_address(&program_id).0);
+        assert_eq!(ix.accounts[7].pubkey, fleet);
+        assert_eq!(ix.accounts[8].pubkey, find_fleet_member_address(&program_id, &fleet, &driver).0);
+    }
+
+    #[test]
+    fn stop_session_passes_the_state_once_and_writable() {
+        let program_id = Pubkey::new_unique();
+        let (driver, charger, authority, fleet_admin) =
+            (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let state = find_state_address(&program_id).0;
+
+        let ix = stop_session(&program_id, &driver, &driver, &charger, &authority, 10, 20, 1_000, None);
+        assert_eq!(ix.accounts.len(), 10);
+        assert_eq!(position(&ix, &state), vec![9]);
+        assert!(ix.accounts[9].is_writable, "the epoch budget is written back");
+        assert_eq!(ix.accounts[6].pubkey, sysvar_instructions::ID);
+        assert_eq!(ix.accounts[8].pubkey, driver);
+
+        let fleet = find_fleet_address(&program_id, &fleet_admin).0;
+        let ix = stop_session(&program_id, &driver, &driver, &charger, &authority, 10, 20, 1_000,
+            Some(FleetPayment { admin: fleet_admin, points_to_fleet: true }));
+        assert_eq!(ix.accounts.len(), 14);
+        assert_eq!(position(&ix, &state), vec![9]);
+        assert_eq!(ix.accounts[10].pubkey, fleet);
+        assert_eq!(ix.accounts[11].pubkey, find_fleet_member_address(&program_id, &fleet, &driver).0);
+        assert_eq!(ix.accounts[12].pubkey, find_fleet_vault_address(&program_id, &fleet).0);
+        assert_eq!(ix.accounts[13].pubkey, find_driver_address(&program_id, &fleet_admin).0);
+    }
+
+    #[test]
+    fn market_buy_lists_the_sellers_after_the_state() {
+        let program_id = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        let sellers = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let ix = market_buy(&program_id, &buyer, &sellers, 10, 5);
+        assert_eq!(ix.accounts.len(), 8);
+        assert_eq!(ix.accounts[3].pubkey, find_state_address(&program_id).0);
+        for (i, seller) in sellers.iter().enumerate() {
+            assert_eq!(ix.accounts[4 + 2 * i].pubkey, find_listing_address(&program_id, seller).0);
+            assert_eq!(ix.accounts[5 + 2 * i].pubkey, *seller);
+        }
+    }
+
+    #[test]
+    fn initialize_passes_the_program_data_account() {
+        let program_id = Pubkey::new_unique();
+        let ix = initialize(&program_id, &Pubkey::new_unique(), Pubkey::new_unique());
+        assert_eq!(ix.accounts.len(), 4);
+        assert_eq!(ix.accounts[1].pubkey, find_state_address(&program_id).0);
+        assert_eq!(ix.accounts[3].pubkey, find_program_data_address(&program_id).0);
+        assert!(!ix.accounts[3].is_writable);
+    }
+
+    #[test]
+    fn unpausable_builders_do_not_pass_the_state() {
+        let program_id = Pubkey::new_unique();
+        let state = find_state_address(&program_id).0;
+        let (driver, charger) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let ix = refund_session(&program_id, &driver, &charger, 10);
+        let driver_pda = find_driver_address(&program_id, &driver).0;
+        assert_eq!(ix.accounts.len(), 4);
+        assert_eq!(ix.accounts[1].pubkey, find_session_address(&program_id, &charger, &driver_pda, 10).0);
+        assert!(ix.accounts[3].is_writable, "charger open session count is decremented");
+
+        let ix = expire_reservation(&program_id, &Pubkey::new_unique(), &charger, &driver);
+        assert_eq!(ix.accounts.len(), 3);
+        assert_eq!(ix.accounts[1].pubkey, find_reservation_address(&program_id, &charger).0);
+        assert!(ix.accounts[2].is_writable);
+
+        for ix in [refund_session(&program_id, &driver, &charger, 10), expire_reservation(&program_id, &driver, &charger, &driver),
+            close_charger(&program_id, &driver, &charger), cancel_listing(&program_id, &driver), cancel_bid(&program_id, &driver),
+            unstake(&program_id, &driver), withdraw_fleet(&program_id, &driver, 1), remove_liquidity(&program_id, &driver, 1, 0, 0)] {
+            assert!(position(&ix, &state).is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn session_settles_through_the_builders() {
+        let program_id = Pubkey::new_unique();
+        let operator = Keypair::new();
+        let driver = Keypair::new();
+        let device = Keypair::new();
+        let mut program_test = ProgramTest::new("contract", program_id, processor!(crate::process_instruction));
+        program_test.add_account(find_program_data_address(&program_id).0, program_data_account(&operator.pubkey()));
+        for wallet in [&operator, &driver] {
+            program_test.add_account(wallet.pubkey(),
+                Account {lamports: 10 * LAMPORTS_PER_SOL, owner: system_program::ID, ..Account::default()});
+        }
+        let context = program_test.start_with_context().await;
+        let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        let start_ts = clock.unix_timestamp;
+        let end_ts = start_ts + 600;
+
+        let charger = find_charger_address(&program_id, "c1", &operator.pubkey()).0;
+        let instructions = [
+            (initialize(&program_id, &operator.pubkey(), operator.pubkey()), &operator),
+            (add_charger(&program_id, &operator.pubkey(), &operator.pubkey(), "c1".into(), "Depot".into(), "Pune".into(),
+                "Bay 1".into(), 18.5, 73.8, 11.0, 10, 1, device.pubkey(), 0, 0, None), &operator),
+            (start_session(&program_id, &driver.pubkey(), &charger, start_ts, None, 0, None), &driver),
+        ];
+        for (ix, signer) in instructions {
+            let tx = Transaction::new_signed_with_payer(&[ix], Some(&context.payer.pubkey()), &[&context.payer, signer],
+                context.last_blockhash);
+            context.banks_client.process_transaction(tx).await.unwrap();
+        }
+
+        context.set_sysvar(&Clock {unix_timestamp: end_ts, ..clock});
+        let driver_pda = find_driver_address(&program_id, &driver.pubkey()).0;
+        let session = find_session_address(&program_id, &charger, &driver_pda, start_ts).0;
+        // 11 kW for 600 s meters at most 1833 Wh, so this reading earns the full duration
+        let energy_wh = 2_000u64;
+        let mut message = session.to_bytes().to_vec();
+        message.extend_from_slice(&energy_wh.to_le_bytes());
+        message.extend_from_slice(&end_ts.to_le_bytes());
+        let signature = <[u8; 64]>::from(device.sign_message(&message));
+        let attestation = new_ed25519_instruction_with_signature(&message, &signature, &device.pubkey().to_bytes());
+        let stop = stop_session(&program_id, &driver.pubkey(), &driver.pubkey(), &charger, &operator.pubkey(), start_ts, end_ts,
+            energy_wh, None);
+        let blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let tx = Transaction::new_signed_with_payer(&[attestation, stop], Some(&context.payer.pubkey()),
+            &[&context.payer, &driver], blockhash);
+        context.banks_client.process_transaction(tx).await.unwrap();
+
+        let account_data = |account: Option<Account>| account.expect("account exists").data;
+        let session_account = SessionAccount::try_from_slice(
+            &account_data(context.banks_client.get_account(session).await.unwrap())).unwrap();
+        let driver_account = DriverAccount::try_from_slice(
+            &account_data(context.banks_client.get_account(driver_pda).await.unwrap())).unwrap();
+        let charger_account = ChargerAccount::try_from_slice(
+            &account_data(context.banks_client.get_account(charger).await.unwrap())).unwrap();
+        // 600 s at 10 points/sec, an unverified charger earns a quarter of it
+        assert!(session_account.settled);
+        assert_eq!(session_account.energy_wh, energy_wh);
+        assert_eq!(session_account.points_awarded, 1_500);
+        assert_eq!(driver_account.amp_balance, 1_500);
+        assert_eq!(charger_account.open_sessions, 0);
+    }
+}
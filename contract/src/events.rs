@@ -0,0 +1,94 @@
+// events.rs - structured events for indexers
+// Every successful handler logs one or more Borsh-serialized `Event`s with sol_log_data,
+// they show up in the transaction logs as "Program data: <base64>".
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{log::sol_log_data, pubkey::Pubkey};
+
+use crate::{ChargerStatus, PauseScope, SlashReason, SwapDirection};
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum Event {
+    ChargerAdded { charger: Pubkey, authority: Pubkey, device_pubkey: Pubkey, bond_lamports: u64 },
+    SessionStarted { session: Pubkey, charger: Pubkey, driver: Pubkey, start_ts: i64, fleet: Pubkey, delegate: Pubkey, escrow_lamports: u64 },
+    SessionSettled {
+        session: Pubkey,
+        charger: Pubkey,
+        driver: Pubkey,
+        end_ts: i64,
+        energy_wh: u64,
+        total_price_lamports: u64,
+        idle_fee_lamports: u64,
+        points_awarded: u64,
+    },
+    // also emitted when an open listing is topped up, with the new totals
+    ListingCreated { listing: Pubkey, seller: Pubkey, amount_total: u64, price_per_point_lamports: u64, expires_at: i64 },
+    Purchase { listing: Pubkey, buyer: Pubkey, seller: Pubkey, amount_points: u64, price_per_point_lamports: u64 },
+    ListingCancelled { listing: Pubkey, seller: Pubkey, returned_points: u64 },
+    ChargerStatusChanged { charger: Pubkey, status: ChargerStatus, at: i64 },
+    ChargerReserved { charger: Pubkey, driver: Pubkey, start_ts: i64, end_ts: i64, deposit_lamports: u64 },
+    ChargingCompleted { session: Pubkey, charger: Pubkey, at: i64 },
+    TariffScheduleSet { charger: Pubkey, band_count: u8 },
+    Staked { owner: Pubkey, amount: u64, tier: u8, lock_until: i64 },
+    UnstakeRequested { owner: Pubkey, withdrawable_at: i64 },
+    Unstaked { owner: Pubkey, amount: u64 },
+    Initialized { admin: Pubkey },
+    ChargerSlashed { charger: Pubkey, lamports: u64, reason: SlashReason },
+    ChargerClosed { charger: Pubkey, refunded_bond_lamports: u64 },
+    PointsTransferred { from: Pubkey, to: Pubkey, amount: u64 },
+    FleetCreated { fleet: Pubkey, admin: Pubkey, points_to_fleet: bool, max_per_session_lamports: u64 },
+    FleetFunded { fleet: Pubkey, funder: Pubkey, lamports: u64 },
+    FleetWithdrawn { fleet: Pubkey, lamports: u64 },
+    FleetMemberSet { fleet: Pubkey, driver: Pubkey, active: bool, spend_limit_lamports: u64 },
+    BidPlaced { bid: Pubkey, buyer: Pubkey, amount_total: u64, price_per_point_lamports: u64 },
+    BidFilled { bid: Pubkey, buyer: Pubkey, seller: Pubkey, amount_points: u64, lamports: u64 },
+    BidCancelled { bid: Pubkey, buyer: Pubkey, refunded_lamports: u64 },
+    // one per MarketBuy, after a Purchase for each listing it filled
+    MarketBuyFilled { buyer: Pubkey, amount_points: u64, lamports: u64 },
+    ListingExpired { listing: Pubkey, seller: Pubkey, returned_points: u64 },
+    PoolCreated { pool: Pubkey, fee_bps: u16 },
+    LiquidityAdded { pool: Pubkey, owner: Pubkey, amp_amount: u64, sol_lamports: u64, shares: u64 },
+    LiquidityRemoved { pool: Pubkey, owner: Pubkey, amp_amount: u64, sol_lamports: u64, shares: u64 },
+    Swapped { pool: Pubkey, trader: Pubkey, direction: SwapDirection, amount_in: u64, amount_out: u64 },
+    AuctionListingCreated {
+        listing: Pubkey,
+        seller: Pubkey,
+        amount_points: u64,
+        start_price: u64,
+        floor_price: u64,
+        start_ts: i64,
+        decay_secs: i64,
+        expires_at: i64,
+    },
+    PartnerRegistered { partner_program: Pubkey, budget_per_period: u64, period_secs: i64, active: bool },
+    // driver is the wallet owning the credited DriverAccount
+    PointsAwarded { partner_program: Pubkey, driver: Pubkey, amount: u64, reason: String },
+    PauseSet { scope: PauseScope, paused: bool },
+    AdminProposed { admin: Pubkey, pending_admin: Pubkey },
+    AdminChanged { old_admin: Pubkey, new_admin: Pubkey },
+    // fee_mint is Pubkey::default() for lamports
+    RegistrationFeeSet { fee: u64, fee_mint: Pubkey },
+    FeeWaiverSet { operator: Pubkey, waived: bool },
+    AuditorSet { auditor: Pubkey, active: bool },
+    ChargerVerified { charger: Pubkey, auditor: Pubkey, verified: bool, at: i64 },
+    EmissionLimitsSet { max_rate_points_per_sec: u64, epoch_secs: i64, epoch_budget: u64 },
+    // driver is the wallet that got the escrow and rent back
+    SessionRefunded { session: Pubkey, charger: Pubkey, driver: Pubkey, escrow_lamports: u64 },
+    ChargerCloseRequested { charger: Pubkey, closable_at: i64 },
+    // claimed = the reserving driver started a session, otherwise it expired
+    ReservationClosed { charger: Pubkey, driver: Pubkey, claimed: bool },
+}
+
+impl Event {
+    pub(crate) fn emit(&self) {
+        // serializing into a Vec cannot fail for these types
+        if let Ok(data) = borsh::to_vec(self) {
+            sol_log_data(&[&data]);
+        }
+    }
+
+    /// Decode the base64-decoded payload of a "Program data:" log line
+    pub fn decode(data: &[u8]) -> Result<Self, std::io::Error> {
+        Self::try_from_slice(data)
+    }
+}
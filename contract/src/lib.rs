@@ -6,29 +6,114 @@ use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     clock::Clock,
-    entrypoint,
+    ed25519_program,
     entrypoint::ProgramResult,
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
-    program_pack::IsInitialized,
     pubkey::Pubkey,
     rent::Rent,
-    system_instruction,
-    sysvar::Sysvar, system_program
+    sysvar::{instructions as sysvar_instructions, Sysvar},
 };
+use solana_sdk_ids::bpf_loader_upgradeable;
+use solana_system_interface::instruction as system_instruction;
+
+pub mod events;
+pub mod pda;
+pub use events::Event;
+#[cfg(any(feature = "client", test))]
+pub mod client;
+#[cfg(test)]
+mod program_tests;
 
-/// 0.5 SOL registration fee
 const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+/// Registration fee set at Initialize, the admin can change it later with SetRegistrationFee
 const REG_FEE_LAMPORTS: u64 = LAMPORTS_PER_SOL / 2; // 0.5 SOL
+/// SPL Token program, registration fees in a mint are paid with its Transfer instruction
+const TOKEN_PROGRAM_ID: Pubkey = solana_program::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+const TOKEN_TRANSFER_IX: u8 = 3;
+/// Refundable operator bond held in the charger vault, slashable by the admin
+const CHARGER_BOND_LAMPORTS: u64 = LAMPORTS_PER_SOL; // 1 SOL
+// CloseCharger refunds the bond this long after the operator asked to close, so disputes can still be slashed
+const CHARGER_UNBONDING_SECS: i64 = 7 * 24 * 60 * 60;
 
 // PDA seeds:
-const STATE_SEED: &[u8] = b"charge2earn_state";
-const CHARGER_SEED: &[u8] = b"charger1"; // + charger_code (bytes) + charger_pubkey
-const DRIVER_SEED: &[u8] = b"driver1"; // + driver_pubkey
-const SESSION_SEED: &[u8] = b"session1"; // + charger_pubkey + driver_pubkey + start_ts
-const LISTING_SEED: &[u8] = b"listing1"; // + seller_pubkey 
-const USER_SEED: &[u8] = b"user1"; // + user_pubkey 
+pub const STATE_SEED: &[u8] = b"charge2earn_state";
+pub const CHARGER_SEED: &[u8] = b"charger1"; // + charger_code (bytes) + charger_pubkey
+pub const DRIVER_SEED: &[u8] = b"driver1"; // + driver_pubkey
+pub const SESSION_SEED: &[u8] = b"session1"; // + charger_pubkey + driver_pubkey + start_ts
+pub const LISTING_SEED: &[u8] = b"listing1"; // + seller_pubkey 
+pub const USER_SEED: &[u8] = b"user1"; // + user_pubkey 
+pub const RESERVATION_SEED: &[u8] = b"reservation1"; // + charger_pubkey
+pub const STAKE_SEED: &[u8] = b"stake1"; // + driver_pubkey
+pub const CHARGER_VAULT_SEED: &[u8] = b"charger_vault1"; // + charger_pubkey, system-owned, holds the bond
+pub const FLEET_SEED: &[u8] = b"fleet1"; // + fleet_admin_pubkey
+pub const FLEET_VAULT_SEED: &[u8] = b"fleet_vault1"; // + fleet_pubkey, system-owned, holds the fleet's SOL
+pub const FLEET_MEMBER_SEED: &[u8] = b"fleet_member1"; // + fleet_pubkey + driver_pubkey
+pub const BID_SEED: &[u8] = b"bid1"; // + buyer_pubkey
+pub const POOL_SEED: &[u8] = b"pool1"; // single AMP/SOL pool
+pub const POOL_VAULT_SEED: &[u8] = b"pool_vault1"; // + pool_pubkey, system-owned, holds the SOL reserve
+pub const LP_SEED: &[u8] = b"lp1"; // + pool_pubkey + owner_pubkey
+pub const PARTNER_SEED: &[u8] = b"partner1"; // + partner_program_id
+pub const FEE_WAIVER_SEED: &[u8] = b"fee_waiver1"; // + operator_pubkey
+pub const AUDITOR_SEED: &[u8] = b"auditor1"; // + auditor_pubkey
+/// Seed of the PDA a partner program signs AwardPoints with, derived under the partner's program id
+pub const PARTNER_AUTHORITY_SEED: &[u8] = b"charge2earn_partner";
+
+// Reservations: non-refundable deposit paid to the charger operator
+const RESERVATION_DEPOSIT_LAMPORTS: u64 = LAMPORTS_PER_SOL / 100; // 0.01 SOL
+const MAX_RESERVATION_SECS: i64 = 2 * 60 * 60;
+const MAX_RESERVATION_LEAD_SECS: i64 = 24 * 60 * 60;
+// Other drivers cannot start a session this long before a reservation begins
+const RESERVATION_GUARD_SECS: i64 = 30 * 60;
+
+// Time-of-use tariffs: multipliers are in basis points of the flat charger rates
+const MAX_TARIFF_BANDS: usize = 8;
+const BPS_DENOMINATOR: u64 = 10_000;
+const MAX_TARIFF_MULTIPLIER_BPS: u16 = 50_000; // 5x
+
+// AMP staking tiers: (lock-up secs, max points boost in bps)
+const STAKE_TIERS: [(i64, u64); 3] = [
+    (30 * 24 * 60 * 60, 1_000),  // 30 days, up to +10%
+    (90 * 24 * 60 * 60, 2_500),  // 90 days, up to +25%
+    (180 * 24 * 60 * 60, 5_000), // 180 days, up to +50%
+];
+const STAKE_BOOST_CAP_POINTS: u64 = 100_000; // stake needed for a tier's full boost
+const UNSTAKE_COOLDOWN_SECS: i64 = 3 * 24 * 60 * 60;
+
+// AMP/SOL pool
+const MAX_POOL_FEE_BPS: u16 = 1_000; // 10%
+// shares of the first deposit that stay in the pool for good, so the share price cannot be inflated from a dust supply
+const MINIMUM_LIQUIDITY: u64 = 1_000;
+
+// AMP emission limits set at Initialize, the admin can change them with SetEmissionLimits
+const DEFAULT_MAX_RATE_POINTS_PER_SEC: u64 = 100;
+const DEFAULT_EPOCH_SECS: i64 = 24 * 60 * 60;
+const DEFAULT_EPOCH_BUDGET_POINTS: u64 = 100_000_000;
+
+// Chargers not yet verified by an auditor earn this share of their points rate
+const UNVERIFIED_POINTS_BPS: u64 = 2_500; // 25%
+
+// Partner awards
+const MAX_AWARD_REASON_LEN: usize = 64;
+
+const LISTING_ACCOUNT_SIZE: usize = 1+ 1+ 32+ 8+ 8+ 8+ 1+ 8+ 8+ 8+ 8;
+
+// Meter attestation signed by the charger's device key:
+// session_pda (32) + energy_wh (u64 le) + end_ts (i64 le)
+const ATTESTATION_MESSAGE_LEN: usize = 32 + 8 + 8;
+
+// A charger that has not reported status for this long is treated as offline
+const HEARTBEAT_TIMEOUT_SECS: i64 = 5 * 60;
+
+// StartSession's start_ts must be within this many seconds of the cluster clock
+const START_TS_TOLERANCE_SECS: i64 = 5 * 60;
+
+// An unsettled session can be refunded to the driver this long after it started
+const SESSION_REFUND_AFTER_SECS: i64 = 2 * 24 * 60 * 60;
+
+// Rated power accepted at AddCharger, session points scale with energy against it
+const MAX_CHARGER_POWER_KW: f32 = 1_000.0;
 
 // ----- Instructions -----
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
@@ -38,25 +123,212 @@ pub enum Instruction {
         name: String,city: String,address: String,latitude: f64,longitude: f64,
         power_kw: f32, rate_points_per_sec: u64,       // points per second
         price_per_sec_lamports: u64,    // how many lamports per second driver pays
+        device_pubkey: Pubkey,          // hardware key that signs meter attestations
+        idle_threshold_secs: u64,       // grace period after charging completes, 0 disables idle fees
+        idle_fee_per_min_lamports: u64, // charged per started minute of idle time past the threshold
     },
-    StartSession { start_ts: i64 },
+    // / delegate: optional key (e.g. the car's app) allowed to stop this session only; it needs
+    // / prepaid_lamports escrowed in the session, since it cannot spend from the driver wallet
+    StartSession { start_ts: i64, delegate: Option<Pubkey>, prepaid_lamports: u64 },
 
     // / Stop session: compute duration, transfer SOL to charger owner, credit AMP points to driver
-    StopSession { end_ts: i64 },
+    // / Requires an Ed25519 program instruction earlier in the same tx, signed by the charger device
+    // / Signed by the driver or delegate, or past SESSION_REFUND_AFTER_SECS by the charger operator or device
+    StopSession { end_ts: i64, energy_wh: u64 },
 
-    // / Create listing (seller reserves points)   
-    CreateListing { amount_points: u64, price_per_point_lamports: u64 },
+    // / Create listing (seller reserves points), optionally expiring at a unix ts
+    CreateListing { amount_points: u64, price_per_point_lamports: u64, expires_at: Option<i64> },
 
-    // / Buy from listing    
-    BuyFromListing { buy_amount_points: u64 },
+    // / Buy from listing, failing if the price moved above max_price_per_point_lamports.
+    // / min_points: accept a partial fill of at least this many points, None = all or nothing
+    BuyFromListing { buy_amount_points: u64, max_price_per_point_lamports: u64, min_points: Option<u64> },
 
     // / Cancel listing 
     CancelListing {},
+
+    // / OCPP status notification / heartbeat, signed by the charger operator or device key
+    SetChargerStatus { status: ChargerStatus },
+
+    // / Book a charger for [start, start + duration), pays a non-refundable deposit to the operator
+    ReserveCharger { start: i64, duration: i64 },
+
+    // / Charger device reports the vehicle stopped drawing power; later time is billed as idle
+    ReportChargingComplete {},
+
+    // / Replace the charger's hour-of-day tariff bands (operator only, no open sessions), empty = flat pricing
+    SetTariffSchedule { bands: Vec<TariffBand> },
+
+    // / Lock AMP points from the driver account to boost points earned in StopSession
+    Stake { amount: u64, tier: u8 },
+
+    // / First call (after lock-up) starts the cool-down, second call (after cool-down) returns the points
+    Unstake {},
+
+    // / One-time setup of the global state PDA, signed by the program upgrade authority
+    Initialize { admin: Pubkey },
+
+    // / Admin slashes part of a charger's bond to the admin account
+    SlashCharger { slash_bps: u16, reason: SlashReason },
+
+    // / Operator closes the charger: the first call stops new sessions and starts CHARGER_UNBONDING_SECS,
+    // / a second call after that with no open sessions reclaims the remaining bond and the account rent
+    CloseCharger {},
+
+    // / Send AMP points from the signer's driver account to another driver account
+    TransferPoints { amount: u64 },
+
+    // / Create the signer's fleet; sessions of its members are paid from the fleet vault
+    CreateFleet { points_to_fleet: bool, max_per_session_lamports: u64 },
+
+    // / Deposit SOL into a fleet vault (anyone)
+    FundFleet { amount: u64 },
+
+    // / Fleet admin withdraws SOL from the fleet vault
+    WithdrawFleet { amount: u64 },
+
+    // / Fleet admin adds/updates a member driver and its cumulative spending limit
+    SetFleetMember { driver: Pubkey, active: bool, spend_limit_lamports: u64 },
+
+    // / Create or top up a standing bid, escrowing amount * price_per_point lamports in the bid PDA
+    CreateBid { amount: u64, price_per_point: u64 },
+
+    // / Seller delivers AMP from their driver account into a bid and takes the escrowed lamports
+    FillBid { amount: u64 },
+
+    // / Cancel bid, refunding the remaining escrow to the buyer
+    CancelBid {},
+
+    // / Buy up to max_points across listings passed as remaining accounts
+    // / (listing PDA, seller wallet) pairs sorted cheapest-first; fails if a needed listing is above max_price_per_point
+    MarketBuy { max_points: u64, max_price_per_point: u64 },
+
+    // / Permissionless crank: return an expired listing's points to the seller and close it
+    ExpireListing {},
+
+    // / Admin creates the AMP/SOL constant-product pool
+    CreatePool { fee_bps: u16 },
+
+    // / Deposit AMP from the driver account plus matching SOL, minting LP shares
+    AddLiquidity { amp_amount: u64, max_sol_lamports: u64 },
+
+    // / Burn LP shares for a pro-rata cut of both reserves
+    RemoveLiquidity { shares: u64, min_amp: u64, min_sol_lamports: u64 },
+
+    // / Swap against the pool on x*y=k, the fee stays in the pool
+    Swap { amount_in: u64, min_amount_out: u64, direction: SwapDirection },
+
+    // / Dutch auction listing: price decays linearly from start_price to floor_price over decay_secs
+    CreateAuctionListing { amount_points: u64, start_price: u64, floor_price: u64, decay_secs: i64, expires_at: Option<i64> },
+
+    // / Admin whitelists (or updates) a partner program allowed to award up to budget_per_period points every period_secs
+    RegisterPartner { partner_program: Pubkey, budget_per_period: u64, period_secs: i64, active: bool },
+
+    // / CPI from a registered partner program, signed by its PARTNER_AUTHORITY_SEED PDA: credit points to a driver
+    AwardPoints { amount: u64, reason: String },
+
+    // / Admin pauses or resumes the whole program or one subsystem; cancels, withdrawals and StopSession stay open
+    SetPause { scope: PauseScope, paused: bool },
+
+    // / Admin nominates its successor, which may be a multisig PDA; Pubkey::default() withdraws the nomination
+    ProposeAdmin { new_admin: Pubkey },
+
+    // / The nominated admin signs to take over
+    AcceptAdmin {},
+
+    // / Admin sets the charger registration fee; fee_mint None = lamports, Some(mint) = base units of that SPL mint
+    SetRegistrationFee { fee: u64, fee_mint: Option<Pubkey> },
+
+    // / Admin waives (or reinstates) the registration fee for an operator
+    SetFeeWaiver { operator: Pubkey, waived: bool },
+
+    // / Admin approves or revokes an auditor
+    SetAuditor { auditor: Pubkey, active: bool },
+
+    // / An active auditor marks a charger as verified (or withdraws verification) after checking it on site
+    VerifyCharger { verified: bool },
+
+    // / Admin sets the max charger rate accepted by AddCharger and the AMP issued by StopSession per epoch
+    SetEmissionLimits { max_rate_points_per_sec: u64, epoch_secs: i64, epoch_budget: u64 },
+
+    // / Driver closes a session nobody settled within SESSION_REFUND_AFTER_SECS, getting back escrow and rent,
+    // / unless the device reported charging complete, then it has to go through StopSession
+    RefundSession {},
+
+    // / Permissionless crank: close a reservation whose window ended unclaimed, rent goes back to the reserver
+    ExpireReservation {},
+}
+
+/// What SetPause switches. Every pausable instruction takes the state PDA as its last account.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseScope {
+    All,
+    Registration,
+    Sessions,
+    Marketplace,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingKind {
+    FixedPrice,
+    DutchAuction,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapDirection {
+    SolToAmp,
+    AmpToSol,
+}
+
+/// Why a charger bond was slashed
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlashReason {
+    Fraud,
+    Offline, // only allowed while the charger is offline or its heartbeat is stale
+}
+
+/// Hour-of-day band (UTC) covering [start_hour, end_hour), wrapping past midnight when start > end.
+/// Bands of one charger may not overlap; hours outside every band use the flat charger rates.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TariffBand {
+    pub start_hour: u8,
+    pub end_hour: u8,
+    pub price_multiplier_bps: u16,
+    pub points_multiplier_bps: u16,
+}
+
+/// Charger availability, mirrors the OCPP connector status we care about
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChargerStatus {
+    Available,
+    Charging,
+    Faulted,
+    Offline,
 }
 
 // ----- State structs -----
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct StateAccount {
+    pub accountType:u8,      //8
+    pub is_initialized: bool,
+    pub admin: Pubkey, // receives registration fees and slashed bonds, never pays rent so it can be a multisig PDA
+    pub pending_admin: Pubkey, // Pubkey::default() = no transfer in progress
+    pub registration_fee: u64,
+    pub fee_mint: Pubkey, // Pubkey::default() = fee in lamports
+    pub max_rate_points_per_sec: u64,
+    pub epoch_secs: i64,
+    pub epoch_budget: u64, // points StopSession may award per epoch, later sessions are pro-rated to what is left
+    pub epoch_start: i64,
+    pub epoch_issued: u64,
+    pub paused: bool,
+    pub registration_paused: bool,
+    pub sessions_paused: bool,
+    pub marketplace_paused: bool,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
 pub struct ChargerAccount {
     pub accountType:u8,      //1
     pub is_initialized: bool,
@@ -70,9 +342,24 @@ pub struct ChargerAccount {
     pub power_kw: f32,
     pub rate_points_per_sec: u64,
     pub price_per_sec_lamports: u64,
+    pub device_pubkey: Pubkey, // signs meter attestations for StopSession
+    pub status: ChargerStatus,
+    pub last_heartbeat: i64, // unix ts of the last SetChargerStatus
+    pub idle_threshold_secs: u64, // 0 = no idle fee
+    pub idle_fee_per_min_lamports: u64,
+    pub tariff_band_count: u8,
+    pub tariff_bands: [TariffBand; MAX_TARIFF_BANDS],
+    pub bond_lamports: u64, // currently held in the charger vault
+    pub slash_count: u8,
+    pub verified: bool, // unverified chargers earn UNVERIFIED_POINTS_BPS of their points rate
+    pub verified_at: i64,
+    pub verified_by: Pubkey, // auditor of the last VerifyCharger
+    pub closing_requested_at: i64, // first CloseCharger call, 0 = open for sessions
+    pub open_sessions: u32, // started and not yet stopped or refunded
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
 pub struct DriverAccount {
     pub accountType:u8,     //2
     pub is_initialized: bool,
@@ -81,6 +368,7 @@ pub struct DriverAccount {
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
 pub struct UserAccount {
     pub accountType:u8,           //5
     // pub is_initialized: bool,
@@ -89,6 +377,7 @@ pub struct UserAccount {
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
 pub struct SessionAccount {
     pub accountType:u8,        //3
     pub is_initialized: bool,
@@ -98,19 +387,142 @@ pub struct SessionAccount {
     pub end_ts: i64,
     pub points_awarded: u64,
     pub settled: bool,
+    pub energy_wh: u64, // attested by the charger device at stop
+    pub charging_finished_ts: i64, // reported by the charger device, 0 = still charging
+    pub idle_fee_lamports: u64,
+    pub fleet: Pubkey, // FleetAccount paying for the session, default = driver pays
+    pub delegate: Pubkey, // may sign StopSession for this session, default = none
+    pub escrow_lamports: u64, // prepaid by the driver, held in the session account
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct ReservationAccount {
+    pub accountType:u8,         //6
+    pub is_initialized: bool,
+    pub driver: Pubkey, // reserving wallet
+    pub charger: Pubkey,
+    pub start_ts: i64,
+    pub end_ts: i64,
+    pub deposit_lamports: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct StakeAccount {
+    pub accountType:u8,         //7
+    pub is_initialized: bool,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub tier: u8,
+    pub lock_until: i64,
+    pub unstake_requested_at: i64, // 0 = not cooling down
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct FleetAccount {
+    pub accountType:u8,         //9
+    pub is_initialized: bool,
+    pub admin: Pubkey,
+    pub points_to_fleet: bool, // AMP accrues to the admin's DriverAccount instead of the member's
+    pub max_per_session_lamports: u64, // 0 = no per-session cap
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct FleetMemberAccount {
+    pub accountType:u8,         //10
+    pub is_initialized: bool,
+    pub fleet: Pubkey,
+    pub driver: Pubkey, // member wallet
+    pub active: bool,
+    pub spend_limit_lamports: u64, // cumulative allowance
+    pub spent_lamports: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct BidAccount {
+    pub accountType:u8,         //11
+    pub is_initialized: bool,
+    pub buyer: Pubkey,
+    pub amount_total: u64, // points still wanted
+    pub price_per_point_lamports: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct PoolAccount {
+    pub accountType:u8,         //12
+    pub is_initialized: bool,
+    pub amp_reserve: u64,
+    pub sol_reserve: u64, // lamports in the vault above its rent-exempt minimum
+    pub total_shares: u64,
+    pub fee_bps: u16,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct LpPositionAccount {
+    pub accountType:u8,         //13
+    pub is_initialized: bool,
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub shares: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct PartnerAccount {
+    pub accountType:u8,         //14
+    pub is_initialized: bool,
+    pub program: Pubkey, // partner program id
+    pub active: bool,
+    pub budget_per_period: u64,
+    pub period_secs: i64,
+    pub period_start: i64,
+    pub awarded_in_period: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct FeeWaiverAccount {
+    pub accountType:u8,         //15
+    pub is_initialized: bool,
+    pub operator: Pubkey,
+    pub waived: bool,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
+pub struct AuditorAccount {
+    pub accountType:u8,         //16
+    pub is_initialized: bool,
+    pub auditor: Pubkey,
+    pub active: bool,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[allow(non_snake_case)] // accountType matches the field name clients already decode
 pub struct ListingAccount {
     pub accountType:u8,         //4
     pub is_initialized: bool,
     pub seller: Pubkey,
     pub amount_total: u64,
     pub price_per_point_lamports: u64,
+    pub expires_at: i64, // 0 = never
+    pub kind: ListingKind,
+    // Dutch auction only, price_per_point_lamports is unused for auctions
+    pub auction_start_price: u64,
+    pub auction_floor_price: u64,
+    pub auction_start_ts: i64,
+    pub auction_decay_secs: i64,
 }
 
 // ---------- Entrypoint ----------
-entrypoint!(process_instruction);
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(process_instruction);
 pub fn process_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
@@ -118,38 +530,122 @@ pub fn process_instruction(
 ) -> ProgramResult {
     let ix = Instruction::try_from_slice(input).map_err(|_| ProgramError::InvalidInstructionData)?;
     match ix {
-        // Instruction::Initialize { admin } => instruction_initialize(program_id, accounts, admin),
         Instruction::AddCharger { code, name, city, address, latitude,
-            longitude,power_kw,rate_points_per_sec,price_per_sec_lamports,
+            longitude,power_kw,rate_points_per_sec,price_per_sec_lamports,device_pubkey,
+            idle_threshold_secs,idle_fee_per_min_lamports,
         } => instruction_add_charger(program_id,accounts,code,name,city,address,
-            latitude,longitude,power_kw,rate_points_per_sec,price_per_sec_lamports
+            latitude,longitude,power_kw,rate_points_per_sec,price_per_sec_lamports,device_pubkey,
+            idle_threshold_secs,idle_fee_per_min_lamports
         ),
-        Instruction::StartSession { start_ts } => instruction_start_session(program_id, accounts, start_ts),
-        Instruction::StopSession { end_ts } => instruction_stop_session(program_id, accounts, end_ts),
-        Instruction::CreateListing { amount_points, price_per_point_lamports } => {
-            instruction_create_listing(program_id, accounts,  amount_points, price_per_point_lamports)
+        Instruction::StartSession { start_ts, delegate, prepaid_lamports } => {
+            instruction_start_session(program_id, accounts, start_ts, delegate, prepaid_lamports)
+        }
+        Instruction::StopSession { end_ts, energy_wh } => instruction_stop_session(program_id, accounts, end_ts, energy_wh),
+        Instruction::CreateListing { amount_points, price_per_point_lamports, expires_at } => {
+            instruction_create_listing(program_id, accounts,  amount_points, price_per_point_lamports, expires_at)
+        }
+        Instruction::BuyFromListing { buy_amount_points, max_price_per_point_lamports, min_points } => {
+            instruction_buy_from_listing(program_id, accounts, buy_amount_points, max_price_per_point_lamports, min_points)
         }
-        Instruction::BuyFromListing { buy_amount_points } => instruction_buy_from_listing(program_id, accounts, buy_amount_points),
         Instruction::CancelListing {} => instruction_cancel_listing(program_id, accounts),
+        Instruction::SetChargerStatus { status } => instruction_set_charger_status(program_id, accounts, status),
+        Instruction::ReserveCharger { start, duration } => instruction_reserve_charger(program_id, accounts, start, duration),
+        Instruction::ReportChargingComplete {} => instruction_report_charging_complete(program_id, accounts),
+        Instruction::SetTariffSchedule { bands } => instruction_set_tariff_schedule(program_id, accounts, bands),
+        Instruction::Stake { amount, tier } => instruction_stake(program_id, accounts, amount, tier),
+        Instruction::Unstake {} => instruction_unstake(program_id, accounts),
+        Instruction::Initialize { admin } => instruction_initialize(program_id, accounts, admin),
+        Instruction::SlashCharger { slash_bps, reason } => instruction_slash_charger(program_id, accounts, slash_bps, reason),
+        Instruction::CloseCharger {} => instruction_close_charger(program_id, accounts),
+        Instruction::TransferPoints { amount } => instruction_transfer_points(program_id, accounts, amount),
+        Instruction::CreateFleet { points_to_fleet, max_per_session_lamports } => {
+            instruction_create_fleet(program_id, accounts, points_to_fleet, max_per_session_lamports)
+        }
+        Instruction::FundFleet { amount } => instruction_fund_fleet(program_id, accounts, amount),
+        Instruction::WithdrawFleet { amount } => instruction_withdraw_fleet(program_id, accounts, amount),
+        Instruction::SetFleetMember { driver, active, spend_limit_lamports } => {
+            instruction_set_fleet_member(program_id, accounts, driver, active, spend_limit_lamports)
+        }
+        Instruction::CreateBid { amount, price_per_point } => instruction_create_bid(program_id, accounts, amount, price_per_point),
+        Instruction::FillBid { amount } => instruction_fill_bid(program_id, accounts, amount),
+        Instruction::CancelBid {} => instruction_cancel_bid(program_id, accounts),
+        Instruction::MarketBuy { max_points, max_price_per_point } => {
+            instruction_market_buy(program_id, accounts, max_points, max_price_per_point)
+        }
+        Instruction::ExpireListing {} => instruction_expire_listing(program_id, accounts),
+        Instruction::CreateAuctionListing { amount_points, start_price, floor_price, decay_secs, expires_at } => {
+            instruction_create_auction_listing(program_id, accounts, amount_points, start_price, floor_price, decay_secs, expires_at)
+        }
+        Instruction::CreatePool { fee_bps } => instruction_create_pool(program_id, accounts, fee_bps),
+        Instruction::AddLiquidity { amp_amount, max_sol_lamports } => {
+            instruction_add_liquidity(program_id, accounts, amp_amount, max_sol_lamports)
+        }
+        Instruction::RemoveLiquidity { shares, min_amp, min_sol_lamports } => {
+            instruction_remove_liquidity(program_id, accounts, shares, min_amp, min_sol_lamports)
+        }
+        Instruction::Swap { amount_in, min_amount_out, direction } => {
+            instruction_swap(program_id, accounts, amount_in, min_amount_out, direction)
+        }
+        Instruction::RegisterPartner { partner_program, budget_per_period, period_secs, active } => {
+            instruction_register_partner(program_id, accounts, partner_program, budget_per_period, period_secs, active)
+        }
+        Instruction::AwardPoints { amount, reason } => instruction_award_points(program_id, accounts, amount, reason),
+        Instruction::SetPause { scope, paused } => instruction_set_pause(program_id, accounts, scope, paused),
+        Instruction::ProposeAdmin { new_admin } => instruction_propose_admin(program_id, accounts, new_admin),
+        Instruction::AcceptAdmin {} => instruction_accept_admin(program_id, accounts),
+        Instruction::SetRegistrationFee { fee, fee_mint } => instruction_set_registration_fee(program_id, accounts, fee, fee_mint),
+        Instruction::SetFeeWaiver { operator, waived } => instruction_set_fee_waiver(program_id, accounts, operator, waived),
+        Instruction::SetAuditor { auditor, active } => instruction_set_auditor(program_id, accounts, auditor, active),
+        Instruction::VerifyCharger { verified } => instruction_verify_charger(program_id, accounts, verified),
+        Instruction::SetEmissionLimits { max_rate_points_per_sec, epoch_secs, epoch_budget } => {
+            instruction_set_emission_limits(program_id, accounts, max_rate_points_per_sec, epoch_secs, epoch_budget)
+        }
+        Instruction::RefundSession {} => instruction_refund_session(program_id, accounts),
+        Instruction::ExpireReservation {} => instruction_expire_reservation(program_id, accounts),
     }
 }
 
 // ---------- Instruction handlers ----------
 
+#[allow(clippy::too_many_arguments)]
 fn instruction_add_charger(program_id: &Pubkey,accounts: &[AccountInfo],code: String,name: String,
     city: String,address: String,latitude: f64,longitude: f64,power_kw: f32,rate_points_per_sec: u64,price_per_sec_lamports: u64,
+    device_pubkey: Pubkey,idle_threshold_secs: u64,idle_fee_per_min_lamports: u64,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let payer = next_account_info(account_info_iter)?; // signer, will pay reg fee
     let charger_pda = next_account_info(account_info_iter)?; // writable PDA
-    let admin_account = next_account_info(account_info_iter)?; 
+    let admin_account = next_account_info(account_info_iter)?; // writable, must be state.admin
+    let system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA
+    let charger_vault = next_account_info(account_info_iter)?; // writable vault PDA, receives the bond
+    let fee_waiver_pda = next_account_info(account_info_iter)?; // FeeWaiverAccount PDA of the payer (may be empty)
+    // SPL fee only: payer token account (writable), admin token account (writable), token program
 
+    check_not_paused(program_id, state_pda, PauseScope::Registration)?;
     if !payer.is_signer {
         msg!("Payer must sign");
         return Err(ProgramError::MissingRequiredSignature);
     }
+    let state = load_state(program_id, state_pda)?;
+    if *admin_account.key != state.admin {
+        msg!("Admin account does not match global state");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if device_pubkey == Pubkey::default() {
+        msg!("Charger device pubkey required");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if !(power_kw > 0.0 && power_kw <= MAX_CHARGER_POWER_KW) {
+        msg!("power_kw must be above 0 and at most {}", MAX_CHARGER_POWER_KW);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if rate_points_per_sec > state.max_rate_points_per_sec {
+        msg!("rate_points_per_sec capped at {}", state.max_rate_points_per_sec);
+        return Err(ProgramError::InvalidArgument);
+    }
     msg!("code : {} , address : {}",code,address);
-    let seeds=&[CHARGER_SEED,code.as_bytes().as_ref(), payer.key.as_ref()];
+    let seeds=&[CHARGER_SEED,code.as_bytes(), payer.key.as_ref()];
     let (expected_charger_pda_account,bump)=Pubkey::find_program_address(seeds, program_id);
     let seeds_with_bump=&[CHARGER_SEED, code.as_bytes(), payer.key.as_ref(), &[bump]];
     if expected_charger_pda_account!=*charger_pda.key{
@@ -158,7 +654,9 @@ fn instruction_add_charger(program_id: &Pubkey,accounts: &[AccountInfo],code: St
     let rent=Rent::get()?;
     let charger_account_size:usize=1+ 1+ 32+
                                    4+ code.len() + 4+ name.len() + 4+ city.len() + 4+ address.len()+
-                                   8+ 8+ 4+ 8+ 8;
+                                   8+ 8+ 4+ 8+ 8+ 32+ 1+ 8+ 8+ 8+
+                                   1+ MAX_TARIFF_BANDS * (1+ 1+ 2+ 2)+ 8+ 1+
+                                   1+ 8+ 32+ 8+ 4;
     let charger_min_bal_for_rent_exempt=rent.minimum_balance(charger_account_size);
     let charger_pda_create_ix=system_instruction::create_account(payer.key,
         charger_pda.key, charger_min_bal_for_rent_exempt, charger_account_size as u64, program_id);
@@ -167,63 +665,74 @@ fn instruction_add_charger(program_id: &Pubkey,accounts: &[AccountInfo],code: St
 
     msg!("charger pda created!!");
 
-    // Transfer registration fee from payer -> admin
-    msg!("Transferring registration fee: {} lamports", REG_FEE_LAMPORTS);
-    let transfer_ix = system_instruction::transfer(payer.key, admin_account.key, REG_FEE_LAMPORTS);
-    invoke(
-        &transfer_ix,
-        &[ payer.clone(), admin_account.clone()],
-    ).map(|_| ()).map_err(|e| {
-        msg!("Registration transfer failed: {:?}", e);
-        e
-    })?;
-    // NOTE: above: in practice the client must pass the admin account info in the accounts list.
-    // For clarity, please ensure the client includes state.admin account as writable in the account list.
+    // Transfer registration fee from payer -> admin, in lamports or in the fee mint
+    if is_fee_waived(program_id, fee_waiver_pda, payer.key)? {
+        msg!("Registration fee waived for {}", payer.key);
+    } else if state.registration_fee > 0 && state.fee_mint == Pubkey::default() {
+        msg!("Transferring registration fee: {} lamports", state.registration_fee);
+        let transfer_ix = system_instruction::transfer(payer.key, admin_account.key, state.registration_fee);
+        invoke(
+            &transfer_ix,
+            &[ payer.clone(), admin_account.clone()],
+        ).map(|_| ()).map_err(|e| {
+            msg!("Registration transfer failed: {:?}", e);
+            e
+        })?;
+    } else if state.registration_fee > 0 {
+        let payer_token_account = next_account_info(account_info_iter)?;
+        let admin_token_account = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        msg!("Transferring registration fee: {} of mint {}", state.registration_fee, state.fee_mint);
+        transfer_registration_fee_spl(&state, payer, payer_token_account, admin_token_account, token_program)?;
+    }
+
+    // Post the operator bond into the charger vault
+    let vault_seeds=&[CHARGER_VAULT_SEED, charger_pda.key.as_ref()];
+    let (expected_vault,_)=Pubkey::find_program_address(vault_seeds, program_id);
+    if expected_vault!=*charger_vault.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    msg!("Posting operator bond: {} lamports", CHARGER_BOND_LAMPORTS);
+    let bond_ix = system_instruction::transfer(payer.key, charger_vault.key, CHARGER_BOND_LAMPORTS);
+    invoke(&bond_ix, &[payer.clone(), charger_vault.clone(), system_program_acc.clone()])?;
 
     // Populate charger account
-    let mut charger = ChargerAccount {accountType:1, is_initialized: true, authority: *payer.key,
+    let charger = ChargerAccount {accountType:1, is_initialized: true, authority: *payer.key,
         code, name, city, address, latitude, longitude, power_kw, rate_points_per_sec, price_per_sec_lamports,
+        device_pubkey, status: ChargerStatus::Available, last_heartbeat: Clock::get()?.unix_timestamp,
+        idle_threshold_secs, idle_fee_per_min_lamports,
+        tariff_band_count: 0, tariff_bands: [TariffBand::default(); MAX_TARIFF_BANDS],
+        bond_lamports: CHARGER_BOND_LAMPORTS, slash_count: 0,
+        verified: false, verified_at: 0, verified_by: Pubkey::default(),
+        closing_requested_at: 0, open_sessions: 0,
     };
-    charger.serialize(&mut *charger_pda.data.borrow_mut())?;
+    charger.serialize(&mut &mut charger_pda.data.borrow_mut()[..])?;
+    Event::ChargerAdded {charger: *charger_pda.key, authority: *payer.key, device_pubkey,
+        bond_lamports: CHARGER_BOND_LAMPORTS}.emit();
     msg!("Charger added by {}", payer.key);
     Ok(())
 }
 
 
-fn instruction_start_session(program_id: &Pubkey, accounts: &[AccountInfo], start_ts: i64) -> ProgramResult {
+fn instruction_start_session(program_id: &Pubkey, accounts: &[AccountInfo], start_ts: i64, delegate: Option<Pubkey>, prepaid_lamports: u64) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
 
     let user = next_account_info(account_info_iter)?; // signer
     let driver_pda = next_account_info(account_info_iter)?; // signer
     let session_pda = next_account_info(account_info_iter)?; // writable PDA
-    let charger_pda = next_account_info(account_info_iter)?; // readonly
+    let charger_pda = next_account_info(account_info_iter)?; // writable ChargerAccount
+    let system_program_acc = next_account_info(account_info_iter)?;
+    let reservation_pda = next_account_info(account_info_iter)?; // writable ReservationAccount PDA (may be empty)
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+    // optional: fleet paying for this session
+    let fleet_pda = account_info_iter.next(); // readonly FleetAccount
+    let fleet_member_pda = account_info_iter.next(); // readonly FleetMemberAccount of the driver
 
+    check_not_paused(program_id, state_pda, PauseScope::Sessions)?;
     msg!("start ts in contract : {}",start_ts);
 
-    let driver_seeds=&[DRIVER_SEED, user.key.as_ref()];
-    let (expected_driver_pda,bump)=Pubkey::find_program_address(driver_seeds, program_id);
-    msg!("expected_driver_pda : {}",expected_driver_pda);
-    let driver_seeds_with_bump=&[DRIVER_SEED, user.key.as_ref(),&[bump]];
-    if expected_driver_pda!=*driver_pda.key{
-        return Err(ProgramError::InvalidSeeds);
-    }
-
     //Create driver account if it does not exists
-    if driver_pda.data_is_empty(){
-        let rent=Rent::get()?;
-        let driver_pda_account_size:usize=1+ 1+ 32+ 8;
-        let driver_pda_rent_exempt_bal=rent.minimum_balance(driver_pda_account_size);
-        let driver_pda_create_ix=system_instruction::create_account(user.key,
-            driver_pda.key, driver_pda_rent_exempt_bal, driver_pda_account_size as u64, program_id);
-        invoke_signed(&driver_pda_create_ix,
-            &[user.clone(), driver_pda.clone()],
-            &[driver_seeds_with_bump])?;
-            msg!("driver pda created");
-
-        let driver_data=DriverAccount{accountType:2, owner:*user.key, is_initialized:true, amp_balance:0};
-        driver_data.serialize(&mut *driver_pda.data.borrow_mut())?;
-        msg!("driver pda data updated"); 
-    }
+    create_driver_account_if_empty(program_id, user, driver_pda)?;
 
     //Create Session account if it does not exists
     let start_ts_bytes=start_ts.to_le_bytes();
@@ -236,7 +745,7 @@ fn instruction_start_session(program_id: &Pubkey, accounts: &[AccountInfo], star
     }
     
     let rent=Rent::get()?;
-    let session_pda_account_size:usize=1+ 1+ 32+ 32+ 8+ 8+ 8+ 1;
+    let session_pda_account_size:usize=1+ 1+ 32+ 32+ 8+ 8+ 8+ 1+ 8+ 8+ 8+ 32+ 32+ 8;
     let session_pda_rent_exempt_bal=rent.minimum_balance(session_pda_account_size);
     let session_pda_create_ix=system_instruction::create_account(user.key,
         session_pda.key, session_pda_rent_exempt_bal, session_pda_account_size as u64, program_id);
@@ -245,35 +754,116 @@ fn instruction_start_session(program_id: &Pubkey, accounts: &[AccountInfo], star
         &[session_seeds_with_bump])?;
     msg!("session pda created");
 
-    // Verify charger exists
-    let charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    // Verify charger exists and is able to charge
+    if charger_pda.owner != program_id {
+        msg!("Charger account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
     if !charger.is_initialized {
         msg!("Charger not initialized");
         return Err(ProgramError::UninitializedAccount);
     }
+    if matches!(charger.status, ChargerStatus::Faulted | ChargerStatus::Offline) {
+        msg!("Charger is {:?}", charger.status);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if charger.closing_requested_at != 0 {
+        msg!("Charger is closing");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if (start_ts - now).abs() > START_TS_TOLERANCE_SECS {
+        msg!("start_ts {} is too far from now {}", start_ts, now);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if now - charger.last_heartbeat > HEARTBEAT_TIMEOUT_SECS {
+        msg!("Charger heartbeat is stale, last seen at {}", charger.last_heartbeat);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // Respect an active reservation on this charger
+    let reservation_seeds=&[RESERVATION_SEED, charger_pda.key.as_ref()];
+    let (expected_reservation_pda,_)=Pubkey::find_program_address(reservation_seeds, program_id);
+    if expected_reservation_pda!=*reservation_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !reservation_pda.data_is_empty(){
+        let reservation = ReservationAccount::try_from_slice(&reservation_pda.data.borrow())?;
+        // the guard keeps walk-up sessions from running into the reserved window
+        let in_window = reservation.start_ts - RESERVATION_GUARD_SECS <= now && now < reservation.end_ts;
+        if reservation.is_initialized && in_window {
+            if reservation.driver != *user.key {
+                msg!("Charger is reserved from {} until {}", reservation.start_ts, reservation.end_ts);
+                return Err(ProgramError::InvalidAccountData);
+            }
+            close_program_account(reservation_pda, user)?;
+            Event::ReservationClosed {charger: *charger_pda.key, driver: *user.key, claimed: true}.emit();
+            msg!("Reservation claimed");
+        }
+    }
+
+    // Fleet-funded session: the driver must be an active member of the fleet
+    let fleet = match (fleet_pda, fleet_member_pda) {
+        (Some(fleet_pda), Some(fleet_member_pda)) => {
+            let (_, member) = load_fleet_member(program_id, fleet_pda, fleet_member_pda, user.key)?;
+            if !member.active {
+                msg!("Driver is not an active fleet member");
+                return Err(ProgramError::IllegalOwner);
+            }
+            msg!("Session paid by fleet {}", fleet_pda.key);
+            *fleet_pda.key
+        }
+        _ => Pubkey::default(),
+    };
+
+    // Delegated session: escrow the prepayment in the session account
+    let delegate = delegate.unwrap_or_default();
+    let escrow_lamports = if delegate != Pubkey::default() { prepaid_lamports } else { 0 };
+    if escrow_lamports > 0 {
+        let escrow_ix = system_instruction::transfer(user.key, session_pda.key, escrow_lamports);
+        invoke(&escrow_ix, &[user.clone(), session_pda.clone(), system_program_acc.clone()])?;
+        msg!("Escrowed {} lamports for delegate {}", escrow_lamports, delegate);
+    }
 
     // Create session record
     let session = SessionAccount {accountType:3, is_initialized: true, driver: *driver_pda.key,
-        charger: *charger_pda.key, start_ts, end_ts: 0, points_awarded: 0, settled: false,
+        charger: *charger_pda.key, start_ts, end_ts: 0, points_awarded: 0, settled: false, energy_wh: 0,
+        charging_finished_ts: 0, idle_fee_lamports: 0, fleet, delegate, escrow_lamports,
     };
-    session.serialize(&mut *session_pda.data.borrow_mut())?;
+    session.serialize(&mut &mut session_pda.data.borrow_mut()[..])?;
+    charger.open_sessions = charger.open_sessions.checked_add(1).ok_or(ProgramError::InvalidArgument)?;
+    charger.serialize(&mut &mut charger_pda.data.borrow_mut()[..])?;
+    Event::SessionStarted {session: *session_pda.key, charger: *charger_pda.key, driver: *user.key, start_ts,
+        fleet, delegate, escrow_lamports}.emit();
     msg!("Session started at {}", start_ts);
     Ok(())
 }
 
-fn instruction_stop_session(program_id: &Pubkey, accounts: &[AccountInfo], end_ts: i64) -> ProgramResult {
+fn instruction_stop_session(program_id: &Pubkey, accounts: &[AccountInfo], end_ts: i64, energy_wh: u64) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let user = next_account_info(account_info_iter)?; // signer
+    let user = next_account_info(account_info_iter)?; // signer: driver wallet, session delegate, or for a stale session the charger operator or device
     let session_pda = next_account_info(account_info_iter)?; // writable
     let driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA
     let charger_pda = next_account_info(account_info_iter)?; // writable ChargerAccount
     let charger_owner_account = next_account_info(account_info_iter)?; // writable receiver
     let system_program_acc = next_account_info(account_info_iter)?;
-
+    let instructions_sysvar = next_account_info(account_info_iter)?; // Sysvar1nstructions
+    let stake_pda = next_account_info(account_info_iter)?; // StakeAccount PDA of the driver (may be empty)
+    let driver_wallet = next_account_info(account_info_iter)?; // writable, DriverAccount owner, gets escrow refunds
+    let state_pda = next_account_info(account_info_iter)?; // writable StateAccount PDA, tracks the epoch budget
+    // fleet sessions only: FleetAccount, FleetMemberAccount (writable), fleet vault (writable),
+    // and the fleet admin's DriverAccount (writable) when points accrue to the fleet
+
+    // not pausable: settling is the only way out for the escrow and the charger's open session count
     if !user.is_signer {
-        msg!("Driver must sign");
+        msg!("Driver or session delegate must sign");
         return Err(ProgramError::MissingRequiredSignature);
     }
+    if session_pda.owner != program_id || charger_pda.owner != program_id || driver_pda.owner != program_id {
+        msg!("Session, charger and driver accounts must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
 
     let mut session = SessionAccount::try_from_slice(&session_pda.data.borrow())?;
     if !session.is_initialized {
@@ -284,6 +874,17 @@ fn instruction_stop_session(program_id: &Pubkey, accounts: &[AccountInfo], end_t
         msg!("Session already settled");
         return Err(ProgramError::InvalidAccountData);
     }
+    if session.charger != *charger_pda.key || session.driver != *driver_pda.key {
+        msg!("Session does not belong to this charger/driver");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // sanity: driver_account.owner must be the driver wallet; the signer is it or the delegate
+    let drv_acc = DriverAccount::try_from_slice(&driver_pda.data.borrow())?;
+    if drv_acc.owner != *driver_wallet.key {
+        msg!("Driver account owner mismatch");
+        return Err(ProgramError::IllegalOwner);
+    }
 
     // compute duration
     let start = session.start_ts;
@@ -291,60 +892,182 @@ fn instruction_stop_session(program_id: &Pubkey, accounts: &[AccountInfo], end_t
         msg!("Invalid end_ts");
         return Err(ProgramError::InvalidArgument);
     }
-    let duration_secs = (end_ts - start) as u64;
+    let now = Clock::get()?.unix_timestamp;
+    if end_ts > now {
+        msg!("end_ts {} is in the future (now {})", end_ts, now);
+        return Err(ProgramError::InvalidArgument);
+    }
 
     // read charger to get rates
-    let charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    let mut charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
     if !charger.is_initialized {
         msg!("Charger uninitialized");
         return Err(ProgramError::UninitializedAccount);
     }
+    if *charger_owner_account.key != charger.authority {
+        msg!("Charger owner account mismatch");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // once the driver could refund, the charger side may settle instead so metered energy still gets paid
+    let signed_by_driver = *user.key == drv_acc.owner;
+    let signed_by_delegate = session.delegate != Pubkey::default() && *user.key == session.delegate;
+    let stale = now >= start.saturating_add(SESSION_REFUND_AFTER_SECS);
+    let signed_by_charger = stale && (*user.key == charger.authority || *user.key == charger.device_pubkey);
+    if !signed_by_driver && !signed_by_delegate && !signed_by_charger {
+        msg!("Signer is not the driver or session delegate, nor the charger of a session open since {}", start);
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    // the charger device must have signed what it metered for this session
+    let mut attestation = [0u8; ATTESTATION_MESSAGE_LEN];
+    attestation[..32].copy_from_slice(session_pda.key.as_ref());
+    attestation[32..40].copy_from_slice(&energy_wh.to_le_bytes());
+    attestation[40..].copy_from_slice(&end_ts.to_le_bytes());
+    verify_device_attestation(instructions_sysvar, &charger.device_pubkey, &attestation)?;
+    msg!("Device attested {} Wh delivered", energy_wh);
+
+    // split the session into charging time and idle time after the device reported completion
+    let charge_end_ts = if session.charging_finished_ts > 0 {
+        session.charging_finished_ts.clamp(start, end_ts)
+    } else {
+        end_ts
+    };
+    let charging_secs = (charge_end_ts - start) as u64;
+    let idle_secs = (end_ts - charge_end_ts) as u64;
+    let idle_fee = if charger.idle_threshold_secs > 0 && idle_secs > charger.idle_threshold_secs {
+        let billable_mins = (idle_secs - charger.idle_threshold_secs).div_ceil(60);
+        billable_mins.checked_mul(charger.idle_fee_per_min_lamports).ok_or(ProgramError::InvalidArgument)?
+    } else {
+        0
+    };
+    msg!("Charging {} secs, idle {} secs, idle fee {} lamports", charging_secs, idle_secs, idle_fee);
 
-    // total payment lamports
-    let total_price = charger.price_per_sec_lamports.checked_mul(duration_secs).ok_or(ProgramError::InvalidArgument)?;
+    // total payment lamports, charging time priced through the tariff bands it spans;
+    // the admin may have lowered the rate cap since AddCharger, and band multipliers can push past it
+    let mut state = load_state(program_id, state_pda)?;
+    let (charging_price, charging_points) = tariff_price_and_points(&charger, start, charge_end_ts, state.max_rate_points_per_sec)?;
+    let total_price = charging_price.checked_add(idle_fee).ok_or(ProgramError::InvalidArgument)?;
     msg!("Total price (lamports) for session: {}", total_price);
 
-    // Transfer lamports from driver -> charger owner
-    // driver must sign; include both driver and charger_owner in accounts
-    let transfer_ix = system_instruction::transfer(user.key, &charger.authority, total_price);
-    invoke(
-        &transfer_ix,
-        &[
-            user.clone(),
-            charger_owner_account.clone(), // MUST be the same pubkey as charger.authority; client must pass it
-            system_program_acc.clone(),
-        ],
-    )?;
+    // Fleet pays what its vault and the member's allowance cover, the driver pays the rest
+    let mut driver_share = total_price;
+    let mut points_driver_pda = driver_pda;
+    if session.fleet != Pubkey::default() {
+        let fleet_pda = next_account_info(account_info_iter)?;
+        let fleet_member_pda = next_account_info(account_info_iter)?;
+        let fleet_vault = next_account_info(account_info_iter)?;
+        if *fleet_pda.key != session.fleet {
+            msg!("Fleet account does not match session");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        // the fleet committed to this session at start, so a member deactivated since then is still covered
+        let (fleet, mut member) = load_fleet_member(program_id, fleet_pda, fleet_member_pda, driver_wallet.key)?;
+
+        let vault_spendable = fleet_vault.lamports().saturating_sub(Rent::get()?.minimum_balance(0));
+        let mut fleet_share = total_price
+            .min(member.spend_limit_lamports.saturating_sub(member.spent_lamports))
+            .min(vault_spendable);
+        if fleet.max_per_session_lamports > 0 {
+            fleet_share = fleet_share.min(fleet.max_per_session_lamports);
+        }
+        if fleet_share > 0 {
+            transfer_from_fleet_vault(program_id, fleet_pda, fleet_vault, charger_owner_account, system_program_acc, fleet_share)?;
+            member.spent_lamports = member.spent_lamports.checked_add(fleet_share).ok_or(ProgramError::InvalidArgument)?;
+            member.serialize(&mut &mut fleet_member_pda.data.borrow_mut()[..])?;
+        }
+        driver_share = total_price - fleet_share;
+        msg!("Fleet paid {} lamports, driver pays {}", fleet_share, driver_share);
+
+        if fleet.points_to_fleet {
+            let fleet_driver_pda = next_account_info(account_info_iter)?;
+            let (expected_fleet_driver_pda,_)=Pubkey::find_program_address(&[DRIVER_SEED, fleet.admin.as_ref()], program_id);
+            if expected_fleet_driver_pda!=*fleet_driver_pda.key || fleet_driver_pda.owner != program_id {
+                msg!("Fleet admin driver account expected");
+                return Err(ProgramError::InvalidSeeds);
+            }
+            points_driver_pda = fleet_driver_pda;
+        }
+    }
 
-    // credit points to driver account
-    let points_awarded = charger.rate_points_per_sec.checked_mul(duration_secs).ok_or(ProgramError::InvalidArgument)?;
-    let mut drv_acc = DriverAccount::try_from_slice(&driver_pda.data.borrow())?;
+    // Prepaid escrow in the session account comes next, the unused part goes back to the driver
+    let escrow_share = driver_share.min(session.escrow_lamports);
+    if session.escrow_lamports > 0 {
+        let refund = session.escrow_lamports - escrow_share;
+        **session_pda.try_borrow_mut_lamports()? = session_pda.lamports().checked_sub(session.escrow_lamports).ok_or(ProgramError::InsufficientFunds)?;
+        **charger_owner_account.try_borrow_mut_lamports()? = charger_owner_account.lamports().checked_add(escrow_share).ok_or(ProgramError::InvalidArgument)?;
+        **driver_wallet.try_borrow_mut_lamports()? = driver_wallet.lamports().checked_add(refund).ok_or(ProgramError::InvalidArgument)?;
+        driver_share -= escrow_share;
+        session.escrow_lamports = 0;
+        msg!("Escrow paid {} lamports, refunded {}", escrow_share, refund);
+    }
 
-    // sanity: driver_account.owner must match signer
-    if drv_acc.owner != *user.key && drv_acc.owner != Pubkey::default() {
-        msg!("Driver account owner mismatch");
-        return Err(ProgramError::IllegalOwner);
+    // Transfer lamports from driver -> charger owner
+    // driver must sign; include both driver and charger_owner in accounts
+    if driver_share > 0 {
+        if !signed_by_driver {
+            msg!("Escrow does not cover {} lamports, the driver must stop this session", driver_share);
+            return Err(ProgramError::InsufficientFunds);
+        }
+        let transfer_ix = system_instruction::transfer(user.key, &charger.authority, driver_share);
+        invoke(
+            &transfer_ix,
+            &[
+                user.clone(),
+                charger_owner_account.clone(), // MUST be the same pubkey as charger.authority; client must pass it
+                system_program_acc.clone(),
+            ],
+        )?;
     }
-   
-    drv_acc.amp_balance = drv_acc.amp_balance.checked_add(points_awarded).ok_or(ProgramError::InvalidArgument)?;
-    drv_acc.serialize(&mut *driver_pda.data.borrow_mut())?;
+
+    // credit points to driver account for charging time only, scaled by how much of the charger's
+    // rated power the device actually metered, so a trickle of energy does not earn the full duration
+    let points_awarded = if energy_wh == 0 {
+        msg!("No energy delivered, no points awarded");
+        0
+    } else {
+        let utilisation_bps = energy_utilisation_bps(charger.power_kw, charging_secs, energy_wh);
+        let verified_bps = if charger.verified { BPS_DENOMINATOR } else { UNVERIFIED_POINTS_BPS };
+        let boost_bps = stake_boost_bps(program_id, stake_pda, driver_wallet.key)?;
+        let boosted = charging_points as u128 * utilisation_bps as u128 / BPS_DENOMINATOR as u128
+            * verified_bps as u128 / BPS_DENOMINATOR as u128
+            * (BPS_DENOMINATOR + boost_bps) as u128 / BPS_DENOMINATOR as u128;
+        msg!("Utilisation {} bps, charger verified: {}, stake boost {} bps", utilisation_bps, charger.verified, boost_bps);
+        u64::try_from(boosted).map_err(|_| ProgramError::InvalidArgument)?
+    };
+    let points_awarded = take_from_epoch_budget(&mut state, points_awarded, now);
+    state.serialize(&mut &mut state_pda.data.borrow_mut()[..])?;
+    let mut points_acc = DriverAccount::try_from_slice(&points_driver_pda.data.borrow())?;
+    points_acc.amp_balance = points_acc.amp_balance.checked_add(points_awarded).ok_or(ProgramError::InvalidArgument)?;
+    points_acc.serialize(&mut &mut points_driver_pda.data.borrow_mut()[..])?;
 
     // update session
     session.end_ts = end_ts;
     session.points_awarded = points_awarded;
     session.settled = true;
-    session.serialize(&mut *session_pda.data.borrow_mut())?;
-
+    session.energy_wh = energy_wh;
+    session.idle_fee_lamports = idle_fee;
+    session.serialize(&mut &mut session_pda.data.borrow_mut()[..])?;
+    charger.open_sessions = charger.open_sessions.saturating_sub(1);
+    charger.serialize(&mut &mut charger_pda.data.borrow_mut()[..])?;
+
+    Event::SessionSettled {session: *session_pda.key, charger: *charger_pda.key, driver: *driver_wallet.key, end_ts,
+        energy_wh, total_price_lamports: total_price, idle_fee_lamports: idle_fee, points_awarded}.emit();
     msg!("Stopped session. awarded {} points", points_awarded);
     Ok(())
 }
 
-fn instruction_create_listing(program_id: &Pubkey, accounts: &[AccountInfo], amount_points: u64, price_per_point_lamports: u64) -> ProgramResult {
+fn instruction_create_listing(program_id: &Pubkey, accounts: &[AccountInfo], amount_points: u64, price_per_point_lamports: u64,
+    expires_at: Option<i64>,
+) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let user = next_account_info(account_info_iter)?; // signer
     let driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA
     let listing_pda = next_account_info(account_info_iter)?; // writable listing PDA
+    let _system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
 
+    check_not_paused(program_id, state_pda, PauseScope::Marketplace)?;
     if !user.is_signer {
         msg!("Seller must sign");
         return Err(ProgramError::MissingRequiredSignature);
@@ -364,10 +1087,15 @@ fn instruction_create_listing(program_id: &Pubkey, accounts: &[AccountInfo], amo
         msg!("Insufficient points to create listing");
         return Err(ProgramError::InsufficientFunds);
     }
+    let now = Clock::get()?.unix_timestamp;
+    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+        msg!("Listing expiry must be in the future");
+        return Err(ProgramError::InvalidArgument);
+    }
 
     // deduct points into listing reservation
     drv_acc.amp_balance = drv_acc.amp_balance.checked_sub(amount_points).ok_or(ProgramError::InvalidArgument)?;
-    drv_acc.serialize(&mut *driver_pda.data.borrow_mut())?;
+    drv_acc.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
 
 
     if listing_pda.data_is_empty(){
@@ -378,7 +1106,7 @@ fn instruction_create_listing(program_id: &Pubkey, accounts: &[AccountInfo], amo
             return  Err(ProgramError::InvalidSeeds);
         }
         let rent=Rent::get()?;
-        let listing_account_size:usize=1+ 1+ 32+ 8+ 8;
+        let listing_account_size:usize=LISTING_ACCOUNT_SIZE;
         let listing_min_bal_for_rent_exempt=rent.minimum_balance(listing_account_size);
         let listing_pda_create_ix=system_instruction::create_account(user.key,
             listing_pda.key, listing_min_bal_for_rent_exempt, listing_account_size as u64, program_id);
@@ -394,47 +1122,98 @@ fn instruction_create_listing(program_id: &Pubkey, accounts: &[AccountInfo], amo
             seller: *user.key,
             amount_total: amount_points,
             price_per_point_lamports,
+            expires_at: expires_at.unwrap_or(0),
+            kind: ListingKind::FixedPrice,
+            auction_start_price: 0,
+            auction_floor_price: 0,
+            auction_start_ts: 0,
+            auction_decay_secs: 0,
         };
-        listing.serialize(&mut *listing_pda.data.borrow_mut())?;
+        listing.serialize(&mut &mut listing_pda.data.borrow_mut()[..])?;
+        Event::ListingCreated {listing: *listing_pda.key, seller: *user.key, amount_total: amount_points,
+            price_per_point_lamports, expires_at: listing.expires_at}.emit();
         msg!("Listing created: {} points at {} lamports each", amount_points, price_per_point_lamports);
     }else{
         let mut listing_data=ListingAccount::try_from_slice(&listing_pda.data.borrow())?;
-        msg!("before amount points : {}",listing_data.amount_total);
+        if listing_pda.owner != program_id || listing_data.seller != *user.key {
+            msg!("Listing does not belong to seller");
+            return Err(ProgramError::IllegalOwner);
+        }
+        if listing_data.amount_total == 0 {
+            // canceled or sold out listing starts over as a fixed price listing
+            listing_data.expires_at = 0;
+            listing_data.kind = ListingKind::FixedPrice;
+        }
+        if listing_data.kind != ListingKind::FixedPrice {
+            msg!("Cancel the running auction before listing at a fixed price");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if let Some(expires_at) = expires_at {
+            listing_data.expires_at = expires_at;
+        }
+        if is_listing_expired(&listing_data, now) {
+            msg!("Listing expired at {}, pass a new expires_at", listing_data.expires_at);
+            return Err(ProgramError::InvalidArgument);
+        }
         listing_data.price_per_point_lamports=(listing_data.amount_total * listing_data.price_per_point_lamports + price_per_point_lamports * amount_points) / (listing_data.amount_total + amount_points);
         listing_data.amount_total+=amount_points;
         listing_data.is_initialized=true;
-        listing_data.serialize(&mut *listing_pda.data.borrow_mut())?;
+        listing_data.serialize(&mut &mut listing_pda.data.borrow_mut()[..])?;
+        Event::ListingCreated {listing: *listing_pda.key, seller: *user.key, amount_total: listing_data.amount_total,
+            price_per_point_lamports: listing_data.price_per_point_lamports, expires_at: listing_data.expires_at}.emit();
         msg!("Listing updated: {} points at {} lamports each", amount_points, price_per_point_lamports);
     }
 
     Ok(())
 }
 
-fn instruction_buy_from_listing(program_id: &Pubkey, accounts: &[AccountInfo], buy_amount_points: u64) -> ProgramResult {
+fn instruction_buy_from_listing(program_id: &Pubkey, accounts: &[AccountInfo], requested_points: u64,
+    max_price_per_point_lamports: u64, min_points: Option<u64>,
+) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let user = next_account_info(account_info_iter)?; // signer
     let user_pda = next_account_info(account_info_iter)?; // writable buyer DriverAccount PDA
     let listing_pda = next_account_info(account_info_iter)?; // writable listing PDA
     let driver_pda = next_account_info(account_info_iter)?; // writable seller pubkey to receive lamports
     let system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
 
+    check_not_paused(program_id, state_pda, PauseScope::Marketplace)?;
     if !user.is_signer {
         msg!("Buyer must sign");
         return Err(ProgramError::MissingRequiredSignature);
     }
 
+    if listing_pda.owner != program_id {
+        msg!("Listing account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
     let mut listing = ListingAccount::try_from_slice(&listing_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
     if !listing.is_initialized {
         msg!("Listing not initialized");
         return Err(ProgramError::UninitializedAccount);
     }
-    if buy_amount_points == 0 || buy_amount_points > listing.amount_total {
-        msg!("Invalid buy amount");
+    let now = Clock::get()?.unix_timestamp;
+    if is_listing_expired(&listing, now) {
+        msg!("Listing expired at {}", listing.expires_at);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let price_per_point = listing_price(&listing, now);
+    if price_per_point > max_price_per_point_lamports {
+        msg!("Listing price {} exceeds max {}", price_per_point, max_price_per_point_lamports);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let buy_amount_points = match min_points {
+        Some(_) => requested_points.min(listing.amount_total),
+        None => requested_points,
+    };
+    if buy_amount_points == 0 || buy_amount_points > listing.amount_total || buy_amount_points < min_points.unwrap_or(0) {
+        msg!("Invalid buy amount, {} points available", listing.amount_total);
         return Err(ProgramError::InvalidArgument);
     }
 
     // total price
-    let total_price = listing.price_per_point_lamports.checked_mul(buy_amount_points).ok_or(ProgramError::InvalidArgument)?;
+    let total_price = price_per_point.checked_mul(buy_amount_points).ok_or(ProgramError::InvalidArgument)?;
     msg!("Buyer must pay {} lamports", total_price);
 
     // transfer lamports from buyer -> seller
@@ -448,40 +1227,14 @@ fn instruction_buy_from_listing(program_id: &Pubkey, accounts: &[AccountInfo], b
         ],
     )?;
 
-    // credit points to buyer driver account
-    let user_seeds=&[USER_SEED, user.key.as_ref()];
-    let (expected_user_pda_account,bump)=Pubkey::find_program_address(user_seeds, program_id);
-    let user_seeds_with_bump=&[USER_SEED, user.key.as_ref(), &[bump]];
-    if expected_user_pda_account!=*user_pda.key{
-        return  Err(ProgramError::InvalidSeeds);
-    }
-    msg!("a");
-    if user_pda.data_is_empty(){
-        let rent=Rent::get()?;
-        let user_account_size:usize=1+ 8;
-        let user_min_bal_for_rent_exempt=rent.minimum_balance(user_account_size);
-        let user_pda_create_ix=system_instruction::create_account(user.key,
-            user_pda.key, user_min_bal_for_rent_exempt, user_account_size as u64, program_id);
-        invoke_signed(&user_pda_create_ix,
-            &[user.clone(), user_pda.clone()],
-            &[user_seeds_with_bump])?;
-        msg!("user pda created!!");
-
-        // let user_acc = UserAccount {accountType:5,amp_balance:0};
-        // user_acc.serialize(&mut *user_pda.data.borrow_mut())?;
-    }
-    let mut buyer_user = UserAccount::try_from_slice(&user_pda.data.borrow())?;
-    msg!("b");
-    buyer_user.amp_balance = buyer_user.amp_balance.checked_add(buy_amount_points).ok_or(ProgramError::InvalidArgument)?;
-    buyer_user.accountType=5;
-    msg!("c");
-    buyer_user.serialize(&mut *user_pda.data.borrow_mut())?;
-    msg!("d");
+    // credit points to buyer user account
+    credit_user_account(program_id, user, user_pda, buy_amount_points)?;
     
     // reduce listing remaining
     listing.amount_total = listing.amount_total.checked_sub(buy_amount_points).ok_or(ProgramError::InvalidArgument)?;
-    msg!("e");
-    listing.serialize(&mut *listing_pda.data.borrow_mut())?;
+    listing.serialize(&mut &mut listing_pda.data.borrow_mut()[..])?;
+    Event::Purchase {listing: *listing_pda.key, buyer: *user.key, seller: listing.seller, amount_points: buy_amount_points,
+        price_per_point_lamports: price_per_point}.emit();
     msg!("Buyer purchased {} points", buy_amount_points);
     Ok(())
 }
@@ -496,6 +1249,10 @@ fn instruction_cancel_listing(program_id: &Pubkey, accounts: &[AccountInfo]) ->
         msg!("Seller must sign");
         return Err(ProgramError::MissingRequiredSignature);
     }
+    if listing_pda.owner != program_id || driver_pda.owner != program_id {
+        msg!("Listing and driver accounts must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
 
     let mut listing = ListingAccount::try_from_slice(&listing_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
     if listing.seller != *user.key {
@@ -511,7 +1268,7 @@ fn instruction_cancel_listing(program_id: &Pubkey, accounts: &[AccountInfo]) ->
             return Err(ProgramError::IllegalOwner);
         }
         drv.amp_balance = drv.amp_balance.checked_add(remaining).ok_or(ProgramError::InvalidArgument)?;
-        drv.serialize(&mut *driver_pda.data.borrow_mut())?;
+        drv.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
     }
 
     // zero out listing
@@ -519,7 +1276,2355 @@ fn instruction_cancel_listing(program_id: &Pubkey, accounts: &[AccountInfo]) ->
     listing.price_per_point_lamports=0;
     listing.is_initialized=false;
 
-    listing.serialize(&mut *listing_pda.data.borrow_mut())?;
+    listing.serialize(&mut &mut listing_pda.data.borrow_mut()[..])?;
+    Event::ListingCancelled {listing: *listing_pda.key, seller: *user.key, returned_points: remaining}.emit();
     msg!("Listing canceled, returned {} points", remaining);
     Ok(())
 }
+
+fn instruction_set_charger_status(program_id: &Pubkey, accounts: &[AccountInfo], status: ChargerStatus) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let signer = next_account_info(account_info_iter)?; // operator or device key
+    let charger_pda = next_account_info(account_info_iter)?; // writable ChargerAccount
+
+    if !signer.is_signer {
+        msg!("Operator or device must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if charger_pda.owner != program_id {
+        msg!("Charger account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    if !charger.is_initialized {
+        msg!("Charger uninitialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if *signer.key != charger.authority && *signer.key != charger.device_pubkey {
+        msg!("Only the charger operator or device can report status");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    charger.status = status;
+    charger.last_heartbeat = Clock::get()?.unix_timestamp;
+    charger.serialize(&mut &mut charger_pda.data.borrow_mut()[..])?;
+    Event::ChargerStatusChanged {charger: *charger_pda.key, status, at: charger.last_heartbeat}.emit();
+    msg!("Charger status {:?} at {}", status, charger.last_heartbeat);
+    Ok(())
+}
+
+fn instruction_reserve_charger(program_id: &Pubkey, accounts: &[AccountInfo], start: i64, duration: i64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer
+    let reservation_pda = next_account_info(account_info_iter)?; // writable ReservationAccount PDA
+    let charger_pda = next_account_info(account_info_iter)?; // readonly ChargerAccount
+    let charger_owner_account = next_account_info(account_info_iter)?; // writable, receives deposit
+    let system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::Sessions)?;
+    if !user.is_signer {
+        msg!("Driver must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if charger_pda.owner != program_id {
+        msg!("Charger account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    if !charger.is_initialized {
+        msg!("Charger uninitialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if matches!(charger.status, ChargerStatus::Faulted | ChargerStatus::Offline) {
+        msg!("Charger is {:?}", charger.status);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if charger.closing_requested_at != 0 {
+        msg!("Charger is closing");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if *charger_owner_account.key != charger.authority {
+        msg!("Charger owner account mismatch");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    if duration <= 0 || duration > MAX_RESERVATION_SECS {
+        msg!("Reservation duration must be 1..={} secs", MAX_RESERVATION_SECS);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let end = start.checked_add(duration).ok_or(ProgramError::InvalidArgument)?;
+    if end <= now || start > now + MAX_RESERVATION_LEAD_SECS {
+        msg!("Reservation window must end in the future and start within {} secs", MAX_RESERVATION_LEAD_SECS);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let reservation_seeds=&[RESERVATION_SEED, charger_pda.key.as_ref()];
+    let (expected_reservation_pda,bump)=Pubkey::find_program_address(reservation_seeds, program_id);
+    let reservation_seeds_with_bump=&[RESERVATION_SEED, charger_pda.key.as_ref(), &[bump]];
+    if expected_reservation_pda!=*reservation_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    // one reservation per charger at a time, an expired one has to go through ExpireReservation first
+    if !reservation_pda.data_is_empty(){
+        let existing = ReservationAccount::try_from_slice(&reservation_pda.data.borrow())?;
+        msg!("Charger already reserved until {}", existing.end_ts);
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    let rent=Rent::get()?;
+    let reservation_account_size:usize=1+ 1+ 32+ 32+ 8+ 8+ 8;
+    let reservation_min_bal_for_rent_exempt=rent.minimum_balance(reservation_account_size);
+    let reservation_pda_create_ix=system_instruction::create_account(user.key,
+        reservation_pda.key, reservation_min_bal_for_rent_exempt, reservation_account_size as u64, program_id);
+    invoke_signed(&reservation_pda_create_ix,
+        &[user.clone(), reservation_pda.clone()],
+        &[reservation_seeds_with_bump])?;
+    msg!("reservation pda created!!");
+
+    // Transfer the non-refundable deposit to the charger operator
+    let transfer_ix = system_instruction::transfer(user.key, &charger.authority, RESERVATION_DEPOSIT_LAMPORTS);
+    invoke(
+        &transfer_ix,
+        &[user.clone(), charger_owner_account.clone(), system_program_acc.clone()],
+    )?;
+
+    let reservation = ReservationAccount {accountType:6, is_initialized: true, driver: *user.key,
+        charger: *charger_pda.key, start_ts: start, end_ts: end,
+        deposit_lamports: RESERVATION_DEPOSIT_LAMPORTS,
+    };
+    reservation.serialize(&mut &mut reservation_pda.data.borrow_mut()[..])?;
+    Event::ChargerReserved {charger: *charger_pda.key, driver: *user.key, start_ts: start, end_ts: end,
+        deposit_lamports: RESERVATION_DEPOSIT_LAMPORTS}.emit();
+    msg!("Charger reserved from {} to {}", start, end);
+    Ok(())
+}
+
+fn instruction_report_charging_complete(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let device = next_account_info(account_info_iter)?; // signer, charger device key
+    let session_pda = next_account_info(account_info_iter)?; // writable SessionAccount
+    let charger_pda = next_account_info(account_info_iter)?; // readonly ChargerAccount
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::Sessions)?;
+    if !device.is_signer {
+        msg!("Charger device must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if session_pda.owner != program_id || charger_pda.owner != program_id {
+        msg!("Session and charger accounts must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    if *device.key != charger.device_pubkey {
+        msg!("Signer is not the charger device");
+        return Err(ProgramError::IllegalOwner);
+    }
+    let mut session = SessionAccount::try_from_slice(&session_pda.data.borrow())?;
+    if !session.is_initialized || session.charger != *charger_pda.key {
+        msg!("Session does not belong to this charger");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if session.settled || session.charging_finished_ts != 0 {
+        msg!("Session already settled or completion already reported");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    session.charging_finished_ts = Clock::get()?.unix_timestamp;
+    session.serialize(&mut &mut session_pda.data.borrow_mut()[..])?;
+    Event::ChargingCompleted {session: *session_pda.key, charger: *charger_pda.key, at: session.charging_finished_ts}.emit();
+    msg!("Charging completed at {}", session.charging_finished_ts);
+    Ok(())
+}
+
+fn instruction_set_tariff_schedule(program_id: &Pubkey, accounts: &[AccountInfo], bands: Vec<TariffBand>) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let operator = next_account_info(account_info_iter)?; // signer, charger authority
+    let charger_pda = next_account_info(account_info_iter)?; // writable ChargerAccount
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::All)?;
+    if !operator.is_signer {
+        msg!("Operator must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if charger_pda.owner != program_id {
+        msg!("Charger account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    if charger.authority != *operator.key {
+        msg!("Only the charger operator can set tariffs");
+        return Err(ProgramError::IllegalOwner);
+    }
+    // StopSession prices the whole session against the bands it finds, so they stay fixed while any is open
+    if charger.open_sessions > 0 {
+        msg!("Charger still has {} open sessions", charger.open_sessions);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if bands.len() > MAX_TARIFF_BANDS {
+        msg!("At most {} tariff bands", MAX_TARIFF_BANDS);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mut covered_hours: u32 = 0;
+    for band in &bands {
+        if band.start_hour >= 24 || band.end_hour > 24 || band.start_hour == band.end_hour
+            || band.price_multiplier_bps > MAX_TARIFF_MULTIPLIER_BPS || band.points_multiplier_bps > MAX_TARIFF_MULTIPLIER_BPS {
+            msg!("Invalid tariff band {:?}", band);
+            return Err(ProgramError::InvalidArgument);
+        }
+        let hours = tariff_band_hours(band);
+        if covered_hours & hours != 0 {
+            msg!("Tariff band {:?} overlaps an earlier band", band);
+            return Err(ProgramError::InvalidArgument);
+        }
+        covered_hours |= hours;
+    }
+
+    charger.tariff_bands = [TariffBand::default(); MAX_TARIFF_BANDS];
+    charger.tariff_bands[..bands.len()].copy_from_slice(&bands);
+    charger.tariff_band_count = bands.len() as u8;
+    charger.serialize(&mut &mut charger_pda.data.borrow_mut()[..])?;
+    Event::TariffScheduleSet {charger: *charger_pda.key, band_count: charger.tariff_band_count}.emit();
+    msg!("Tariff schedule set with {} bands", bands.len());
+    Ok(())
+}
+
+fn instruction_stake(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, tier: u8) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer
+    let driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA
+    let stake_pda = next_account_info(account_info_iter)?; // writable StakeAccount PDA
+    let _system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::All)?;
+    if !user.is_signer {
+        msg!("Staker must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let Some(&(lock_secs, _)) = STAKE_TIERS.get(tier as usize) else {
+        msg!("Unknown stake tier {}", tier);
+        return Err(ProgramError::InvalidArgument);
+    };
+    if amount == 0 {
+        msg!("Stake amount must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if driver_pda.owner != program_id {
+        msg!("Driver account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut drv_acc = DriverAccount::try_from_slice(&driver_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if drv_acc.owner != *user.key {
+        msg!("Staker does not own driver account");
+        return Err(ProgramError::IllegalOwner);
+    }
+    if drv_acc.amp_balance < amount {
+        msg!("Insufficient points to stake");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    let stake_seeds=&[STAKE_SEED, user.key.as_ref()];
+    let (expected_stake_pda,bump)=Pubkey::find_program_address(stake_seeds, program_id);
+    let stake_seeds_with_bump=&[STAKE_SEED, user.key.as_ref(), &[bump]];
+    if expected_stake_pda!=*stake_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if stake_pda.data_is_empty(){
+        let rent=Rent::get()?;
+        let stake_account_size:usize=1+ 1+ 32+ 8+ 1+ 8+ 8;
+        let stake_min_bal_for_rent_exempt=rent.minimum_balance(stake_account_size);
+        let stake_pda_create_ix=system_instruction::create_account(user.key,
+            stake_pda.key, stake_min_bal_for_rent_exempt, stake_account_size as u64, program_id);
+        invoke_signed(&stake_pda_create_ix,
+            &[user.clone(), stake_pda.clone()],
+            &[stake_seeds_with_bump])?;
+        msg!("stake pda created!!");
+
+        let stake = StakeAccount {accountType:7, is_initialized: true, owner: *user.key,
+            amount: 0, tier: 0, lock_until: 0, unstake_requested_at: 0,
+        };
+        stake.serialize(&mut &mut stake_pda.data.borrow_mut()[..])?;
+    }
+
+    let mut stake = StakeAccount::try_from_slice(&stake_pda.data.borrow())?;
+    if stake.unstake_requested_at != 0 {
+        msg!("Stake is cooling down, wait for unstake to complete");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if stake.amount > 0 && tier < stake.tier {
+        msg!("Cannot top up into a shorter lock-up tier");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // topping up restarts the lock for the whole position
+    let now = Clock::get()?.unix_timestamp;
+    drv_acc.amp_balance = drv_acc.amp_balance.checked_sub(amount).ok_or(ProgramError::InvalidArgument)?;
+    drv_acc.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
+    stake.amount = stake.amount.checked_add(amount).ok_or(ProgramError::InvalidArgument)?;
+    stake.tier = tier;
+    stake.lock_until = stake.lock_until.max(now + lock_secs);
+    stake.serialize(&mut &mut stake_pda.data.borrow_mut()[..])?;
+    Event::Staked {owner: *user.key, amount, tier, lock_until: stake.lock_until}.emit();
+    msg!("Staked {} points in tier {}, locked until {}", amount, tier, stake.lock_until);
+    Ok(())
+}
+
+fn instruction_unstake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer
+    let driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA
+    let stake_pda = next_account_info(account_info_iter)?; // writable StakeAccount PDA
+
+    if !user.is_signer {
+        msg!("Staker must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if driver_pda.owner != program_id || stake_pda.owner != program_id {
+        msg!("Driver and stake accounts must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut stake = StakeAccount::try_from_slice(&stake_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if stake.owner != *user.key {
+        msg!("Only the staker can unstake");
+        return Err(ProgramError::IllegalOwner);
+    }
+    if stake.amount == 0 {
+        msg!("Nothing staked");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    if stake.unstake_requested_at == 0 {
+        if now < stake.lock_until {
+            msg!("Stake locked until {}", stake.lock_until);
+            return Err(ProgramError::InvalidArgument);
+        }
+        stake.unstake_requested_at = now;
+        stake.serialize(&mut &mut stake_pda.data.borrow_mut()[..])?;
+        Event::UnstakeRequested {owner: *user.key, withdrawable_at: now + UNSTAKE_COOLDOWN_SECS}.emit();
+        msg!("Unstake requested, withdrawable after {}", now + UNSTAKE_COOLDOWN_SECS);
+        return Ok(());
+    }
+
+    if now < stake.unstake_requested_at + UNSTAKE_COOLDOWN_SECS {
+        msg!("Unstake cool-down ends at {}", stake.unstake_requested_at + UNSTAKE_COOLDOWN_SECS);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut drv_acc = DriverAccount::try_from_slice(&driver_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if drv_acc.owner != *user.key {
+        msg!("Staker does not own driver account");
+        return Err(ProgramError::IllegalOwner);
+    }
+    let returned = stake.amount;
+    drv_acc.amp_balance = drv_acc.amp_balance.checked_add(returned).ok_or(ProgramError::InvalidArgument)?;
+    drv_acc.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
+
+    stake.amount = 0;
+    stake.tier = 0;
+    stake.lock_until = 0;
+    stake.unstake_requested_at = 0;
+    stake.serialize(&mut &mut stake_pda.data.borrow_mut()[..])?;
+    Event::Unstaked {owner: *user.key, amount: returned}.emit();
+    msg!("Unstaked {} points", returned);
+    Ok(())
+}
+
+/// Reads the upgrade authority out of the upgradeable loader's ProgramData account:
+/// u32 enum tag (3 = ProgramData), u64 slot, then an Option<Pubkey>
+fn program_upgrade_authority(program_id: &Pubkey, program_data: &AccountInfo) -> Result<Option<Pubkey>, ProgramError> {
+    let (expected_program_data,_)=Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::ID);
+    if expected_program_data!=*program_data.key || *program_data.owner != bpf_loader_upgradeable::ID {
+        msg!("Invalid ProgramData account");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let data = program_data.data.borrow();
+    if data.len() < 45 || data[0..4] != 3u32.to_le_bytes() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    match data[12] {
+        0 => Ok(None),
+        1 => Ok(Some(Pubkey::new_from_array(data[13..45].try_into().unwrap()))),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+fn instruction_initialize(program_id: &Pubkey, accounts: &[AccountInfo], admin: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let payer = next_account_info(account_info_iter)?; // signer
+    let state_pda = next_account_info(account_info_iter)?; // writable StateAccount PDA
+    let _system_program_acc = next_account_info(account_info_iter)?;
+    let program_data = next_account_info(account_info_iter)?; // readonly ProgramData account of this program
+
+    if !payer.is_signer {
+        msg!("Payer must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // Only the upgrade authority may pick the first admin, otherwise anyone could front-run the deploy
+    if program_upgrade_authority(program_id, program_data)? != Some(*payer.key) {
+        msg!("Initialize must be signed by the program upgrade authority");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (expected_state_pda,bump)=Pubkey::find_program_address(&[STATE_SEED], program_id);
+    if expected_state_pda!=*state_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !state_pda.data_is_empty(){
+        msg!("State already initialized");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent=Rent::get()?;
+    let state_account_size:usize=1+ 1+ 32+ 32+ 8+ 32+ 8+ 8+ 8+ 8+ 8+ 1+ 1+ 1+ 1;
+    let state_min_bal_for_rent_exempt=rent.minimum_balance(state_account_size);
+    let state_pda_create_ix=system_instruction::create_account(payer.key,
+        state_pda.key, state_min_bal_for_rent_exempt, state_account_size as u64, program_id);
+    invoke_signed(&state_pda_create_ix,
+        &[payer.clone(), state_pda.clone()],
+        &[&[STATE_SEED, &[bump]]])?;
+
+    let state = StateAccount {accountType:8, is_initialized: true, admin, pending_admin: Pubkey::default(),
+        registration_fee: REG_FEE_LAMPORTS, fee_mint: Pubkey::default(),
+        max_rate_points_per_sec: DEFAULT_MAX_RATE_POINTS_PER_SEC, epoch_secs: DEFAULT_EPOCH_SECS,
+        epoch_budget: DEFAULT_EPOCH_BUDGET_POINTS, epoch_start: Clock::get()?.unix_timestamp, epoch_issued: 0,
+        paused: false, registration_paused: false, sessions_paused: false, marketplace_paused: false,
+    };
+    state.serialize(&mut &mut state_pda.data.borrow_mut()[..])?;
+    Event::Initialized {admin}.emit();
+    msg!("State initialized with admin {}", admin);
+    Ok(())
+}
+
+fn instruction_slash_charger(program_id: &Pubkey, accounts: &[AccountInfo], slash_bps: u16, reason: SlashReason) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin = next_account_info(account_info_iter)?; // signer, writable, receives the slashed lamports
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA
+    let charger_pda = next_account_info(account_info_iter)?; // writable ChargerAccount
+    let charger_vault = next_account_info(account_info_iter)?; // writable vault PDA
+    let system_program_acc = next_account_info(account_info_iter)?;
+
+    let state = load_state(program_id, state_pda)?;
+    if !admin.is_signer || *admin.key != state.admin {
+        msg!("Admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if slash_bps == 0 || slash_bps as u64 > BPS_DENOMINATOR {
+        msg!("slash_bps must be 1..={}", BPS_DENOMINATOR);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if charger_pda.owner != program_id {
+        msg!("Charger account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    if reason == SlashReason::Offline {
+        let now = Clock::get()?.unix_timestamp;
+        let stale = now - charger.last_heartbeat > HEARTBEAT_TIMEOUT_SECS;
+        if !stale && charger.status != ChargerStatus::Offline {
+            msg!("Charger is online, last heartbeat at {}", charger.last_heartbeat);
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+
+    let mut slashed = charger.bond_lamports * slash_bps as u64 / BPS_DENOMINATOR;
+    // a system account left below rent exemption would be rejected, so take all of it instead
+    let remaining = charger.bond_lamports - slashed;
+    if remaining > 0 && remaining < Rent::get()?.minimum_balance(0) {
+        slashed = charger.bond_lamports;
+    }
+    if slashed == 0 {
+        msg!("Nothing left to slash");
+        return Err(ProgramError::InsufficientFunds);
+    }
+    transfer_from_charger_vault(program_id, charger_pda, charger_vault, admin, system_program_acc, slashed)?;
+
+    charger.bond_lamports -= slashed;
+    charger.slash_count = charger.slash_count.saturating_add(1);
+    charger.serialize(&mut &mut charger_pda.data.borrow_mut()[..])?;
+    Event::ChargerSlashed {charger: *charger_pda.key, lamports: slashed, reason}.emit();
+    msg!("Slashed {} lamports from charger {} for {:?}", slashed, charger_pda.key, reason);
+    Ok(())
+}
+
+fn instruction_close_charger(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let operator = next_account_info(account_info_iter)?; // signer, writable, receives bond and rent
+    let charger_pda = next_account_info(account_info_iter)?; // writable ChargerAccount
+    let charger_vault = next_account_info(account_info_iter)?; // writable vault PDA
+    let system_program_acc = next_account_info(account_info_iter)?;
+
+    if !operator.is_signer {
+        msg!("Operator must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if charger_pda.owner != program_id {
+        msg!("Charger account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    if charger.authority != *operator.key {
+        msg!("Only the charger operator can close it");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    // first call starts unbonding: no new sessions or reservations, SlashCharger still applies
+    let now = Clock::get()?.unix_timestamp;
+    if charger.closing_requested_at == 0 {
+        charger.closing_requested_at = now;
+        charger.serialize(&mut &mut charger_pda.data.borrow_mut()[..])?;
+        Event::ChargerCloseRequested {charger: *charger_pda.key, closable_at: now + CHARGER_UNBONDING_SECS}.emit();
+        msg!("Charger closing, bond refundable from {}", now + CHARGER_UNBONDING_SECS);
+        return Ok(());
+    }
+    if now < charger.closing_requested_at + CHARGER_UNBONDING_SECS {
+        msg!("Charger is unbonding until {}", charger.closing_requested_at + CHARGER_UNBONDING_SECS);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if charger.open_sessions > 0 {
+        msg!("Charger still has {} open sessions", charger.open_sessions);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let refund = charger.bond_lamports;
+    if refund > 0 {
+        transfer_from_charger_vault(program_id, charger_pda, charger_vault, operator, system_program_acc, refund)?;
+    }
+    close_program_account(charger_pda, operator)?;
+    Event::ChargerClosed {charger: *charger_pda.key, refunded_bond_lamports: refund}.emit();
+    msg!("Charger closed, refunded {} lamports of bond", refund);
+    Ok(())
+}
+
+fn instruction_transfer_points(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let sender = next_account_info(account_info_iter)?; // signer
+    let sender_driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA of sender
+    let recipient_driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA of recipient
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::All)?;
+    if !sender.is_signer {
+        msg!("Sender must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if amount == 0 {
+        msg!("Transfer amount must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if sender_driver_pda.key == recipient_driver_pda.key {
+        msg!("Cannot transfer points to the same account");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if sender_driver_pda.owner != program_id || recipient_driver_pda.owner != program_id {
+        msg!("Driver accounts must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut from = DriverAccount::try_from_slice(&sender_driver_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if from.owner != *sender.key {
+        msg!("Sender does not own driver account");
+        return Err(ProgramError::IllegalOwner);
+    }
+    let mut to = DriverAccount::try_from_slice(&recipient_driver_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if !to.is_initialized || to.accountType != 2 {
+        msg!("Recipient driver account not init");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if from.amp_balance < amount {
+        msg!("Insufficient points to transfer");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    from.amp_balance = from.amp_balance.checked_sub(amount).ok_or(ProgramError::InvalidArgument)?;
+    to.amp_balance = to.amp_balance.checked_add(amount).ok_or(ProgramError::InvalidArgument)?;
+    from.serialize(&mut &mut sender_driver_pda.data.borrow_mut()[..])?;
+    to.serialize(&mut &mut recipient_driver_pda.data.borrow_mut()[..])?;
+    Event::PointsTransferred {from: from.owner, to: to.owner, amount}.emit();
+    msg!("Transferred {} points from {} to {}", amount, from.owner, to.owner);
+    Ok(())
+}
+
+fn instruction_create_fleet(program_id: &Pubkey, accounts: &[AccountInfo], points_to_fleet: bool, max_per_session_lamports: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin = next_account_info(account_info_iter)?; // signer, fleet admin and payer
+    let fleet_pda = next_account_info(account_info_iter)?; // writable FleetAccount PDA
+    let _system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::All)?;
+    if !admin.is_signer {
+        msg!("Fleet admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let fleet_seeds=&[FLEET_SEED, admin.key.as_ref()];
+    let (expected_fleet_pda,bump)=Pubkey::find_program_address(fleet_seeds, program_id);
+    let fleet_seeds_with_bump=&[FLEET_SEED, admin.key.as_ref(), &[bump]];
+    if expected_fleet_pda!=*fleet_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !fleet_pda.data_is_empty(){
+        msg!("Fleet already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent=Rent::get()?;
+    let fleet_account_size:usize=1+ 1+ 32+ 1+ 8;
+    let fleet_min_bal_for_rent_exempt=rent.minimum_balance(fleet_account_size);
+    let fleet_pda_create_ix=system_instruction::create_account(admin.key,
+        fleet_pda.key, fleet_min_bal_for_rent_exempt, fleet_account_size as u64, program_id);
+    invoke_signed(&fleet_pda_create_ix,
+        &[admin.clone(), fleet_pda.clone()],
+        &[fleet_seeds_with_bump])?;
+
+    let fleet = FleetAccount {accountType:9, is_initialized: true, admin: *admin.key, points_to_fleet, max_per_session_lamports};
+    fleet.serialize(&mut &mut fleet_pda.data.borrow_mut()[..])?;
+    Event::FleetCreated {fleet: *fleet_pda.key, admin: *admin.key, points_to_fleet, max_per_session_lamports}.emit();
+    msg!("Fleet created by {}", admin.key);
+    Ok(())
+}
+
+fn instruction_fund_fleet(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let funder = next_account_info(account_info_iter)?; // signer
+    let fleet_pda = next_account_info(account_info_iter)?; // readonly FleetAccount
+    let fleet_vault = next_account_info(account_info_iter)?; // writable fleet vault PDA
+    let system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::All)?;
+    if !funder.is_signer {
+        msg!("Funder must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if fleet_pda.owner != program_id {
+        msg!("Fleet account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let (expected_vault,_)=Pubkey::find_program_address(&[FLEET_VAULT_SEED, fleet_pda.key.as_ref()], program_id);
+    if expected_vault!=*fleet_vault.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let transfer_ix = system_instruction::transfer(funder.key, fleet_vault.key, amount);
+    invoke(&transfer_ix, &[funder.clone(), fleet_vault.clone(), system_program_acc.clone()])?;
+    Event::FleetFunded {fleet: *fleet_pda.key, funder: *funder.key, lamports: amount}.emit();
+    msg!("Fleet {} funded with {} lamports", fleet_pda.key, amount);
+    Ok(())
+}
+
+fn instruction_withdraw_fleet(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin = next_account_info(account_info_iter)?; // signer, writable, receives the lamports
+    let fleet_pda = next_account_info(account_info_iter)?; // readonly FleetAccount
+    let fleet_vault = next_account_info(account_info_iter)?; // writable fleet vault PDA
+    let system_program_acc = next_account_info(account_info_iter)?;
+
+    let fleet = load_fleet(program_id, fleet_pda)?;
+    if !admin.is_signer || fleet.admin != *admin.key {
+        msg!("Fleet admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    transfer_from_fleet_vault(program_id, fleet_pda, fleet_vault, admin, system_program_acc, amount)?;
+    Event::FleetWithdrawn {fleet: *fleet_pda.key, lamports: amount}.emit();
+    msg!("Withdrew {} lamports from fleet {}", amount, fleet_pda.key);
+    Ok(())
+}
+
+fn instruction_set_fleet_member(program_id: &Pubkey, accounts: &[AccountInfo], driver: Pubkey, active: bool, spend_limit_lamports: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin = next_account_info(account_info_iter)?; // signer, pays for the member account
+    let fleet_pda = next_account_info(account_info_iter)?; // readonly FleetAccount
+    let fleet_member_pda = next_account_info(account_info_iter)?; // writable FleetMemberAccount PDA
+    let _system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::All)?;
+    let fleet = load_fleet(program_id, fleet_pda)?;
+    if !admin.is_signer || fleet.admin != *admin.key {
+        msg!("Fleet admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let member_seeds=&[FLEET_MEMBER_SEED, fleet_pda.key.as_ref(), driver.as_ref()];
+    let (expected_member_pda,bump)=Pubkey::find_program_address(member_seeds, program_id);
+    let member_seeds_with_bump=&[FLEET_MEMBER_SEED, fleet_pda.key.as_ref(), driver.as_ref(), &[bump]];
+    if expected_member_pda!=*fleet_member_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut member = if fleet_member_pda.data_is_empty() {
+        let rent=Rent::get()?;
+        let member_account_size:usize=1+ 1+ 32+ 32+ 1+ 8+ 8;
+        let member_min_bal_for_rent_exempt=rent.minimum_balance(member_account_size);
+        let member_pda_create_ix=system_instruction::create_account(admin.key,
+            fleet_member_pda.key, member_min_bal_for_rent_exempt, member_account_size as u64, program_id);
+        invoke_signed(&member_pda_create_ix,
+            &[admin.clone(), fleet_member_pda.clone()],
+            &[member_seeds_with_bump])?;
+        msg!("fleet member pda created!!");
+        FleetMemberAccount {accountType:10, is_initialized: true, fleet: *fleet_pda.key, driver,
+            active, spend_limit_lamports, spent_lamports: 0,
+        }
+    } else {
+        FleetMemberAccount::try_from_slice(&fleet_member_pda.data.borrow())?
+    };
+    member.active = active;
+    member.spend_limit_lamports = spend_limit_lamports;
+    member.serialize(&mut &mut fleet_member_pda.data.borrow_mut()[..])?;
+    Event::FleetMemberSet {fleet: *fleet_pda.key, driver, active, spend_limit_lamports}.emit();
+    msg!("Fleet member {} active: {}, limit {} lamports, spent {}", driver, active, spend_limit_lamports, member.spent_lamports);
+    Ok(())
+}
+
+fn instruction_create_bid(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, price_per_point: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer, buyer
+    let bid_pda = next_account_info(account_info_iter)?; // writable BidAccount PDA
+    let driver_pda = next_account_info(account_info_iter)?; // writable buyer DriverAccount PDA, receives the points
+    let system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::Marketplace)?;
+    if !user.is_signer {
+        msg!("Buyer must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if amount == 0 || price_per_point == 0 {
+        msg!("Bid amount and price must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let escrow = amount.checked_mul(price_per_point).ok_or(ProgramError::InvalidArgument)?;
+
+    // filled points are credited to the buyer's driver account
+    create_driver_account_if_empty(program_id, user, driver_pda)?;
+
+    let bid_seeds=&[BID_SEED, user.key.as_ref()];
+    let (expected_bid_pda,bump)=Pubkey::find_program_address(bid_seeds, program_id);
+    let bid_seeds_with_bump=&[BID_SEED, user.key.as_ref(), &[bump]];
+    if expected_bid_pda!=*bid_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut bid = if bid_pda.data_is_empty(){
+        let rent=Rent::get()?;
+        let bid_account_size:usize=1+ 1+ 32+ 8+ 8;
+        let bid_min_bal_for_rent_exempt=rent.minimum_balance(bid_account_size);
+        let bid_pda_create_ix=system_instruction::create_account(user.key,
+            bid_pda.key, bid_min_bal_for_rent_exempt, bid_account_size as u64, program_id);
+        invoke_signed(&bid_pda_create_ix,
+            &[user.clone(), bid_pda.clone()],
+            &[bid_seeds_with_bump])?;
+        msg!("bid pda created!!");
+        BidAccount {accountType:11, is_initialized: true, buyer: *user.key, amount_total: 0, price_per_point_lamports: price_per_point}
+    }else{
+        BidAccount::try_from_slice(&bid_pda.data.borrow())?
+    };
+
+    // escrow is exact per point, so a live bid can only be topped up at its own price
+    if bid.amount_total > 0 && bid.price_per_point_lamports != price_per_point {
+        msg!("Bid is open at {} lamports per point, cancel it to change price", bid.price_per_point_lamports);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let escrow_ix = system_instruction::transfer(user.key, bid_pda.key, escrow);
+    invoke(&escrow_ix, &[user.clone(), bid_pda.clone(), system_program_acc.clone()])?;
+
+    bid.is_initialized = true;
+    bid.price_per_point_lamports = price_per_point;
+    bid.amount_total = bid.amount_total.checked_add(amount).ok_or(ProgramError::InvalidArgument)?;
+    bid.serialize(&mut &mut bid_pda.data.borrow_mut()[..])?;
+    Event::BidPlaced {bid: *bid_pda.key, buyer: *user.key, amount_total: bid.amount_total,
+        price_per_point_lamports: price_per_point}.emit();
+    msg!("Bid open: {} points at {} lamports each, escrowed {}", bid.amount_total, price_per_point, escrow);
+    Ok(())
+}
+
+fn instruction_fill_bid(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer, writable seller wallet receives lamports
+    let seller_driver_pda = next_account_info(account_info_iter)?; // writable seller DriverAccount PDA
+    let bid_pda = next_account_info(account_info_iter)?; // writable BidAccount PDA
+    let buyer_driver_pda = next_account_info(account_info_iter)?; // writable buyer DriverAccount PDA
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::Marketplace)?;
+    if !user.is_signer {
+        msg!("Seller must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if bid_pda.owner != program_id || seller_driver_pda.owner != program_id || buyer_driver_pda.owner != program_id {
+        msg!("Bid and driver accounts must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut bid = BidAccount::try_from_slice(&bid_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if !bid.is_initialized {
+        msg!("Bid not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if amount == 0 || amount > bid.amount_total {
+        msg!("Invalid fill amount");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let (expected_buyer_driver_pda,_)=Pubkey::find_program_address(&[DRIVER_SEED, bid.buyer.as_ref()], program_id);
+    if expected_buyer_driver_pda!=*buyer_driver_pda.key || seller_driver_pda.key == buyer_driver_pda.key {
+        msg!("Buyer driver account mismatch");
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut seller = DriverAccount::try_from_slice(&seller_driver_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if seller.owner != *user.key {
+        msg!("Seller does not own driver account");
+        return Err(ProgramError::IllegalOwner);
+    }
+    if seller.amp_balance < amount {
+        msg!("Insufficient points to fill bid");
+        return Err(ProgramError::InsufficientFunds);
+    }
+    let mut buyer = DriverAccount::try_from_slice(&buyer_driver_pda.data.borrow())?;
+
+    // escrowed lamports -> seller
+    let payment = amount.checked_mul(bid.price_per_point_lamports).ok_or(ProgramError::InvalidArgument)?;
+    **bid_pda.try_borrow_mut_lamports()? = bid_pda.lamports().checked_sub(payment).ok_or(ProgramError::InsufficientFunds)?;
+    **user.try_borrow_mut_lamports()? = user.lamports().checked_add(payment).ok_or(ProgramError::InvalidArgument)?;
+
+    // points seller -> buyer
+    seller.amp_balance = seller.amp_balance.checked_sub(amount).ok_or(ProgramError::InvalidArgument)?;
+    buyer.amp_balance = buyer.amp_balance.checked_add(amount).ok_or(ProgramError::InvalidArgument)?;
+    seller.serialize(&mut &mut seller_driver_pda.data.borrow_mut()[..])?;
+    buyer.serialize(&mut &mut buyer_driver_pda.data.borrow_mut()[..])?;
+
+    bid.amount_total -= amount;
+    bid.serialize(&mut &mut bid_pda.data.borrow_mut()[..])?;
+    Event::BidFilled {bid: *bid_pda.key, buyer: bid.buyer, seller: *user.key, amount_points: amount, lamports: payment}.emit();
+    msg!("Bid filled: {} points for {} lamports", amount, payment);
+    Ok(())
+}
+
+fn instruction_cancel_bid(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer, writable buyer wallet
+    let bid_pda = next_account_info(account_info_iter)?; // writable BidAccount PDA
+
+    if !user.is_signer {
+        msg!("Buyer must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if bid_pda.owner != program_id {
+        msg!("Bid account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut bid = BidAccount::try_from_slice(&bid_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if bid.buyer != *user.key {
+        msg!("Only the bidder can cancel");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let refund = bid.amount_total.checked_mul(bid.price_per_point_lamports).ok_or(ProgramError::InvalidArgument)?;
+    **bid_pda.try_borrow_mut_lamports()? = bid_pda.lamports().checked_sub(refund).ok_or(ProgramError::InsufficientFunds)?;
+    **user.try_borrow_mut_lamports()? = user.lamports().checked_add(refund).ok_or(ProgramError::InvalidArgument)?;
+
+    // zero out bid
+    bid.amount_total = 0;
+    bid.price_per_point_lamports = 0;
+    bid.is_initialized = false;
+    bid.serialize(&mut &mut bid_pda.data.borrow_mut()[..])?;
+    Event::BidCancelled {bid: *bid_pda.key, buyer: *user.key, refunded_lamports: refund}.emit();
+    msg!("Bid canceled, refunded {} lamports", refund);
+    Ok(())
+}
+
+fn instruction_market_buy(program_id: &Pubkey, accounts: &[AccountInfo], max_points: u64, max_price_per_point: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer
+    let user_pda = next_account_info(account_info_iter)?; // writable buyer UserAccount PDA
+    let system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+    // remaining: (writable listing PDA, writable seller wallet) pairs, cheapest first
+
+    check_not_paused(program_id, state_pda, PauseScope::Marketplace)?;
+    if !user.is_signer {
+        msg!("Buyer must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if max_points == 0 {
+        msg!("max_points must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut remaining = max_points;
+    let mut total_paid: u64 = 0;
+    let mut last_price: u64 = 0;
+    while remaining > 0 {
+        let Some(listing_pda) = account_info_iter.next() else { break };
+        let seller_account = next_account_info(account_info_iter)?;
+        if listing_pda.owner != program_id {
+            msg!("Listing account must be owned by the program");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut listing = ListingAccount::try_from_slice(&listing_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+        if !listing.is_initialized || listing.amount_total == 0 || is_listing_expired(&listing, now) {
+            continue;
+        }
+        if *seller_account.key != listing.seller {
+            msg!("Seller account does not match listing {}", listing_pda.key);
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let price = listing_price(&listing, now);
+        if price < last_price {
+            msg!("Listings must be sorted cheapest first");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if price > max_price_per_point {
+            msg!("Listing {} at {} lamports exceeds limit {}", listing_pda.key, price, max_price_per_point);
+            return Err(ProgramError::InvalidArgument);
+        }
+        last_price = price;
+
+        let fill = remaining.min(listing.amount_total);
+        let cost = price.checked_mul(fill).ok_or(ProgramError::InvalidArgument)?;
+        let transfer_ix = system_instruction::transfer(user.key, &listing.seller, cost);
+        invoke(&transfer_ix, &[user.clone(), seller_account.clone(), system_program_acc.clone()])?;
+
+        listing.amount_total -= fill;
+        listing.serialize(&mut &mut listing_pda.data.borrow_mut()[..])?;
+        remaining -= fill;
+        total_paid = total_paid.checked_add(cost).ok_or(ProgramError::InvalidArgument)?;
+        Event::Purchase {listing: *listing_pda.key, buyer: *user.key, seller: listing.seller, amount_points: fill,
+            price_per_point_lamports: price}.emit();
+        msg!("Filled {} points from {} at {}", fill, listing_pda.key, price);
+    }
+
+    let filled = max_points - remaining;
+    if filled == 0 {
+        msg!("No listing could be filled");
+        return Err(ProgramError::InsufficientFunds);
+    }
+    credit_user_account(program_id, user, user_pda, filled)?;
+    Event::MarketBuyFilled {buyer: *user.key, amount_points: filled, lamports: total_paid}.emit();
+    msg!("Market buy: {} points for {} lamports", filled, total_paid);
+    Ok(())
+}
+
+fn instruction_create_pool(program_id: &Pubkey, accounts: &[AccountInfo], fee_bps: u16) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let payer = next_account_info(account_info_iter)?; // signer, pays for the pool
+    let admin = next_account_info(account_info_iter)?; // signer
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA
+    let pool_pda = next_account_info(account_info_iter)?; // writable PoolAccount PDA
+    let pool_vault = next_account_info(account_info_iter)?; // writable pool vault PDA
+    let system_program_acc = next_account_info(account_info_iter)?;
+
+    let state = load_state(program_id, state_pda)?;
+    if !admin.is_signer || *admin.key != state.admin {
+        msg!("Admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if fee_bps > MAX_POOL_FEE_BPS {
+        msg!("Pool fee capped at {} bps", MAX_POOL_FEE_BPS);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let (expected_pool_pda,bump)=Pubkey::find_program_address(&[POOL_SEED], program_id);
+    if expected_pool_pda!=*pool_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !pool_pda.data_is_empty(){
+        msg!("Pool already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    let (expected_vault,_)=Pubkey::find_program_address(&[POOL_VAULT_SEED, pool_pda.key.as_ref()], program_id);
+    if expected_vault!=*pool_vault.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let rent=Rent::get()?;
+    let pool_account_size:usize=1+ 1+ 8+ 8+ 8+ 2;
+    let pool_min_bal_for_rent_exempt=rent.minimum_balance(pool_account_size);
+    let pool_pda_create_ix=system_instruction::create_account(payer.key,
+        pool_pda.key, pool_min_bal_for_rent_exempt, pool_account_size as u64, program_id);
+    invoke_signed(&pool_pda_create_ix,
+        &[payer.clone(), pool_pda.clone()],
+        &[&[POOL_SEED, &[bump]]])?;
+
+    // keep the vault rent-exempt on its own so the SOL reserve can be fully withdrawn
+    let vault_rent = rent.minimum_balance(0).saturating_sub(pool_vault.lamports());
+    if vault_rent > 0 {
+        let fund_vault_ix = system_instruction::transfer(payer.key, pool_vault.key, vault_rent);
+        invoke(&fund_vault_ix, &[payer.clone(), pool_vault.clone(), system_program_acc.clone()])?;
+    }
+
+    let pool = PoolAccount {accountType:12, is_initialized: true, amp_reserve: 0, sol_reserve: 0, total_shares: 0, fee_bps};
+    pool.serialize(&mut &mut pool_pda.data.borrow_mut()[..])?;
+    Event::PoolCreated {pool: *pool_pda.key, fee_bps}.emit();
+    msg!("Pool created with fee {} bps", fee_bps);
+    Ok(())
+}
+
+fn instruction_add_liquidity(program_id: &Pubkey, accounts: &[AccountInfo], amp_amount: u64, max_sol_lamports: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer
+    let driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA
+    let pool_pda = next_account_info(account_info_iter)?; // writable PoolAccount PDA
+    let pool_vault = next_account_info(account_info_iter)?; // writable pool vault PDA
+    let lp_pda = next_account_info(account_info_iter)?; // writable LpPositionAccount PDA
+    let system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::Marketplace)?;
+    if !user.is_signer {
+        msg!("Liquidity provider must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if amp_amount == 0 {
+        msg!("AMP amount must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mut pool = load_pool(program_id, pool_pda, pool_vault)?;
+    let mut drv_acc = load_owned_driver(program_id, driver_pda, user.key)?;
+    if drv_acc.amp_balance < amp_amount {
+        msg!("Insufficient points to add liquidity");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    let (sol_amount, shares) = pool_deposit_quote(&pool, amp_amount, max_sol_lamports)?;
+    if sol_amount == 0 || sol_amount > max_sol_lamports || shares == 0 {
+        msg!("Deposit needs {} lamports (max {}) for {} shares", sol_amount, max_sol_lamports, shares);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let deposit_ix = system_instruction::transfer(user.key, pool_vault.key, sol_amount);
+    invoke(&deposit_ix, &[user.clone(), pool_vault.clone(), system_program_acc.clone()])?;
+    drv_acc.amp_balance -= amp_amount;
+    drv_acc.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
+
+    let lp_seeds=&[LP_SEED, pool_pda.key.as_ref(), user.key.as_ref()];
+    let (expected_lp_pda,bump)=Pubkey::find_program_address(lp_seeds, program_id);
+    let lp_seeds_with_bump=&[LP_SEED, pool_pda.key.as_ref(), user.key.as_ref(), &[bump]];
+    if expected_lp_pda!=*lp_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut position = if lp_pda.data_is_empty(){
+        let rent=Rent::get()?;
+        let lp_account_size:usize=1+ 1+ 32+ 32+ 8;
+        let lp_min_bal_for_rent_exempt=rent.minimum_balance(lp_account_size);
+        let lp_pda_create_ix=system_instruction::create_account(user.key,
+            lp_pda.key, lp_min_bal_for_rent_exempt, lp_account_size as u64, program_id);
+        invoke_signed(&lp_pda_create_ix,
+            &[user.clone(), lp_pda.clone()],
+            &[lp_seeds_with_bump])?;
+        msg!("lp pda created!!");
+        LpPositionAccount {accountType:13, is_initialized: true, pool: *pool_pda.key, owner: *user.key, shares: 0}
+    }else{
+        LpPositionAccount::try_from_slice(&lp_pda.data.borrow())?
+    };
+    position.shares = position.shares.checked_add(shares).ok_or(ProgramError::InvalidArgument)?;
+    position.serialize(&mut &mut lp_pda.data.borrow_mut()[..])?;
+
+    pool.amp_reserve = pool.amp_reserve.checked_add(amp_amount).ok_or(ProgramError::InvalidArgument)?;
+    pool.sol_reserve = pool.sol_reserve.checked_add(sol_amount).ok_or(ProgramError::InvalidArgument)?;
+    let locked = if pool.total_shares == 0 { MINIMUM_LIQUIDITY } else { 0 };
+    pool.total_shares = pool.total_shares.checked_add(shares + locked).ok_or(ProgramError::InvalidArgument)?;
+    pool.serialize(&mut &mut pool_pda.data.borrow_mut()[..])?;
+    Event::LiquidityAdded {pool: *pool_pda.key, owner: *user.key, amp_amount, sol_lamports: sol_amount, shares}.emit();
+    msg!("Added {} AMP and {} lamports for {} shares", amp_amount, sol_amount, shares);
+    Ok(())
+}
+
+fn instruction_remove_liquidity(program_id: &Pubkey, accounts: &[AccountInfo], shares: u64, min_amp: u64, min_sol_lamports: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer, writable, receives SOL
+    let driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA
+    let pool_pda = next_account_info(account_info_iter)?; // writable PoolAccount PDA
+    let pool_vault = next_account_info(account_info_iter)?; // writable pool vault PDA
+    let lp_pda = next_account_info(account_info_iter)?; // writable LpPositionAccount PDA
+    let system_program_acc = next_account_info(account_info_iter)?;
+
+    if !user.is_signer {
+        msg!("Liquidity provider must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut pool = load_pool(program_id, pool_pda, pool_vault)?;
+    let mut drv_acc = load_owned_driver(program_id, driver_pda, user.key)?;
+    if lp_pda.owner != program_id {
+        msg!("LP account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut position = LpPositionAccount::try_from_slice(&lp_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if position.owner != *user.key || position.pool != *pool_pda.key {
+        msg!("LP position does not belong to signer");
+        return Err(ProgramError::IllegalOwner);
+    }
+    if shares == 0 || shares > position.shares {
+        msg!("Invalid share amount, position has {}", position.shares);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let (amp_out, sol_out) = pool_withdraw_quote(&pool, shares);
+    if amp_out < min_amp || sol_out < min_sol_lamports {
+        msg!("Withdrawal {} AMP / {} lamports below minimums", amp_out, sol_out);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    transfer_from_pool_vault(program_id, pool_pda, pool_vault, user, system_program_acc, sol_out)?;
+    drv_acc.amp_balance = drv_acc.amp_balance.checked_add(amp_out).ok_or(ProgramError::InvalidArgument)?;
+    drv_acc.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
+
+    position.shares -= shares;
+    position.serialize(&mut &mut lp_pda.data.borrow_mut()[..])?;
+    pool.amp_reserve -= amp_out;
+    pool.sol_reserve -= sol_out;
+    pool.total_shares -= shares;
+    pool.serialize(&mut &mut pool_pda.data.borrow_mut()[..])?;
+    Event::LiquidityRemoved {pool: *pool_pda.key, owner: *user.key, amp_amount: amp_out, sol_lamports: sol_out, shares}.emit();
+    msg!("Removed {} shares for {} AMP and {} lamports", shares, amp_out, sol_out);
+    Ok(())
+}
+
+fn instruction_swap(program_id: &Pubkey, accounts: &[AccountInfo], amount_in: u64, min_amount_out: u64, direction: SwapDirection) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer, writable
+    let driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA
+    let pool_pda = next_account_info(account_info_iter)?; // writable PoolAccount PDA
+    let pool_vault = next_account_info(account_info_iter)?; // writable pool vault PDA
+    let system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::Marketplace)?;
+    if !user.is_signer {
+        msg!("Trader must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if amount_in == 0 {
+        msg!("Swap amount must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mut pool = load_pool(program_id, pool_pda, pool_vault)?;
+    if pool.total_shares == 0 {
+        msg!("Pool has no liquidity");
+        return Err(ProgramError::InsufficientFunds);
+    }
+    create_driver_account_if_empty(program_id, user, driver_pda)?;
+    let mut drv_acc = load_owned_driver(program_id, driver_pda, user.key)?;
+
+    let amount_out = pool_swap_quote(&pool, amount_in, direction);
+    if amount_out == 0 || amount_out < min_amount_out {
+        msg!("Swap output {} below minimum {}", amount_out, min_amount_out);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    match direction {
+        SwapDirection::SolToAmp => {
+            let deposit_ix = system_instruction::transfer(user.key, pool_vault.key, amount_in);
+            invoke(&deposit_ix, &[user.clone(), pool_vault.clone(), system_program_acc.clone()])?;
+            drv_acc.amp_balance = drv_acc.amp_balance.checked_add(amount_out).ok_or(ProgramError::InvalidArgument)?;
+            pool.sol_reserve = pool.sol_reserve.checked_add(amount_in).ok_or(ProgramError::InvalidArgument)?;
+            pool.amp_reserve -= amount_out;
+        }
+        SwapDirection::AmpToSol => {
+            if drv_acc.amp_balance < amount_in {
+                msg!("Insufficient points to swap");
+                return Err(ProgramError::InsufficientFunds);
+            }
+            transfer_from_pool_vault(program_id, pool_pda, pool_vault, user, system_program_acc, amount_out)?;
+            drv_acc.amp_balance -= amount_in;
+            pool.amp_reserve = pool.amp_reserve.checked_add(amount_in).ok_or(ProgramError::InvalidArgument)?;
+            pool.sol_reserve -= amount_out;
+        }
+    }
+    drv_acc.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
+    pool.serialize(&mut &mut pool_pda.data.borrow_mut()[..])?;
+    Event::Swapped {pool: *pool_pda.key, trader: *user.key, direction, amount_in, amount_out}.emit();
+    msg!("Swapped {} in for {} out ({:?})", amount_in, amount_out, direction);
+    Ok(())
+}
+
+fn instruction_expire_listing(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let _cranker = next_account_info(account_info_iter)?; // anyone
+    let driver_pda = next_account_info(account_info_iter)?; // writable seller DriverAccount PDA
+    let listing_pda = next_account_info(account_info_iter)?; // writable listing PDA
+    let seller_account = next_account_info(account_info_iter)?; // writable seller wallet, receives the rent
+
+    if listing_pda.owner != program_id || driver_pda.owner != program_id {
+        msg!("Listing and driver accounts must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let listing = ListingAccount::try_from_slice(&listing_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if !is_listing_expired(&listing, Clock::get()?.unix_timestamp) {
+        msg!("Listing has not expired");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if *seller_account.key != listing.seller {
+        msg!("Seller account does not match listing");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let (expected_driver_pda,_)=Pubkey::find_program_address(&[DRIVER_SEED, listing.seller.as_ref()], program_id);
+    if expected_driver_pda!=*driver_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let remaining = listing.amount_total;
+    if remaining > 0 {
+        let mut drv = DriverAccount::try_from_slice(&driver_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+        drv.amp_balance = drv.amp_balance.checked_add(remaining).ok_or(ProgramError::InvalidArgument)?;
+        drv.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
+    }
+    close_program_account(listing_pda, seller_account)?;
+    Event::ListingExpired {listing: *listing_pda.key, seller: listing.seller, returned_points: remaining}.emit();
+    msg!("Listing expired, returned {} points", remaining);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn instruction_create_auction_listing(program_id: &Pubkey, accounts: &[AccountInfo], amount_points: u64,
+    start_price: u64, floor_price: u64, decay_secs: i64, expires_at: Option<i64>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer
+    let driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA
+    let listing_pda = next_account_info(account_info_iter)?; // writable listing PDA
+    let _system_program_acc = next_account_info(account_info_iter)?;
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::Marketplace)?;
+    if !user.is_signer {
+        msg!("Seller must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if amount_points == 0 || floor_price > start_price || decay_secs <= 0 {
+        msg!("Auction needs points, floor_price <= start_price and a positive decay");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+        msg!("Listing expiry must be in the future");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut drv_acc = load_owned_driver(program_id, driver_pda, user.key)?;
+    if drv_acc.amp_balance < amount_points {
+        msg!("Insufficient points to create listing");
+        return Err(ProgramError::InsufficientFunds);
+    }
+    drv_acc.amp_balance -= amount_points;
+    drv_acc.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
+
+    let listing_seeds=&[LISTING_SEED, user.key.as_ref()];
+    let (expected_listing_pda_account,bump)=Pubkey::find_program_address(listing_seeds, program_id);
+    let listing_seeds_with_bump=&[LISTING_SEED, user.key.as_ref(), &[bump]];
+    if expected_listing_pda_account!=*listing_pda.key{
+        return  Err(ProgramError::InvalidSeeds);
+    }
+    if listing_pda.data_is_empty(){
+        let rent=Rent::get()?;
+        let listing_min_bal_for_rent_exempt=rent.minimum_balance(LISTING_ACCOUNT_SIZE);
+        let listing_pda_create_ix=system_instruction::create_account(user.key,
+            listing_pda.key, listing_min_bal_for_rent_exempt, LISTING_ACCOUNT_SIZE as u64, program_id);
+        invoke_signed(&listing_pda_create_ix,
+            &[user.clone(), listing_pda.clone()],
+            &[listing_seeds_with_bump])?;
+        msg!("listing pda created!!");
+    }else{
+        let existing=ListingAccount::try_from_slice(&listing_pda.data.borrow())?;
+        if existing.amount_total > 0 {
+            msg!("Seller already has an active listing, cancel it first");
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+    }
+
+    let listing = ListingAccount {
+        accountType:4,
+        is_initialized: true,
+        seller: *user.key,
+        amount_total: amount_points,
+        price_per_point_lamports: start_price,
+        expires_at: expires_at.unwrap_or(0),
+        kind: ListingKind::DutchAuction,
+        auction_start_price: start_price,
+        auction_floor_price: floor_price,
+        auction_start_ts: now,
+        auction_decay_secs: decay_secs,
+    };
+    listing.serialize(&mut &mut listing_pda.data.borrow_mut()[..])?;
+    Event::AuctionListingCreated {listing: *listing_pda.key, seller: *user.key, amount_points, start_price, floor_price,
+        start_ts: now, decay_secs, expires_at: listing.expires_at}.emit();
+    msg!("Auction created: {} points from {} down to {} lamports over {} secs", amount_points, start_price, floor_price, decay_secs);
+    Ok(())
+}
+
+fn instruction_register_partner(program_id: &Pubkey, accounts: &[AccountInfo], partner_program: Pubkey, budget_per_period: u64,
+    period_secs: i64, active: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let payer = next_account_info(account_info_iter)?; // signer, pays for the partner account
+    let admin = next_account_info(account_info_iter)?; // signer
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA
+    let partner_pda = next_account_info(account_info_iter)?; // writable PartnerAccount PDA
+    let _system_program_acc = next_account_info(account_info_iter)?;
+
+    let state = load_state(program_id, state_pda)?;
+    if !admin.is_signer || *admin.key != state.admin {
+        msg!("Admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if period_secs <= 0 {
+        msg!("period_secs must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let partner_seeds=&[PARTNER_SEED, partner_program.as_ref()];
+    let (expected_partner_pda,bump)=Pubkey::find_program_address(partner_seeds, program_id);
+    let partner_seeds_with_bump=&[PARTNER_SEED, partner_program.as_ref(), &[bump]];
+    if expected_partner_pda!=*partner_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut partner = if partner_pda.data_is_empty(){
+        let rent=Rent::get()?;
+        let partner_account_size:usize=1+ 1+ 32+ 1+ 8+ 8+ 8+ 8;
+        let partner_min_bal_for_rent_exempt=rent.minimum_balance(partner_account_size);
+        let partner_pda_create_ix=system_instruction::create_account(payer.key,
+            partner_pda.key, partner_min_bal_for_rent_exempt, partner_account_size as u64, program_id);
+        invoke_signed(&partner_pda_create_ix,
+            &[payer.clone(), partner_pda.clone()],
+            &[partner_seeds_with_bump])?;
+        msg!("partner pda created!!");
+        PartnerAccount {accountType:14, is_initialized: true, program: partner_program, active, budget_per_period,
+            period_secs, period_start: now, awarded_in_period: 0,
+        }
+    }else{
+        PartnerAccount::try_from_slice(&partner_pda.data.borrow())?
+    };
+    partner.active = active;
+    partner.budget_per_period = budget_per_period;
+    partner.period_secs = period_secs;
+    partner.serialize(&mut &mut partner_pda.data.borrow_mut()[..])?;
+    Event::PartnerRegistered {partner_program, budget_per_period, period_secs, active}.emit();
+    msg!("Partner {} active: {}, budget {} points per {} secs", partner_program, active, budget_per_period, period_secs);
+    Ok(())
+}
+
+fn instruction_award_points(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, reason: String) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let partner_authority = next_account_info(account_info_iter)?; // signer, PARTNER_AUTHORITY_SEED PDA of the partner program
+    let partner_pda = next_account_info(account_info_iter)?; // writable PartnerAccount PDA
+    let driver_pda = next_account_info(account_info_iter)?; // writable DriverAccount PDA of the rewarded driver
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::All)?;
+    if amount == 0 || reason.len() > MAX_AWARD_REASON_LEN {
+        msg!("Award needs a positive amount and a reason of at most {} bytes", MAX_AWARD_REASON_LEN);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if partner_pda.owner != program_id || driver_pda.owner != program_id {
+        msg!("Partner and driver accounts must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut partner = PartnerAccount::try_from_slice(&partner_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    let (expected_partner_pda,_)=Pubkey::find_program_address(&[PARTNER_SEED, partner.program.as_ref()], program_id);
+    if expected_partner_pda!=*partner_pda.key || !partner.is_initialized {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !partner.active {
+        msg!("Partner {} is not active", partner.program);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // only the partner program itself can produce this signature, via invoke_signed
+    let (expected_authority,_)=Pubkey::find_program_address(&[PARTNER_AUTHORITY_SEED], &partner.program);
+    if !partner_authority.is_signer || expected_authority != *partner_authority.key {
+        msg!("Partner program authority must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // roll the budget window forward, keeping periods aligned to the first one
+    let now = Clock::get()?.unix_timestamp;
+    if now >= partner.period_start + partner.period_secs {
+        partner.period_start = now - (now - partner.period_start) % partner.period_secs;
+        partner.awarded_in_period = 0;
+    }
+    let awarded = partner.awarded_in_period.checked_add(amount).ok_or(ProgramError::InvalidArgument)?;
+    if awarded > partner.budget_per_period {
+        msg!("Partner budget exceeded, {} of {} points left this period",
+            partner.budget_per_period.saturating_sub(partner.awarded_in_period), partner.budget_per_period);
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    let mut drv_acc = DriverAccount::try_from_slice(&driver_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if !drv_acc.is_initialized || drv_acc.accountType != 2 {
+        msg!("Driver account not init");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    drv_acc.amp_balance = drv_acc.amp_balance.checked_add(amount).ok_or(ProgramError::InvalidArgument)?;
+    drv_acc.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
+    partner.awarded_in_period = awarded;
+    partner.serialize(&mut &mut partner_pda.data.borrow_mut()[..])?;
+    Event::PointsAwarded {partner_program: partner.program, driver: drv_acc.owner, amount, reason: reason.clone()}.emit();
+    msg!("Partner {} awarded {} points to {}: {}", partner.program, amount, drv_acc.owner, reason);
+    Ok(())
+}
+
+fn instruction_set_pause(program_id: &Pubkey, accounts: &[AccountInfo], scope: PauseScope, paused: bool) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin = next_account_info(account_info_iter)?; // signer
+    let state_pda = next_account_info(account_info_iter)?; // writable StateAccount PDA
+
+    let mut state = load_state(program_id, state_pda)?;
+    if !admin.is_signer || *admin.key != state.admin {
+        msg!("Admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    match scope {
+        PauseScope::All => state.paused = paused,
+        PauseScope::Registration => state.registration_paused = paused,
+        PauseScope::Sessions => state.sessions_paused = paused,
+        PauseScope::Marketplace => state.marketplace_paused = paused,
+    }
+    state.serialize(&mut &mut state_pda.data.borrow_mut()[..])?;
+    Event::PauseSet {scope, paused}.emit();
+    msg!("Pause {:?}: {}", scope, paused);
+    Ok(())
+}
+
+fn instruction_propose_admin(program_id: &Pubkey, accounts: &[AccountInfo], new_admin: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin = next_account_info(account_info_iter)?; // signer
+    let state_pda = next_account_info(account_info_iter)?; // writable StateAccount PDA
+
+    let mut state = load_state(program_id, state_pda)?;
+    if !admin.is_signer || *admin.key != state.admin {
+        msg!("Admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    state.pending_admin = new_admin;
+    state.serialize(&mut &mut state_pda.data.borrow_mut()[..])?;
+    Event::AdminProposed {admin: state.admin, pending_admin: new_admin}.emit();
+    msg!("Admin transfer to {} proposed", new_admin);
+    Ok(())
+}
+
+fn instruction_accept_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let new_admin = next_account_info(account_info_iter)?; // signer, the pending admin
+    let state_pda = next_account_info(account_info_iter)?; // writable StateAccount PDA
+
+    let mut state = load_state(program_id, state_pda)?;
+    if state.pending_admin == Pubkey::default() {
+        msg!("No admin transfer in progress");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if !new_admin.is_signer || *new_admin.key != state.pending_admin {
+        msg!("Pending admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let old_admin = state.admin;
+    state.admin = state.pending_admin;
+    state.pending_admin = Pubkey::default();
+    state.serialize(&mut &mut state_pda.data.borrow_mut()[..])?;
+    Event::AdminChanged {old_admin, new_admin: state.admin}.emit();
+    msg!("Admin changed from {} to {}", old_admin, state.admin);
+    Ok(())
+}
+
+fn instruction_set_registration_fee(program_id: &Pubkey, accounts: &[AccountInfo], fee: u64, fee_mint: Option<Pubkey>) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin = next_account_info(account_info_iter)?; // signer
+    let state_pda = next_account_info(account_info_iter)?; // writable StateAccount PDA
+
+    let mut state = load_state(program_id, state_pda)?;
+    if !admin.is_signer || *admin.key != state.admin {
+        msg!("Admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    state.registration_fee = fee;
+    state.fee_mint = fee_mint.unwrap_or_default();
+    state.serialize(&mut &mut state_pda.data.borrow_mut()[..])?;
+    Event::RegistrationFeeSet {fee, fee_mint: state.fee_mint}.emit();
+    msg!("Registration fee set to {} (mint {})", fee, state.fee_mint);
+    Ok(())
+}
+
+fn instruction_set_fee_waiver(program_id: &Pubkey, accounts: &[AccountInfo], operator: Pubkey, waived: bool) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let payer = next_account_info(account_info_iter)?; // signer, pays for the waiver account
+    let admin = next_account_info(account_info_iter)?; // signer
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA
+    let waiver_pda = next_account_info(account_info_iter)?; // writable FeeWaiverAccount PDA
+    let _system_program_acc = next_account_info(account_info_iter)?;
+
+    let state = load_state(program_id, state_pda)?;
+    if !admin.is_signer || *admin.key != state.admin {
+        msg!("Admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let waiver_seeds=&[FEE_WAIVER_SEED, operator.as_ref()];
+    let (expected_waiver_pda,bump)=Pubkey::find_program_address(waiver_seeds, program_id);
+    let waiver_seeds_with_bump=&[FEE_WAIVER_SEED, operator.as_ref(), &[bump]];
+    if expected_waiver_pda!=*waiver_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if waiver_pda.data_is_empty(){
+        let rent=Rent::get()?;
+        let waiver_account_size:usize=1+ 1+ 32+ 1;
+        let waiver_min_bal_for_rent_exempt=rent.minimum_balance(waiver_account_size);
+        let waiver_pda_create_ix=system_instruction::create_account(payer.key,
+            waiver_pda.key, waiver_min_bal_for_rent_exempt, waiver_account_size as u64, program_id);
+        invoke_signed(&waiver_pda_create_ix,
+            &[payer.clone(), waiver_pda.clone()],
+            &[waiver_seeds_with_bump])?;
+        msg!("fee waiver pda created!!");
+    }
+    let waiver = FeeWaiverAccount {accountType:15, is_initialized: true, operator, waived};
+    waiver.serialize(&mut &mut waiver_pda.data.borrow_mut()[..])?;
+    Event::FeeWaiverSet {operator, waived}.emit();
+    msg!("Registration fee waiver for {}: {}", operator, waived);
+    Ok(())
+}
+
+fn instruction_set_auditor(program_id: &Pubkey, accounts: &[AccountInfo], auditor: Pubkey, active: bool) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let payer = next_account_info(account_info_iter)?; // signer, pays for the auditor account
+    let admin = next_account_info(account_info_iter)?; // signer
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA
+    let auditor_pda = next_account_info(account_info_iter)?; // writable AuditorAccount PDA
+    let _system_program_acc = next_account_info(account_info_iter)?;
+
+    let state = load_state(program_id, state_pda)?;
+    if !admin.is_signer || *admin.key != state.admin {
+        msg!("Admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let auditor_seeds=&[AUDITOR_SEED, auditor.as_ref()];
+    let (expected_auditor_pda,bump)=Pubkey::find_program_address(auditor_seeds, program_id);
+    let auditor_seeds_with_bump=&[AUDITOR_SEED, auditor.as_ref(), &[bump]];
+    if expected_auditor_pda!=*auditor_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if auditor_pda.data_is_empty(){
+        let rent=Rent::get()?;
+        let auditor_account_size:usize=1+ 1+ 32+ 1;
+        let auditor_min_bal_for_rent_exempt=rent.minimum_balance(auditor_account_size);
+        let auditor_pda_create_ix=system_instruction::create_account(payer.key,
+            auditor_pda.key, auditor_min_bal_for_rent_exempt, auditor_account_size as u64, program_id);
+        invoke_signed(&auditor_pda_create_ix,
+            &[payer.clone(), auditor_pda.clone()],
+            &[auditor_seeds_with_bump])?;
+        msg!("auditor pda created!!");
+    }
+    let auditor_acc = AuditorAccount {accountType:16, is_initialized: true, auditor, active};
+    auditor_acc.serialize(&mut &mut auditor_pda.data.borrow_mut()[..])?;
+    Event::AuditorSet {auditor, active}.emit();
+    msg!("Auditor {} active: {}", auditor, active);
+    Ok(())
+}
+
+fn instruction_verify_charger(program_id: &Pubkey, accounts: &[AccountInfo], verified: bool) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let auditor = next_account_info(account_info_iter)?; // signer
+    let auditor_pda = next_account_info(account_info_iter)?; // readonly AuditorAccount PDA
+    let charger_pda = next_account_info(account_info_iter)?; // writable ChargerAccount
+    let state_pda = next_account_info(account_info_iter)?; // readonly StateAccount PDA, pause flags
+
+    check_not_paused(program_id, state_pda, PauseScope::Registration)?;
+    if !auditor.is_signer {
+        msg!("Auditor must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (expected_auditor_pda,_)=Pubkey::find_program_address(&[AUDITOR_SEED, auditor.key.as_ref()], program_id);
+    if expected_auditor_pda!=*auditor_pda.key || auditor_pda.owner != program_id {
+        msg!("Signer is not a registered auditor");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let auditor_acc = AuditorAccount::try_from_slice(&auditor_pda.data.borrow())?;
+    if !auditor_acc.is_initialized || !auditor_acc.active {
+        msg!("Auditor {} is not active", auditor.key);
+        return Err(ProgramError::IllegalOwner);
+    }
+    if charger_pda.owner != program_id {
+        msg!("Charger account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mut charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    if !charger.is_initialized {
+        msg!("Charger uninitialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if charger.authority == *auditor.key {
+        msg!("Auditors cannot verify their own chargers");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    charger.verified = verified;
+    charger.verified_at = now;
+    charger.verified_by = *auditor.key;
+    charger.serialize(&mut &mut charger_pda.data.borrow_mut()[..])?;
+    Event::ChargerVerified {charger: *charger_pda.key, auditor: *auditor.key, verified, at: now}.emit();
+    msg!("Charger {} verified: {} by {}", charger_pda.key, verified, auditor.key);
+    Ok(())
+}
+
+fn instruction_set_emission_limits(program_id: &Pubkey, accounts: &[AccountInfo], max_rate_points_per_sec: u64, epoch_secs: i64,
+    epoch_budget: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin = next_account_info(account_info_iter)?; // signer
+    let state_pda = next_account_info(account_info_iter)?; // writable StateAccount PDA
+
+    let mut state = load_state(program_id, state_pda)?;
+    if !admin.is_signer || *admin.key != state.admin {
+        msg!("Admin must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if epoch_secs <= 0 {
+        msg!("epoch_secs must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+    // a new epoch length starts a fresh epoch, the issued count carries over otherwise
+    if epoch_secs != state.epoch_secs {
+        state.epoch_start = Clock::get()?.unix_timestamp;
+        state.epoch_issued = 0;
+    }
+    state.max_rate_points_per_sec = max_rate_points_per_sec;
+    state.epoch_secs = epoch_secs;
+    state.epoch_budget = epoch_budget;
+    state.serialize(&mut &mut state_pda.data.borrow_mut()[..])?;
+    Event::EmissionLimitsSet {max_rate_points_per_sec, epoch_secs, epoch_budget}.emit();
+    msg!("Emission limits: max rate {}, {} points per {} secs", max_rate_points_per_sec, epoch_budget, epoch_secs);
+    Ok(())
+}
+
+fn instruction_refund_session(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account_info(account_info_iter)?; // signer, writable driver wallet, receives escrow and rent
+    let session_pda = next_account_info(account_info_iter)?; // writable SessionAccount PDA
+    let driver_pda = next_account_info(account_info_iter)?; // readonly DriverAccount PDA
+    let charger_pda = next_account_info(account_info_iter)?; // writable ChargerAccount of the session
+
+    if !user.is_signer {
+        msg!("Driver must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if session_pda.owner != program_id {
+        msg!("Session account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    load_owned_driver(program_id, driver_pda, user.key)?;
+    let session = SessionAccount::try_from_slice(&session_pda.data.borrow())?;
+    if !session.is_initialized || session.driver != *driver_pda.key {
+        msg!("Session does not belong to this driver");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if session.settled {
+        msg!("Session already settled");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // the device reported delivering energy, that gets billed through StopSession instead
+    if session.charging_finished_ts != 0 {
+        msg!("Charging completed at {}, the session has to be stopped", session.charging_finished_ts);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let refundable_at = session.start_ts.saturating_add(SESSION_REFUND_AFTER_SECS);
+    if Clock::get()?.unix_timestamp < refundable_at {
+        msg!("Session can be refunded from {}", refundable_at);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // a charger cannot close with open sessions, so it is still there
+    if session.charger != *charger_pda.key || charger_pda.owner != program_id {
+        msg!("Charger account does not match session");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let mut charger = ChargerAccount::try_from_slice(&charger_pda.data.borrow())?;
+    charger.open_sessions = charger.open_sessions.saturating_sub(1);
+    charger.serialize(&mut &mut charger_pda.data.borrow_mut()[..])?;
+
+    let refunded = session_pda.lamports();
+    close_program_account(session_pda, user)?;
+    Event::SessionRefunded {session: *session_pda.key, charger: session.charger, driver: *user.key,
+        escrow_lamports: session.escrow_lamports}.emit();
+    msg!("Session refunded, {} lamports back to the driver", refunded);
+    Ok(())
+}
+
+fn instruction_expire_reservation(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let _cranker = next_account_info(account_info_iter)?; // anyone
+    let reservation_pda = next_account_info(account_info_iter)?; // writable ReservationAccount PDA
+    let reserver_account = next_account_info(account_info_iter)?; // writable reserving wallet, receives the rent
+
+    if reservation_pda.owner != program_id {
+        msg!("Reservation account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let reservation = ReservationAccount::try_from_slice(&reservation_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    let (expected_reservation_pda, _) = Pubkey::find_program_address(&[RESERVATION_SEED, reservation.charger.as_ref()], program_id);
+    if expected_reservation_pda != *reservation_pda.key {
+        msg!("Reservation account is not the charger's reservation PDA");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if Clock::get()?.unix_timestamp < reservation.end_ts {
+        msg!("Reservation runs until {}", reservation.end_ts);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if *reserver_account.key != reservation.driver {
+        msg!("Reserver account does not match reservation");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    close_program_account(reservation_pda, reserver_account)?;
+    Event::ReservationClosed {charger: reservation.charger, driver: reservation.driver, claimed: false}.emit();
+    msg!("Reservation expired");
+    Ok(())
+}
+
+// ---------- Helpers ----------
+
+/// Books points against the global epoch budget, returning how many may actually be awarded
+fn take_from_epoch_budget(state: &mut StateAccount, points: u64, now: i64) -> u64 {
+    if now >= state.epoch_start + state.epoch_secs {
+        state.epoch_start = now - (now - state.epoch_start) % state.epoch_secs;
+        state.epoch_issued = 0;
+    }
+    let awarded = points.min(state.epoch_budget.saturating_sub(state.epoch_issued));
+    if awarded < points {
+        msg!("Epoch budget left {} points, award cut from {}", awarded, points);
+    }
+    state.epoch_issued += awarded;
+    awarded
+}
+
+/// Circuit breaker, pausable handlers read the state PDA in their account list and call this first.
+/// Admin instructions and exits (cancels, withdrawals, unstaking, StopSession, refunds) never check it.
+fn check_not_paused(program_id: &Pubkey, state_pda: &AccountInfo, scope: PauseScope) -> ProgramResult {
+    let state = load_state(program_id, state_pda)?;
+    let paused = state.paused || match scope {
+        PauseScope::All => false,
+        PauseScope::Registration => state.registration_paused,
+        PauseScope::Sessions => state.sessions_paused,
+        PauseScope::Marketplace => state.marketplace_paused,
+    };
+    if paused {
+        msg!("{:?} is paused", scope);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+/// Current price per point; Dutch auctions decay linearly to their floor
+fn listing_price(listing: &ListingAccount, now: i64) -> u64 {
+    match listing.kind {
+        ListingKind::FixedPrice => listing.price_per_point_lamports,
+        ListingKind::DutchAuction => {
+            let decay_secs = listing.auction_decay_secs.max(1) as u128;
+            let elapsed = (now - listing.auction_start_ts).clamp(0, listing.auction_decay_secs.max(1)) as u128;
+            let drop = (listing.auction_start_price - listing.auction_floor_price) as u128 * elapsed / decay_secs;
+            listing.auction_start_price - drop as u64
+        }
+    }
+}
+
+fn is_listing_expired(listing: &ListingAccount, now: i64) -> bool {
+    listing.expires_at != 0 && now >= listing.expires_at
+}
+
+/// Credits purchased points to the buyer's UserAccount PDA, creating it on first purchase
+fn credit_user_account<'a>(program_id: &Pubkey, user: &AccountInfo<'a>, user_pda: &AccountInfo<'a>, points: u64) -> ProgramResult {
+    let user_seeds=&[USER_SEED, user.key.as_ref()];
+    let (expected_user_pda_account,bump)=Pubkey::find_program_address(user_seeds, program_id);
+    let user_seeds_with_bump=&[USER_SEED, user.key.as_ref(), &[bump]];
+    if expected_user_pda_account!=*user_pda.key{
+        return  Err(ProgramError::InvalidSeeds);
+    }
+    if user_pda.data_is_empty(){
+        let rent=Rent::get()?;
+        let user_account_size:usize=1+ 8;
+        let user_min_bal_for_rent_exempt=rent.minimum_balance(user_account_size);
+        let user_pda_create_ix=system_instruction::create_account(user.key,
+            user_pda.key, user_min_bal_for_rent_exempt, user_account_size as u64, program_id);
+        invoke_signed(&user_pda_create_ix,
+            &[user.clone(), user_pda.clone()],
+            &[user_seeds_with_bump])?;
+        msg!("user pda created!!");
+    }
+    let mut buyer_user = UserAccount::try_from_slice(&user_pda.data.borrow())?;
+    buyer_user.amp_balance = buyer_user.amp_balance.checked_add(points).ok_or(ProgramError::InvalidArgument)?;
+    buyer_user.accountType=5;
+    buyer_user.serialize(&mut &mut user_pda.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+/// Creates the DriverAccount PDA of `user` (paid by `user`) if it does not exist yet
+fn create_driver_account_if_empty<'a>(program_id: &Pubkey, user: &AccountInfo<'a>, driver_pda: &AccountInfo<'a>) -> ProgramResult {
+    let driver_seeds=&[DRIVER_SEED, user.key.as_ref()];
+    let (expected_driver_pda,bump)=Pubkey::find_program_address(driver_seeds, program_id);
+    let driver_seeds_with_bump=&[DRIVER_SEED, user.key.as_ref(),&[bump]];
+    if expected_driver_pda!=*driver_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !driver_pda.data_is_empty(){
+        return Ok(());
+    }
+
+    let rent=Rent::get()?;
+    let driver_pda_account_size:usize=1+ 1+ 32+ 8;
+    let driver_pda_rent_exempt_bal=rent.minimum_balance(driver_pda_account_size);
+    let driver_pda_create_ix=system_instruction::create_account(user.key,
+        driver_pda.key, driver_pda_rent_exempt_bal, driver_pda_account_size as u64, program_id);
+    invoke_signed(&driver_pda_create_ix,
+        &[user.clone(), driver_pda.clone()],
+        &[driver_seeds_with_bump])?;
+    msg!("driver pda created");
+
+    let driver_data=DriverAccount{accountType:2, owner:*user.key, is_initialized:true, amp_balance:0};
+    driver_data.serialize(&mut &mut driver_pda.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+/// The waiver PDA is always passed; an empty account means no waiver
+fn is_fee_waived(program_id: &Pubkey, waiver_pda: &AccountInfo, operator: &Pubkey) -> Result<bool, ProgramError> {
+    let (expected_waiver_pda,_)=Pubkey::find_program_address(&[FEE_WAIVER_SEED, operator.as_ref()], program_id);
+    if expected_waiver_pda!=*waiver_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if waiver_pda.data_is_empty() || waiver_pda.owner != program_id {
+        return Ok(false);
+    }
+    let waiver = FeeWaiverAccount::try_from_slice(&waiver_pda.data.borrow())?;
+    Ok(waiver.is_initialized && waiver.waived)
+}
+
+/// Registration fee in the state's SPL mint, via a hand-built Token Transfer CPI.
+/// The token program checks both accounts share a mint, we check it is the fee mint and the admin's.
+fn transfer_registration_fee_spl<'a>(state: &StateAccount, payer: &AccountInfo<'a>, payer_token_account: &AccountInfo<'a>,
+    admin_token_account: &AccountInfo<'a>, token_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    if *token_program.key != TOKEN_PROGRAM_ID || admin_token_account.owner != &TOKEN_PROGRAM_ID {
+        msg!("SPL Token program and token accounts expected");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    {
+        // token account layout: mint (32) then owner (32)
+        let data = admin_token_account.data.borrow();
+        if data.len() < 64 || data[..32] != state.fee_mint.to_bytes() || data[32..64] != state.admin.to_bytes() {
+            msg!("Fee must go to the admin's token account for mint {}", state.fee_mint);
+            return Err(ProgramError::InvalidAccountData);
+        }
+    }
+    let mut data = Vec::with_capacity(9);
+    data.push(TOKEN_TRANSFER_IX);
+    data.extend_from_slice(&state.registration_fee.to_le_bytes());
+    let transfer_ix = solana_program::instruction::Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![
+            solana_program::instruction::AccountMeta::new(*payer_token_account.key, false),
+            solana_program::instruction::AccountMeta::new(*admin_token_account.key, false),
+            solana_program::instruction::AccountMeta::new_readonly(*payer.key, true),
+        ],
+        data,
+    };
+    invoke(&transfer_ix, &[payer_token_account.clone(), admin_token_account.clone(), payer.clone(), token_program.clone()])
+}
+
+/// Loads the global state PDA, checking its address and that it was initialized
+fn load_state(program_id: &Pubkey, state_pda: &AccountInfo) -> Result<StateAccount, ProgramError> {
+    let (expected_state_pda,_)=Pubkey::find_program_address(&[STATE_SEED], program_id);
+    if expected_state_pda!=*state_pda.key || state_pda.owner != program_id {
+        msg!("Invalid global state account");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let state = StateAccount::try_from_slice(&state_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if !state.is_initialized {
+        msg!("Global state not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(state)
+}
+
+/// Moves lamports out of a charger's system-owned vault PDA
+fn transfer_from_charger_vault<'a>(program_id: &Pubkey, charger_pda: &AccountInfo<'a>, charger_vault: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>, system_program_acc: &AccountInfo<'a>, lamports: u64,
+) -> ProgramResult {
+    let (expected_vault,bump)=Pubkey::find_program_address(&[CHARGER_VAULT_SEED, charger_pda.key.as_ref()], program_id);
+    if expected_vault!=*charger_vault.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let transfer_ix = system_instruction::transfer(charger_vault.key, destination.key, lamports);
+    invoke_signed(&transfer_ix,
+        &[charger_vault.clone(), destination.clone(), system_program_acc.clone()],
+        &[&[CHARGER_VAULT_SEED, charger_pda.key.as_ref(), &[bump]]])
+}
+
+fn load_fleet(program_id: &Pubkey, fleet_pda: &AccountInfo) -> Result<FleetAccount, ProgramError> {
+    if fleet_pda.owner != program_id {
+        msg!("Fleet account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let fleet = FleetAccount::try_from_slice(&fleet_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if !fleet.is_initialized || fleet.accountType != 9 {
+        msg!("Fleet not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(fleet)
+}
+
+/// Loads a fleet and the driver's membership in it, active or not
+fn load_fleet_member(program_id: &Pubkey, fleet_pda: &AccountInfo, fleet_member_pda: &AccountInfo, driver: &Pubkey)
+    -> Result<(FleetAccount, FleetMemberAccount), ProgramError> {
+    let fleet = load_fleet(program_id, fleet_pda)?;
+    let (expected_member_pda,_)=Pubkey::find_program_address(&[FLEET_MEMBER_SEED, fleet_pda.key.as_ref(), driver.as_ref()], program_id);
+    if expected_member_pda!=*fleet_member_pda.key || fleet_member_pda.owner != program_id {
+        msg!("Fleet member account mismatch");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let member = FleetMemberAccount::try_from_slice(&fleet_member_pda.data.borrow())?;
+    Ok((fleet, member))
+}
+
+/// Moves lamports out of a fleet's system-owned vault PDA
+fn transfer_from_fleet_vault<'a>(program_id: &Pubkey, fleet_pda: &AccountInfo<'a>, fleet_vault: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>, system_program_acc: &AccountInfo<'a>, lamports: u64,
+) -> ProgramResult {
+    let (expected_vault,bump)=Pubkey::find_program_address(&[FLEET_VAULT_SEED, fleet_pda.key.as_ref()], program_id);
+    if expected_vault!=*fleet_vault.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let transfer_ix = system_instruction::transfer(fleet_vault.key, destination.key, lamports);
+    invoke_signed(&transfer_ix,
+        &[fleet_vault.clone(), destination.clone(), system_program_acc.clone()],
+        &[&[FLEET_VAULT_SEED, fleet_pda.key.as_ref(), &[bump]]])
+}
+
+fn load_owned_driver(program_id: &Pubkey, driver_pda: &AccountInfo, owner: &Pubkey) -> Result<DriverAccount, ProgramError> {
+    if driver_pda.owner != program_id {
+        msg!("Driver account must be owned by the program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let drv_acc = DriverAccount::try_from_slice(&driver_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)?;
+    if drv_acc.owner != *owner {
+        msg!("Signer does not own driver account");
+        return Err(ProgramError::IllegalOwner);
+    }
+    Ok(drv_acc)
+}
+
+fn load_pool(program_id: &Pubkey, pool_pda: &AccountInfo, pool_vault: &AccountInfo) -> Result<PoolAccount, ProgramError> {
+    let (expected_pool_pda,_)=Pubkey::find_program_address(&[POOL_SEED], program_id);
+    if expected_pool_pda!=*pool_pda.key || pool_pda.owner != program_id {
+        msg!("Invalid pool account");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let (expected_vault,_)=Pubkey::find_program_address(&[POOL_VAULT_SEED, pool_pda.key.as_ref()], program_id);
+    if expected_vault!=*pool_vault.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    PoolAccount::try_from_slice(&pool_pda.data.borrow()).map_err(|_| ProgramError::UninitializedAccount)
+}
+
+/// Moves lamports out of the pool's system-owned vault PDA
+fn transfer_from_pool_vault<'a>(program_id: &Pubkey, pool_pda: &AccountInfo<'a>, pool_vault: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>, system_program_acc: &AccountInfo<'a>, lamports: u64,
+) -> ProgramResult {
+    let (_,bump)=Pubkey::find_program_address(&[POOL_VAULT_SEED, pool_pda.key.as_ref()], program_id);
+    let transfer_ix = system_instruction::transfer(pool_vault.key, destination.key, lamports);
+    invoke_signed(&transfer_ix,
+        &[pool_vault.clone(), destination.clone(), system_program_acc.clone()],
+        &[&[POOL_VAULT_SEED, pool_pda.key.as_ref(), &[bump]]])
+}
+
+/// (lamports, shares minted to the depositor) for depositing amp_amount; the first deposit sets the
+/// price and MINIMUM_LIQUIDITY of its shares are locked, later ones must match the reserve ratio
+fn pool_deposit_quote(pool: &PoolAccount, amp_amount: u64, max_sol_lamports: u64) -> Result<(u64, u64), ProgramError> {
+    let (sol, shares) = if pool.total_shares == 0 {
+        let minted = isqrt(amp_amount as u128 * max_sol_lamports as u128);
+        (max_sol_lamports as u128, minted.saturating_sub(MINIMUM_LIQUIDITY as u128))
+    } else {
+        let sol = (amp_amount as u128 * pool.sol_reserve as u128).div_ceil(pool.amp_reserve as u128);
+        (sol, amp_amount as u128 * pool.total_shares as u128 / pool.amp_reserve as u128)
+    };
+    Ok((
+        u64::try_from(sol).map_err(|_| ProgramError::InvalidArgument)?,
+        u64::try_from(shares).map_err(|_| ProgramError::InvalidArgument)?,
+    ))
+}
+
+/// (AMP, lamports) returned for burning shares, pro rata to the reserves
+fn pool_withdraw_quote(pool: &PoolAccount, shares: u64) -> (u64, u64) {
+    let amp_out = shares as u128 * pool.amp_reserve as u128 / pool.total_shares as u128;
+    let sol_out = shares as u128 * pool.sol_reserve as u128 / pool.total_shares as u128;
+    (amp_out as u64, sol_out as u64)
+}
+
+/// Constant product output for amount_in after the pool fee
+fn pool_swap_quote(pool: &PoolAccount, amount_in: u64, direction: SwapDirection) -> u64 {
+    let (reserve_in, reserve_out) = match direction {
+        SwapDirection::SolToAmp => (pool.sol_reserve, pool.amp_reserve),
+        SwapDirection::AmpToSol => (pool.amp_reserve, pool.sol_reserve),
+    };
+    let amount_in_after_fee = amount_in as u128 * (BPS_DENOMINATOR - pool.fee_bps as u64) as u128 / BPS_DENOMINATOR as u128;
+    (reserve_out as u128 * amount_in_after_fee / (reserve_in as u128 + amount_in_after_fee)) as u64
+}
+
+/// Integer square root (floor), used for the initial LP share supply
+fn isqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+    let mut x = value;
+    let mut y = x.div_ceil(2);
+    while y < x {
+        x = y;
+        y = (x + value / x) / 2;
+    }
+    x
+}
+
+/// Closes a program-owned account, sending its lamports to `destination`
+fn close_program_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    let lamports = account.lamports();
+    **destination.try_borrow_mut_lamports()? = destination.lamports().checked_add(lamports).ok_or(ProgramError::InvalidArgument)?;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.resize(0)?;
+    account.assign(&solana_system_interface::program::ID);
+    Ok(())
+}
+
+/// Points boost in bps for the driver's stake; scales with the staked amount up to
+/// STAKE_BOOST_CAP_POINTS and stops while the stake is cooling down.
+fn stake_boost_bps(program_id: &Pubkey, stake_pda: &AccountInfo, driver: &Pubkey) -> Result<u64, ProgramError> {
+    let (expected_stake_pda,_)=Pubkey::find_program_address(&[STAKE_SEED, driver.as_ref()], program_id);
+    if expected_stake_pda!=*stake_pda.key{
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if stake_pda.data_is_empty() {
+        return Ok(0);
+    }
+    let stake = StakeAccount::try_from_slice(&stake_pda.data.borrow())?;
+    if stake.amount == 0 || stake.unstake_requested_at != 0 {
+        return Ok(0);
+    }
+    let (_, max_boost_bps) = STAKE_TIERS[(stake.tier as usize).min(STAKE_TIERS.len() - 1)];
+    Ok(max_boost_bps * stake.amount.min(STAKE_BOOST_CAP_POINTS) / STAKE_BOOST_CAP_POINTS)
+}
+
+/// Bitmask of the hours of day a band covers, bit n set for hour n
+fn tariff_band_hours(band: &TariffBand) -> u32 {
+    let mask_until = |hour: u8| ((1u64 << hour) - 1) as u32;
+    if band.start_hour < band.end_hour {
+        mask_until(band.end_hour) & !mask_until(band.start_hour)
+    } else {
+        (!mask_until(band.start_hour) & mask_until(24)) | mask_until(band.end_hour)
+    }
+}
+
+/// Seconds of [start_ts, end_ts) whose UTC hour of day falls in [from_hour, to_hour), from_hour < to_hour
+fn secs_in_hours(start_ts: i64, end_ts: i64, from_hour: u8, to_hour: u8) -> u64 {
+    let from = from_hour as i64 * 3600;
+    let len = (to_hour as i64 - from_hour as i64) * 3600;
+    // covered seconds between the unix epoch and t: full days plus the part of the current day
+    let covered_until = |t: i64| t.div_euclid(86_400) * len + (t.rem_euclid(86_400) - from).clamp(0, len);
+    (covered_until(end_ts) - covered_until(start_ts)) as u64
+}
+
+/// Integrates the charger's tariff over [start_ts, end_ts) and returns
+/// (price in lamports, points) for that span. Bands never overlap, so every
+/// second outside them is charged at the flat rate. The points rate after the
+/// band multiplier never exceeds max_rate_points_per_sec.
+fn tariff_price_and_points(charger: &ChargerAccount, start_ts: i64, end_ts: i64, max_rate_points_per_sec: u64)
+    -> Result<(u64, u64), ProgramError> {
+    // seconds weighted by the multiplier of the band they fall in, points already times the rate
+    let mut price_weighted_secs: u128 = 0;
+    let mut points_weighted: u128 = 0;
+    let rate = charger.rate_points_per_sec as u128;
+    let max_rate_bps = max_rate_points_per_sec as u128 * BPS_DENOMINATOR as u128;
+    let mut flat_secs = (end_ts - start_ts) as u64;
+    let band_count = (charger.tariff_band_count as usize).min(MAX_TARIFF_BANDS);
+    for band in &charger.tariff_bands[..band_count] {
+        let secs = if band.start_hour < band.end_hour {
+            secs_in_hours(start_ts, end_ts, band.start_hour, band.end_hour)
+        } else {
+            secs_in_hours(start_ts, end_ts, band.start_hour, 24) + secs_in_hours(start_ts, end_ts, 0, band.end_hour)
+        };
+        flat_secs = flat_secs.saturating_sub(secs);
+        price_weighted_secs += secs as u128 * band.price_multiplier_bps as u128;
+        points_weighted += secs as u128 * (rate * band.points_multiplier_bps as u128).min(max_rate_bps);
+    }
+    price_weighted_secs += flat_secs as u128 * BPS_DENOMINATOR as u128;
+    points_weighted += flat_secs as u128 * (rate * BPS_DENOMINATOR as u128).min(max_rate_bps);
+
+    let price = price_weighted_secs * charger.price_per_sec_lamports as u128 / BPS_DENOMINATOR as u128;
+    let points = points_weighted / BPS_DENOMINATOR as u128;
+    Ok((
+        u64::try_from(price).map_err(|_| ProgramError::InvalidArgument)?,
+        u64::try_from(points).map_err(|_| ProgramError::InvalidArgument)?,
+    ))
+}
+
+/// Metered energy as a share (bps) of what the charger could deliver at its rated power
+/// over the charging time, capped at 100%. A charger without a rated power earns nothing.
+fn energy_utilisation_bps(power_kw: f32, charging_secs: u64, energy_wh: u64) -> u64 {
+    let rated_watts = (power_kw.max(0.0) * 1000.0) as u128;
+    let max_wh = rated_watts * charging_secs as u128 / 3600;
+    if max_wh == 0 {
+        return 0;
+    }
+    (energy_wh as u128 * BPS_DENOMINATOR as u128 / max_wh).min(BPS_DENOMINATOR as u128) as u64
+}
+
+/// Whether an Ed25519 program instruction's data carries exactly one signature by
+/// `signer` over `expected_message`, with key and message inside the instruction itself
+fn ed25519_instruction_signs(data: &[u8], signer: &Pubkey, expected_message: &[u8]) -> bool {
+    // header: num_signatures (u8) + padding (u8), then one 14 byte offsets struct
+    if data.len() < 16 || data[0] != 1 {
+        return false;
+    }
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let pubkey_offset = read_u16(6) as usize;
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+    // all data must come from the Ed25519 instruction itself
+    if read_u16(4) != u16::MAX || read_u16(8) != u16::MAX || read_u16(14) != u16::MAX {
+        return false;
+    }
+    let (Some(pubkey_bytes), Some(message)) = (
+        data.get(pubkey_offset..pubkey_offset + 32),
+        data.get(message_offset..message_offset + message_size),
+    ) else {
+        return false;
+    };
+    pubkey_bytes == signer.as_ref() && message == expected_message
+}
+
+/// Looks through the instructions before the current one for an Ed25519 program
+/// instruction that verified `expected_message` against `device_pubkey`.
+/// The signature, key and message must all live inside that Ed25519 instruction.
+fn verify_device_attestation(instructions_sysvar: &AccountInfo, device_pubkey: &Pubkey, expected_message: &[u8]) -> ProgramResult {
+    if *instructions_sysvar.key != sysvar_instructions::ID {
+        msg!("Instructions sysvar account expected");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let current_index = sysvar_instructions::load_current_index_checked(instructions_sysvar)?;
+
+    for index in 0..current_index {
+        let ix = sysvar_instructions::load_instruction_at_checked(index as usize, instructions_sysvar)?;
+        if ix.program_id != ed25519_program::ID {
+            continue;
+        }
+        if ed25519_instruction_signs(&ix.data, device_pubkey, expected_message) {
+            return Ok(());
+        }
+    }
+
+    msg!("Missing meter attestation signed by charger device {}", device_pubkey);
+    Err(ProgramError::MissingRequiredSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charger(rate_points_per_sec: u64, price_per_sec_lamports: u64, bands: &[TariffBand]) -> ChargerAccount {
+        let mut tariff_bands = [TariffBand::default(); MAX_TARIFF_BANDS];
+        tariff_bands[..bands.len()].copy_from_slice(bands);
+        ChargerAccount {accountType:1, is_initialized: true, authority: Pubkey::new_unique(),
+            code: "c1".to_string(), name: String::new(), city: String::new(), address: String::new(),
+            latitude: 0.0, longitude: 0.0, power_kw: 11.0, rate_points_per_sec, price_per_sec_lamports,
+            device_pubkey: Pubkey::new_unique(), status: ChargerStatus::Available, last_heartbeat: 0,
+            idle_threshold_secs: 0, idle_fee_per_min_lamports: 0,
+            tariff_band_count: bands.len() as u8, tariff_bands,
+            bond_lamports: CHARGER_BOND_LAMPORTS, slash_count: 0,
+            verified: false, verified_at: 0, verified_by: Pubkey::default(),
+            closing_requested_at: 0, open_sessions: 0,
+        }
+    }
+
+    fn band(start_hour: u8, end_hour: u8, price_multiplier_bps: u16, points_multiplier_bps: u16) -> TariffBand {
+        TariffBand {start_hour, end_hour, price_multiplier_bps, points_multiplier_bps}
+    }
+
+    fn pool(amp_reserve: u64, sol_reserve: u64, total_shares: u64, fee_bps: u16) -> PoolAccount {
+        PoolAccount {accountType:12, is_initialized: true, amp_reserve, sol_reserve, total_shares, fee_bps}
+    }
+
+    const DAY: i64 = 86_400;
+
+    #[test]
+    fn flat_tariff_is_rate_times_duration() {
+        let c = charger(10, 3, &[]);
+        assert_eq!(tariff_price_and_points(&c, 1_000, 4_600, u64::MAX).unwrap(), (3 * 3_600, 10 * 3_600));
+    }
+
+    #[test]
+    fn tariff_band_wrapping_midnight_is_prorated() {
+        // 22:00-06:00 at half price and double points, session 21:30 to 00:30
+        let c = charger(10, 4, &[band(22, 6, 5_000, 20_000)]);
+        let start = 10 * DAY + 21 * 3_600 + 1_800;
+        let (price, points) = tariff_price_and_points(&c, start, start + 3 * 3_600, u64::MAX).unwrap();
+        assert_eq!(price, 1_800 * 4 + 9_000 * 2);
+        assert_eq!(points, 1_800 * 10 + 9_000 * 20);
+    }
+
+    #[test]
+    fn closed_form_tariff_matches_second_by_second_sum() {
+        let c = charger(7, 5, &[band(0, 6, 5_000, 15_000), band(17, 21, 20_000, 5_000), band(23, 24, 12_000, 10_000)]);
+        let start = 3 * DAY + 5 * 3_600 + 1_234;
+        let end = start + 2 * DAY + 7 * 3_600 + 99;
+        let mut price_bps: u128 = 0;
+        let mut points_bps: u128 = 0;
+        for t in start..end {
+            let hour = (t.rem_euclid(DAY) / 3_600) as u8;
+            let (p, q) = c.tariff_bands[..3].iter()
+                .find(|b| tariff_band_hours(b) & (1 << hour) != 0)
+                .map(|b| (b.price_multiplier_bps as u128, b.points_multiplier_bps as u128))
+                .unwrap_or((BPS_DENOMINATOR as u128, BPS_DENOMINATOR as u128));
+            price_bps += p;
+            points_bps += q;
+        }
+        let expected = ((price_bps * 5 / BPS_DENOMINATOR as u128) as u64, (points_bps * 7 / BPS_DENOMINATOR as u128) as u64);
+        assert_eq!(tariff_price_and_points(&c, start, end, u64::MAX).unwrap(), expected);
+    }
+
+    #[test]
+    fn tariff_points_rate_is_clamped_to_the_cap() {
+        // 10 points/sec doubled to 20 in the band, capped at 15; flat hours stay at 10
+        let c = charger(10, 0, &[band(0, 12, BPS_DENOMINATOR as u16, 20_000)]);
+        let start = 11 * 3_600;
+        let (_, points) = tariff_price_and_points(&c, start, start + 2 * 3_600, 15).unwrap();
+        assert_eq!(points, 3_600 * 15 + 3_600 * 10);
+    }
+
+    #[test]
+    fn secs_in_hours_handles_partial_and_negative_days() {
+        assert_eq!(secs_in_hours(0, DAY, 6, 9), 3 * 3_600);
+        assert_eq!(secs_in_hours(7 * 3_600, 8 * 3_600 + 60, 6, 8), 3_600);
+        assert_eq!(secs_in_hours(-DAY, 0, 0, 24), DAY as u64);
+        assert_eq!(secs_in_hours(5, 5, 0, 24), 0);
+    }
+
+    #[test]
+    fn tariff_band_hours_marks_covered_hours() {
+        assert_eq!(tariff_band_hours(&band(0, 24, 0, 0)), 0x00FF_FFFF);
+        assert_eq!(tariff_band_hours(&band(8, 10, 0, 0)), 0b11 << 8);
+        assert_eq!(tariff_band_hours(&band(22, 2, 0, 0)), (0b11 << 22) | 0b11);
+        assert_eq!(tariff_band_hours(&band(22, 2, 0, 0)) & tariff_band_hours(&band(1, 4, 0, 0)), 0b10);
+    }
+
+    #[test]
+    fn first_deposit_mints_geometric_mean_shares_less_the_locked_minimum() {
+        let p = pool(0, 0, 0, 30);
+        assert_eq!(pool_deposit_quote(&p, 4_000_000, 1_000_000).unwrap(), (1_000_000, 2_000_000 - MINIMUM_LIQUIDITY));
+        // a dust first deposit mints nothing and is rejected
+        assert_eq!(pool_deposit_quote(&p, 400, 100).unwrap(), (100, 0));
+    }
+
+    #[test]
+    fn later_deposits_follow_the_reserve_ratio() {
+        let p = pool(1_000, 500, 700, 30);
+        // 100 AMP needs 50 lamports and mints 10% of the supply
+        assert_eq!(pool_deposit_quote(&p, 100, 1_000).unwrap(), (50, 70));
+        // lamports round up in the pool's favour
+        assert_eq!(pool_deposit_quote(&p, 3, 1_000).unwrap(), (2, 2));
+    }
+
+    #[test]
+    fn withdraw_is_pro_rata() {
+        let p = pool(1_000, 500, 700, 30);
+        assert_eq!(pool_withdraw_quote(&p, 70), (100, 50));
+        assert_eq!(pool_withdraw_quote(&p, 700), (1_000, 500));
+    }
+
+    #[test]
+    fn swap_charges_fee_and_keeps_the_product() {
+        let p = pool(1_000_000, 2_000_000, 1, 30);
+        let out = pool_swap_quote(&p, 10_000, SwapDirection::SolToAmp);
+        // 9_970 in after fee: 1_000_000 * 9_970 / 2_009_970
+        assert_eq!(out, 4_960);
+        assert!((p.sol_reserve + 10_000) as u128 * (p.amp_reserve - out) as u128 >= p.sol_reserve as u128 * p.amp_reserve as u128);
+        assert_eq!(pool_swap_quote(&pool(1_000, 1_000, 1, 0), 1_000, SwapDirection::AmpToSol), 500);
+    }
+
+    #[test]
+    fn isqrt_floors() {
+        assert_eq!(isqrt(0), 0);
+        assert_eq!(isqrt(15), 3);
+        assert_eq!(isqrt(16), 4);
+        assert_eq!(isqrt(u64::MAX as u128 * u64::MAX as u128), u64::MAX as u128);
+    }
+
+    #[test]
+    fn dutch_auction_decays_linearly_to_the_floor() {
+        let listing = ListingAccount {accountType:4, is_initialized: true, seller: Pubkey::new_unique(),
+            amount_total: 10, price_per_point_lamports: 0, expires_at: 0, kind: ListingKind::DutchAuction,
+            auction_start_price: 1_000, auction_floor_price: 200, auction_start_ts: 100, auction_decay_secs: 400,
+        };
+        assert_eq!(listing_price(&listing, 0), 1_000);
+        assert_eq!(listing_price(&listing, 100), 1_000);
+        assert_eq!(listing_price(&listing, 300), 600);
+        assert_eq!(listing_price(&listing, 10_000), 200);
+        assert!(!is_listing_expired(&listing, i64::MAX));
+    }
+
+    fn ed25519_data(pubkey: &Pubkey, message: &[u8], instruction_index: u16) -> Vec<u8> {
+        // header (2) + offsets (14), then pubkey, signature, message
+        let pubkey_offset = 16u16;
+        let signature_offset = pubkey_offset + 32;
+        let message_offset = signature_offset + 64;
+        let mut data = vec![1, 0];
+        for value in [signature_offset, instruction_index, pubkey_offset, instruction_index, message_offset,
+            message.len() as u16, instruction_index] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(pubkey.as_ref());
+        data.extend_from_slice(&[7u8; 64]);
+        data.extend_from_slice(message);
+        data
+    }
+
+    #[test]
+    fn ed25519_offsets_must_point_at_key_and_message_in_the_instruction() {
+        let device = Pubkey::new_unique();
+        let message = [3u8; ATTESTATION_MESSAGE_LEN];
+        assert!(ed25519_instruction_signs(&ed25519_data(&device, &message, u16::MAX), &device, &message));
+        assert!(!ed25519_instruction_signs(&ed25519_data(&Pubkey::new_unique(), &message, u16::MAX), &device, &message));
+        assert!(!ed25519_instruction_signs(&ed25519_data(&device, &[4u8; ATTESTATION_MESSAGE_LEN], u16::MAX), &device, &message));
+        // key and message living in another instruction are not accepted
+        assert!(!ed25519_instruction_signs(&ed25519_data(&device, &message, 0), &device, &message));
+        let mut truncated = ed25519_data(&device, &message, u16::MAX);
+        truncated.truncate(truncated.len() - 1);
+        assert!(!ed25519_instruction_signs(&truncated, &device, &message));
+    }
+
+    #[test]
+    fn epoch_budget_caps_and_rolls_over() {
+        let mut state = StateAccount {accountType:8, is_initialized: true, admin: Pubkey::default(),
+            pending_admin: Pubkey::default(), registration_fee: 0, fee_mint: Pubkey::default(),
+            max_rate_points_per_sec: DEFAULT_MAX_RATE_POINTS_PER_SEC, epoch_secs: 100, epoch_budget: 1_000,
+            epoch_start: 0, epoch_issued: 0,
+            paused: false, registration_paused: false, sessions_paused: false, marketplace_paused: false,
+        };
+        assert_eq!(take_from_epoch_budget(&mut state, 600, 10), 600);
+        assert_eq!(take_from_epoch_budget(&mut state, 600, 20), 400);
+        assert_eq!(take_from_epoch_budget(&mut state, 1, 99), 0);
+        // a new epoch starts on the epoch grid, not at the call
+        assert_eq!(take_from_epoch_budget(&mut state, 600, 250), 600);
+        assert_eq!(state.epoch_start, 200);
+        assert_eq!(state.epoch_issued, 600);
+    }
+
+    #[test]
+    fn points_scale_with_metered_energy() {
+        // 11 kW for an hour can deliver 11_000 Wh
+        assert_eq!(energy_utilisation_bps(11.0, 3_600, 11_000), BPS_DENOMINATOR);
+        assert_eq!(energy_utilisation_bps(11.0, 3_600, 2_750), 2_500);
+        assert_eq!(energy_utilisation_bps(11.0, 3_600, 50_000), BPS_DENOMINATOR);
+        assert_eq!(energy_utilisation_bps(11.0, 3_600, 1), 0);
+        assert_eq!(energy_utilisation_bps(0.0, 3_600, 1), 0);
+    }
+}
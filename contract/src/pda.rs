@@ -0,0 +1,98 @@
+// pda.rs - PDA derivations for every account the program owns or signs for
+// Usable on-chain (CPI callers, with the "no-entrypoint" feature) and off-chain.
+
+use solana_program::pubkey::Pubkey;
+use solana_sdk_ids::bpf_loader_upgradeable;
+
+use crate::{
+    AUDITOR_SEED, BID_SEED, CHARGER_SEED, CHARGER_VAULT_SEED, DRIVER_SEED, FEE_WAIVER_SEED, FLEET_MEMBER_SEED, FLEET_SEED, FLEET_VAULT_SEED,
+    LISTING_SEED, LP_SEED, PARTNER_AUTHORITY_SEED, PARTNER_SEED, POOL_SEED, POOL_VAULT_SEED, RESERVATION_SEED, SESSION_SEED,
+    STAKE_SEED, STATE_SEED, USER_SEED,
+};
+
+pub fn find_state_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STATE_SEED], program_id)
+}
+
+/// ProgramData account of the upgradeable loader, holds the upgrade authority checked by Initialize
+pub fn find_program_data_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::ID)
+}
+
+pub fn find_charger_address(program_id: &Pubkey, code: &str, authority: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CHARGER_SEED, code.as_bytes(), authority.as_ref()], program_id)
+}
+
+pub fn find_charger_vault_address(program_id: &Pubkey, charger: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CHARGER_VAULT_SEED, charger.as_ref()], program_id)
+}
+
+pub fn find_driver_address(program_id: &Pubkey, wallet: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[DRIVER_SEED, wallet.as_ref()], program_id)
+}
+
+/// driver is the DriverAccount PDA, not the wallet
+pub fn find_session_address(program_id: &Pubkey, charger: &Pubkey, driver: &Pubkey, start_ts: i64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SESSION_SEED, charger.as_ref(), driver.as_ref(), &start_ts.to_le_bytes()], program_id)
+}
+
+pub fn find_listing_address(program_id: &Pubkey, seller: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LISTING_SEED, seller.as_ref()], program_id)
+}
+
+pub fn find_user_address(program_id: &Pubkey, wallet: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[USER_SEED, wallet.as_ref()], program_id)
+}
+
+pub fn find_reservation_address(program_id: &Pubkey, charger: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RESERVATION_SEED, charger.as_ref()], program_id)
+}
+
+pub fn find_stake_address(program_id: &Pubkey, wallet: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STAKE_SEED, wallet.as_ref()], program_id)
+}
+
+pub fn find_fleet_address(program_id: &Pubkey, admin: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FLEET_SEED, admin.as_ref()], program_id)
+}
+
+pub fn find_fleet_vault_address(program_id: &Pubkey, fleet: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FLEET_VAULT_SEED, fleet.as_ref()], program_id)
+}
+
+pub fn find_fleet_member_address(program_id: &Pubkey, fleet: &Pubkey, driver: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FLEET_MEMBER_SEED, fleet.as_ref(), driver.as_ref()], program_id)
+}
+
+pub fn find_bid_address(program_id: &Pubkey, buyer: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[BID_SEED, buyer.as_ref()], program_id)
+}
+
+pub fn find_pool_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POOL_SEED], program_id)
+}
+
+pub fn find_pool_vault_address(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POOL_VAULT_SEED, pool.as_ref()], program_id)
+}
+
+pub fn find_lp_address(program_id: &Pubkey, pool: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LP_SEED, pool.as_ref(), owner.as_ref()], program_id)
+}
+
+pub fn find_fee_waiver_address(program_id: &Pubkey, operator: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FEE_WAIVER_SEED, operator.as_ref()], program_id)
+}
+
+pub fn find_auditor_address(program_id: &Pubkey, auditor: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[AUDITOR_SEED, auditor.as_ref()], program_id)
+}
+
+pub fn find_partner_address(program_id: &Pubkey, partner_program: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PARTNER_SEED, partner_program.as_ref()], program_id)
+}
+
+/// The signer a partner program uses when it CPIs AwardPoints, derived under the partner's own id
+pub fn find_partner_authority_address(partner_program: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PARTNER_AUTHORITY_SEED], partner_program)
+}
@@ -0,0 +1,210 @@
+// program_tests.rs - handlers run end to end under solana-program-test, built through the client builders.
+// Harness holds the test validator context with the program initialized and the admin as its
+// upgrade authority; the submodules exercise one area each, error paths included.
+
+use borsh::BorshDeserialize;
+use solana_ed25519_program::new_ed25519_instruction_with_signature;
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction as SolInstruction, InstructionError},
+    native_token::LAMPORTS_PER_SOL,
+    program::invoke_signed,
+    pubkey::Pubkey,
+    rent::Rent,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use solana_system_interface::instruction as system_instruction;
+
+use crate::client::*;
+use crate::{DriverAccount, PARTNER_AUTHORITY_SEED};
+
+mod admin;
+mod chargers;
+mod fleets;
+mod market;
+mod points;
+mod pool;
+mod reservations;
+mod sessions;
+mod staking;
+
+/// A fake ProgramData account naming `authority` as upgrade authority, so Initialize runs under program-test
+pub(crate) fn program_data_account(authority: &Pubkey) -> Account {
+    let mut data = 3u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(authority.as_ref());
+    Account {lamports: LAMPORTS_PER_SOL, data, owner: solana_sdk_ids::bpf_loader_upgradeable::ID, executable: false,
+        rent_epoch: 0}
+}
+
+/// The transaction failed in one of its instructions with `error`
+pub(crate) fn assert_rejected(result: Result<(), TransactionError>, error: InstructionError) {
+    match result {
+        Err(TransactionError::InstructionError(_, actual)) => assert_eq!(actual, error),
+        other => panic!("expected {:?}, got {:?}", error, other),
+    }
+}
+
+/// The charger device's Ed25519 signature over session ‖ energy_wh ‖ end_ts, to go ahead of StopSession
+pub(crate) fn attestation(device: &Keypair, session: &Pubkey, energy_wh: u64, end_ts: i64) -> SolInstruction {
+    let mut message = session.to_bytes().to_vec();
+    message.extend_from_slice(&energy_wh.to_le_bytes());
+    message.extend_from_slice(&end_ts.to_le_bytes());
+    let signature = <[u8; 64]>::from(device.sign_message(&message));
+    new_ed25519_instruction_with_signature(&message, &signature, &device.pubkey().to_bytes())
+}
+
+/// Stand-in partner program: forwards its instruction data as AwardPoints to the program passed last,
+/// signing with its PARTNER_AUTHORITY_SEED PDA
+fn partner_process_instruction(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let (authority, bump) = find_partner_authority_address(program_id);
+    let (program, award_accounts) = accounts.split_last().unwrap();
+    let metas = award_accounts.iter().map(|account| AccountMeta {pubkey: *account.key,
+        is_signer: account.is_signer || *account.key == authority, is_writable: account.is_writable}).collect();
+    let award = SolInstruction::new_with_bytes(*program.key, data, metas);
+    invoke_signed(&award, accounts, &[&[PARTNER_AUTHORITY_SEED, &[bump]]])
+}
+
+/// Routes an AwardPoints instruction through the stand-in partner program, which signs for its authority
+pub(crate) fn through_partner(partner_program: &Pubkey, award: SolInstruction) -> SolInstruction {
+    let mut accounts: Vec<AccountMeta> = award.accounts.into_iter()
+        .map(|meta| AccountMeta {is_signer: false, ..meta}).collect();
+    accounts.push(AccountMeta::new_readonly(award.program_id, false));
+    SolInstruction::new_with_bytes(*partner_program, &award.data, accounts)
+}
+
+pub(crate) struct Charger {
+    pub address: Pubkey,
+    pub operator: Pubkey,
+    pub device: Keypair,
+}
+
+pub(crate) struct Harness {
+    pub context: ProgramTestContext,
+    pub program_id: Pubkey,
+    pub admin: Keypair,
+    pub partner_program: Pubkey,
+}
+
+impl Harness {
+    /// Starts the program next to the stand-in partner program and runs Initialize with a funded admin
+    pub async fn new() -> Self {
+        let program_id = Pubkey::new_unique();
+        let partner_program = Pubkey::new_unique();
+        let admin = Keypair::new();
+        let mut program_test = ProgramTest::new("contract", program_id, processor!(crate::process_instruction));
+        program_test.add_program("partner", partner_program, processor!(partner_process_instruction));
+        program_test.add_account(find_program_data_address(&program_id).0, program_data_account(&admin.pubkey()));
+        let context = program_test.start_with_context().await;
+        let mut harness = Harness {context, program_id, admin, partner_program};
+        let admin = harness.admin.insecure_clone();
+        harness.fund(&admin.pubkey(), 10 * LAMPORTS_PER_SOL).await;
+        harness.send(&[initialize(&program_id, &admin.pubkey(), admin.pubkey())], &[&admin]).await.unwrap();
+        harness
+    }
+
+    /// A new system account holding 10 SOL
+    pub async fn wallet(&mut self) -> Keypair {
+        let wallet = Keypair::new();
+        self.fund(&wallet.pubkey(), 10 * LAMPORTS_PER_SOL).await;
+        wallet
+    }
+
+    pub async fn fund(&mut self, to: &Pubkey, lamports: u64) {
+        let payer = self.context.payer.pubkey();
+        self.send(&[system_instruction::transfer(&payer, to, lamports)], &[]).await.unwrap();
+    }
+
+    /// Sends the instructions in one transaction paid by the context payer
+    pub async fn send(&mut self, instructions: &[SolInstruction], signers: &[&Keypair]) -> Result<(), TransactionError> {
+        let blockhash = self.context.banks_client.get_latest_blockhash().await.unwrap();
+        self.send_with(instructions, signers, blockhash).await
+    }
+
+    /// Sends under a new blockhash, the bank reports a transaction identical to an earlier one as done without running it
+    pub async fn resend(&mut self, instructions: &[SolInstruction], signers: &[&Keypair]) -> Result<(), TransactionError> {
+        let latest = self.context.banks_client.get_latest_blockhash().await.unwrap();
+        let mut blockhash = latest;
+        while blockhash == latest {
+            blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+        }
+        self.send_with(instructions, signers, blockhash).await
+    }
+
+    async fn send_with(&mut self, instructions: &[SolInstruction], signers: &[&Keypair], blockhash: Hash)
+        -> Result<(), TransactionError> {
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        let tx = Transaction::new_signed_with_payer(instructions, Some(&self.context.payer.pubkey()), &all_signers, blockhash);
+        self.context.banks_client.process_transaction(tx).await.map_err(|e| e.unwrap())
+    }
+
+    pub async fn now(&mut self) -> i64 {
+        self.context.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+    }
+
+    /// Moves the bank clock to `unix_timestamp`, the background task only registers blockhashes so it sticks
+    pub async fn warp_to(&mut self, unix_timestamp: i64) {
+        let mut clock = self.context.banks_client.get_sysvar::<Clock>().await.unwrap();
+        clock.unix_timestamp = unix_timestamp;
+        self.context.set_sysvar(&clock);
+    }
+
+    pub async fn lamports(&mut self, address: &Pubkey) -> u64 {
+        self.context.banks_client.get_balance(*address).await.unwrap()
+    }
+
+    pub async fn account<T: BorshDeserialize>(&mut self, address: &Pubkey) -> T {
+        let account = self.context.banks_client.get_account(*address).await.unwrap().expect("account exists");
+        T::try_from_slice(&account.data).unwrap()
+    }
+
+    pub async fn exists(&mut self, address: &Pubkey) -> bool {
+        self.context.banks_client.get_account(*address).await.unwrap().is_some_and(|account| account.lamports > 0)
+    }
+
+    /// Writes the driver's DriverAccount holding `points`, standing in for the sessions that would have earned them
+    pub fn give_points(&mut self, driver: &Pubkey, points: u64) {
+        let data = borsh::to_vec(&DriverAccount {accountType: 2, is_initialized: true, owner: *driver, amp_balance: points})
+            .unwrap();
+        let account = Account {lamports: Rent::default().minimum_balance(data.len()), data, owner: self.program_id,
+            executable: false, rent_epoch: 0};
+        self.context.set_account(&find_driver_address(&self.program_id, driver).0, &account.into());
+    }
+
+    pub async fn points(&mut self, driver: &Pubkey) -> u64 {
+        let address = find_driver_address(&self.program_id, driver).0;
+        self.account::<DriverAccount>(&address).await.amp_balance
+    }
+
+    pub fn session_address(&self, charger: &Pubkey, driver: &Pubkey, start_ts: i64) -> Pubkey {
+        find_session_address(&self.program_id, charger, &find_driver_address(&self.program_id, driver).0, start_ts).0
+    }
+
+    /// Stops the driver's own session, the charger device attesting `energy_wh` delivered by `end_ts`
+    pub async fn stop(&mut self, charger: &Charger, driver: &Keypair, start_ts: i64, end_ts: i64, energy_wh: u64,
+        fleet: Option<FleetPayment>) -> Result<(), TransactionError> {
+        let session = self.session_address(&charger.address, &driver.pubkey(), start_ts);
+        let stop = stop_session(&self.program_id, &driver.pubkey(), &driver.pubkey(), &charger.address, &charger.operator,
+            start_ts, end_ts, energy_wh, fleet);
+        self.send(&[attestation(&charger.device, &session, energy_wh, end_ts), stop], &[driver]).await
+    }
+
+    /// Registers an 11 kW charger paying 10 points and 1 lamport per second, with a fresh device key
+    pub async fn add_charger(&mut self, operator: &Keypair, code: &str) -> Charger {
+        let device = Keypair::new();
+        let ix = add_charger(&self.program_id, &operator.pubkey(), &self.admin.pubkey(), code.into(), "Depot".into(),
+            "Pune".into(), "Bay 1".into(), 18.5, 73.8, 11.0, 10, 1, device.pubkey(), 0, 0, None);
+        self.send(&[ix], &[operator]).await.unwrap();
+        Charger {address: find_charger_address(&self.program_id, code, &operator.pubkey()).0, operator: operator.pubkey(), device}
+    }
+}
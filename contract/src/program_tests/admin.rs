@@ -0,0 +1,83 @@
+use solana_program::{instruction::InstructionError, pubkey::Pubkey};
+use solana_sdk::signature::Signer;
+
+use super::{assert_rejected, Harness};
+use crate::client::*;
+use crate::{PauseScope, StateAccount};
+
+#[tokio::test]
+async fn pause_scopes_block_their_instructions_but_not_exits() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let admin = harness.admin.insecure_clone();
+    harness.give_points(&driver.pubkey(), 100);
+    harness.give_points(&operator.pubkey(), 0);
+    let pause = |scope, paused| set_pause(&program_id, &admin.pubkey(), scope, paused);
+    let register = |code: &str| add_charger(&program_id, &operator.pubkey(), &admin.pubkey(), code.into(), "Depot".into(),
+        "Pune".into(), "Bay 2".into(), 18.5, 73.8, 11.0, 10, 1, charger.device.pubkey(), 0, 0, None);
+    let list = create_listing(&program_id, &driver.pubkey(), 10, 50, None);
+    let transfer = transfer_points(&program_id, &driver.pubkey(), &operator.pubkey(), 10);
+
+    assert_rejected(harness.send(&[set_pause(&program_id, &driver.pubkey(), PauseScope::All, true)], &[&driver]).await,
+        InstructionError::MissingRequiredSignature);
+
+    harness.send(&[pause(PauseScope::Registration, true)], &[&admin]).await.unwrap();
+    assert_rejected(harness.send(&[register("c2")], &[&operator]).await, InstructionError::InvalidAccountData);
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0, None)], &[&driver])
+        .await.unwrap();
+
+    // an open session can still be stopped while new ones are paused
+    harness.send(&[pause(PauseScope::Sessions, true)], &[&admin]).await.unwrap();
+    harness.warp_to(start_ts + 60).await;
+    assert_rejected(harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts + 60, None, 0,
+        None)], &[&driver]).await, InstructionError::InvalidAccountData);
+    harness.stop(&charger, &driver, start_ts, start_ts + 60, 100, None).await.unwrap();
+
+    harness.send(&[pause(PauseScope::Marketplace, true)], &[&admin]).await.unwrap();
+    assert_rejected(harness.send(std::slice::from_ref(&list), &[&driver]).await, InstructionError::InvalidAccountData);
+    harness.send(std::slice::from_ref(&transfer), &[&driver]).await.unwrap();
+
+    harness.send(&[pause(PauseScope::All, true)], &[&admin]).await.unwrap();
+    assert_rejected(harness.resend(std::slice::from_ref(&transfer), &[&driver]).await, InstructionError::InvalidAccountData);
+
+    // lifting the global switch leaves the scoped flags in place
+    harness.send(&[pause(PauseScope::All, false)], &[&admin]).await.unwrap();
+    harness.resend(std::slice::from_ref(&transfer), &[&driver]).await.unwrap();
+    assert_rejected(harness.resend(std::slice::from_ref(&list), &[&driver]).await, InstructionError::InvalidAccountData);
+    for scope in [PauseScope::Registration, PauseScope::Marketplace] {
+        harness.send(&[pause(scope, false)], &[&admin]).await.unwrap();
+    }
+    harness.resend(&[register("c2")], &[&operator]).await.unwrap();
+    harness.resend(std::slice::from_ref(&list), &[&driver]).await.unwrap();
+}
+
+#[tokio::test]
+async fn admin_moves_only_once_the_proposed_key_accepts() {
+    let mut harness = Harness::new().await;
+    let new_admin = harness.wallet().await;
+    let stranger = harness.wallet().await;
+    let program_id = harness.program_id;
+    let admin = harness.admin.insecure_clone();
+    let state = find_state_address(&program_id).0;
+
+    assert_rejected(harness.send(&[accept_admin(&program_id, &new_admin.pubkey())], &[&new_admin]).await,
+        InstructionError::InvalidAccountData);
+    assert_rejected(harness.send(&[propose_admin(&program_id, &stranger.pubkey(), stranger.pubkey())], &[&stranger]).await,
+        InstructionError::MissingRequiredSignature);
+    harness.send(&[propose_admin(&program_id, &admin.pubkey(), new_admin.pubkey())], &[&admin]).await.unwrap();
+    // proposing alone hands nothing over
+    assert_eq!(harness.account::<StateAccount>(&state).await.admin, admin.pubkey());
+    assert_rejected(harness.send(&[accept_admin(&program_id, &stranger.pubkey())], &[&stranger]).await,
+        InstructionError::MissingRequiredSignature);
+
+    harness.resend(&[accept_admin(&program_id, &new_admin.pubkey())], &[&new_admin]).await.unwrap();
+    let state_account: StateAccount = harness.account(&state).await;
+    assert_eq!((state_account.admin, state_account.pending_admin), (new_admin.pubkey(), Pubkey::default()));
+    assert_rejected(harness.send(&[set_pause(&program_id, &admin.pubkey(), PauseScope::All, true)], &[&admin]).await,
+        InstructionError::MissingRequiredSignature);
+    harness.send(&[set_pause(&program_id, &new_admin.pubkey(), PauseScope::All, true)], &[&new_admin]).await.unwrap();
+}
@@ -0,0 +1,214 @@
+use solana_program::instruction::InstructionError;
+use solana_sdk::signature::{Keypair, Signer};
+
+use super::{assert_rejected, attestation, Harness};
+use crate::client::*;
+use crate::{ChargerAccount, ChargerStatus, SlashReason, TariffBand, CHARGER_BOND_LAMPORTS, CHARGER_UNBONDING_SECS, HEARTBEAT_TIMEOUT_SECS,
+    REG_FEE_LAMPORTS, UNVERIFIED_POINTS_BPS};
+
+#[tokio::test]
+async fn add_charger_requires_a_rated_power_in_range() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let device = Keypair::new().pubkey();
+    let admin = harness.admin.pubkey();
+    let program_id = harness.program_id;
+    let add = |code: &str, power_kw: f32| add_charger(&program_id, &operator.pubkey(), &admin, code.into(), "Depot".into(),
+        "Pune".into(), "Bay 1".into(), 18.5, 73.8, power_kw, 10, 1, device, 0, 0, None);
+
+    for (code, power_kw) in [("zero", 0.0), ("negative", -11.0), ("huge", 1_000.5)] {
+        assert_rejected(harness.send(&[add(code, power_kw)], &[&operator]).await, InstructionError::InvalidArgument);
+    }
+    harness.send(&[add("c1", 1_000.0)], &[&operator]).await.unwrap();
+    let charger: ChargerAccount = harness.account(&find_charger_address(&program_id, "c1", &operator.pubkey()).0).await;
+    assert_eq!(charger.power_kw, 1_000.0);
+}
+
+#[tokio::test]
+async fn tariff_schedule_is_fixed_while_a_session_is_open() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let band = |start_hour, end_hour, price_multiplier_bps| TariffBand {start_hour, end_hour, price_multiplier_bps,
+        points_multiplier_bps: 10_000};
+    let schedule = |bands: Vec<TariffBand>| set_tariff_schedule(&program_id, &operator.pubkey(), &charger.address, bands);
+
+    assert_rejected(harness.send(&[set_tariff_schedule(&program_id, &driver.pubkey(), &charger.address, vec![])], &[&driver])
+        .await, InstructionError::IllegalOwner);
+    assert_rejected(harness.send(&[schedule(vec![band(0, 12, 20_000), band(11, 24, 20_000)])], &[&operator]).await,
+        InstructionError::InvalidArgument);
+    harness.send(&[schedule(vec![band(0, 24, 20_000)])], &[&operator]).await.unwrap();
+
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0, None)], &[&driver])
+        .await.unwrap();
+    assert_rejected(harness.send(&[schedule(vec![])], &[&operator]).await, InstructionError::InvalidAccountData);
+
+    // the session settles at the double rate it started under
+    let end_ts = start_ts + 600;
+    harness.warp_to(end_ts).await;
+    let session = harness.session_address(&charger.address, &driver.pubkey(), start_ts);
+    let operator_before = harness.lamports(&operator.pubkey()).await;
+    let stop = stop_session(&program_id, &driver.pubkey(), &driver.pubkey(), &charger.address, &operator.pubkey(), start_ts,
+        end_ts, 2_000, None);
+    harness.send(&[attestation(&charger.device, &session, 2_000, end_ts), stop], &[&driver]).await.unwrap();
+    assert_eq!(harness.lamports(&operator.pubkey()).await - operator_before, 1_200);
+
+    harness.resend(&[schedule(vec![])], &[&operator]).await.unwrap();
+    let charger_account: ChargerAccount = harness.account(&charger.address).await;
+    assert_eq!(charger_account.tariff_band_count, 0);
+}
+
+#[tokio::test]
+async fn slash_charger_pays_the_bond_out_to_the_admin() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let admin = harness.admin.insecure_clone();
+    let vault = find_charger_vault_address(&program_id, &charger.address).0;
+    assert_eq!(harness.lamports(&vault).await, CHARGER_BOND_LAMPORTS);
+
+    let slash = |signer: &Keypair, slash_bps, reason| slash_charger(&program_id, &signer.pubkey(), &charger.address, slash_bps,
+        reason);
+    assert_rejected(harness.send(&[slash(&operator, 2_500, SlashReason::Fraud)], &[&operator]).await,
+        InstructionError::MissingRequiredSignature);
+    for slash_bps in [0, 10_001] {
+        assert_rejected(harness.send(&[slash(&admin, slash_bps, SlashReason::Fraud)], &[&admin]).await,
+            InstructionError::InvalidArgument);
+    }
+    // a charger with a fresh heartbeat cannot be slashed for being offline
+    assert_rejected(harness.send(&[slash(&admin, 2_500, SlashReason::Offline)], &[&admin]).await,
+        InstructionError::InvalidArgument);
+
+    let admin_before = harness.lamports(&admin.pubkey()).await;
+    harness.send(&[slash(&admin, 2_500, SlashReason::Fraud)], &[&admin]).await.unwrap();
+    assert_eq!(harness.lamports(&admin.pubkey()).await - admin_before, CHARGER_BOND_LAMPORTS / 4);
+
+    let now = harness.now().await;
+    harness.warp_to(now + HEARTBEAT_TIMEOUT_SECS + 1).await;
+    harness.send(&[slash(&admin, 10_000, SlashReason::Offline)], &[&admin]).await.unwrap();
+    assert_eq!(harness.lamports(&admin.pubkey()).await - admin_before, CHARGER_BOND_LAMPORTS);
+    let slashed: ChargerAccount = harness.account(&charger.address).await;
+    assert_eq!((slashed.bond_lamports, slashed.slash_count), (0, 2));
+    assert_rejected(harness.send(&[slash(&admin, 2_500, SlashReason::Fraud)], &[&admin]).await,
+        InstructionError::InsufficientFunds);
+}
+
+#[tokio::test]
+async fn close_charger_refunds_the_bond_after_unbonding() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let close = close_charger(&program_id, &operator.pubkey(), &charger.address);
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0, None)], &[&driver])
+        .await.unwrap();
+
+    assert_rejected(harness.send(&[close_charger(&program_id, &driver.pubkey(), &charger.address)], &[&driver]).await,
+        InstructionError::IllegalOwner);
+    harness.send(std::slice::from_ref(&close), &[&operator]).await.unwrap();
+    let closing: ChargerAccount = harness.account(&charger.address).await;
+    assert_eq!(closing.closing_requested_at, start_ts);
+
+    // no new sessions or reservations while unbonding
+    let late = harness.wallet().await;
+    assert_rejected(harness.send(&[start_session(&program_id, &late.pubkey(), &charger.address, start_ts, None, 0, None)],
+        &[&late]).await, InstructionError::InvalidAccountData);
+    let reserve = reserve_charger(&program_id, &late.pubkey(), &charger.address, &operator.pubkey(), start_ts + 600, 600);
+    assert_rejected(harness.send(&[reserve], &[&late]).await, InstructionError::InvalidAccountData);
+    assert_rejected(harness.resend(std::slice::from_ref(&close), &[&operator]).await, InstructionError::InvalidArgument);
+
+    // the open session still has to settle before the bond comes back
+    let end_ts = start_ts + CHARGER_UNBONDING_SECS;
+    harness.warp_to(end_ts).await;
+    assert_rejected(harness.resend(std::slice::from_ref(&close), &[&operator]).await, InstructionError::InvalidAccountData);
+    let session = harness.session_address(&charger.address, &driver.pubkey(), start_ts);
+    let stop = stop_session(&program_id, &driver.pubkey(), &driver.pubkey(), &charger.address, &operator.pubkey(), start_ts,
+        end_ts, 2_000, None);
+    harness.send(&[attestation(&charger.device, &session, 2_000, end_ts), stop], &[&driver]).await.unwrap();
+
+    let rent = harness.lamports(&charger.address).await;
+    let operator_before = harness.lamports(&operator.pubkey()).await;
+    harness.resend(&[close], &[&operator]).await.unwrap();
+    assert!(!harness.exists(&charger.address).await);
+    assert_eq!(harness.lamports(&operator.pubkey()).await - operator_before, rent + CHARGER_BOND_LAMPORTS);
+}
+
+#[tokio::test]
+async fn registration_fee_goes_to_the_admin_unless_waived() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let program_id = harness.program_id;
+    let admin = harness.admin.insecure_clone();
+    let waiver = |signer: &Keypair, waived| set_fee_waiver(&program_id, &signer.pubkey(), &signer.pubkey(), operator.pubkey(),
+        waived);
+
+    let admin_before = harness.lamports(&admin.pubkey()).await;
+    harness.add_charger(&operator, "c1").await;
+    assert_eq!(harness.lamports(&admin.pubkey()).await - admin_before, REG_FEE_LAMPORTS);
+
+    assert_rejected(harness.send(&[set_registration_fee(&program_id, &operator.pubkey(), 0, None)], &[&operator]).await,
+        InstructionError::MissingRequiredSignature);
+    harness.send(&[set_registration_fee(&program_id, &admin.pubkey(), 1_000, None)], &[&admin]).await.unwrap();
+    let admin_before = harness.lamports(&admin.pubkey()).await;
+    harness.add_charger(&operator, "c2").await;
+    assert_eq!(harness.lamports(&admin.pubkey()).await - admin_before, 1_000);
+
+    assert_rejected(harness.send(&[waiver(&operator, true)], &[&operator]).await, InstructionError::MissingRequiredSignature);
+    harness.send(&[waiver(&admin, true)], &[&admin]).await.unwrap();
+    let admin_before = harness.lamports(&admin.pubkey()).await;
+    harness.add_charger(&operator, "c3").await;
+    assert_eq!(harness.lamports(&admin.pubkey()).await, admin_before);
+
+    harness.send(&[waiver(&admin, false)], &[&admin]).await.unwrap();
+    let admin_before = harness.lamports(&admin.pubkey()).await;
+    harness.add_charger(&operator, "c4").await;
+    assert_eq!(harness.lamports(&admin.pubkey()).await - admin_before, 1_000);
+}
+
+#[tokio::test]
+async fn verified_chargers_earn_their_full_points_rate() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let auditor = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let admin = harness.admin.insecure_clone();
+    let appoint = |key: &Keypair, active| set_auditor(&program_id, &admin.pubkey(), &admin.pubkey(), key.pubkey(), active);
+    let verify = |key: &Keypair| verify_charger(&program_id, &key.pubkey(), &charger.address, true);
+
+    assert_rejected(harness.send(&[verify(&auditor)], &[&auditor]).await, InstructionError::InvalidSeeds);
+    assert_rejected(harness.send(&[set_auditor(&program_id, &auditor.pubkey(), &auditor.pubkey(), auditor.pubkey(), true)],
+        &[&auditor]).await, InstructionError::MissingRequiredSignature);
+    harness.send(&[appoint(&operator, true)], &[&admin]).await.unwrap();
+    assert_rejected(harness.send(&[verify(&operator)], &[&operator]).await, InstructionError::IllegalOwner);
+    harness.send(&[appoint(&auditor, false)], &[&admin]).await.unwrap();
+    assert_rejected(harness.resend(&[verify(&auditor)], &[&auditor]).await, InstructionError::IllegalOwner);
+
+    // 600 seconds at 10 points per second, fully utilised
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0, None)], &[&driver])
+        .await.unwrap();
+    harness.warp_to(start_ts + 600).await;
+    harness.stop(&charger, &driver, start_ts, start_ts + 600, 2_000, None).await.unwrap();
+    assert_eq!(harness.points(&driver.pubkey()).await, 6_000 * UNVERIFIED_POINTS_BPS / 10_000);
+
+    harness.send(&[appoint(&auditor, true)], &[&admin]).await.unwrap();
+    harness.resend(&[verify(&auditor)], &[&auditor]).await.unwrap();
+    let charger_account: ChargerAccount = harness.account(&charger.address).await;
+    assert_eq!((charger_account.verified, charger_account.verified_by), (true, auditor.pubkey()));
+
+    let start_ts = start_ts + 600;
+    let heartbeat = set_charger_status(&program_id, &charger.device.pubkey(), &charger.address, ChargerStatus::Available);
+    let start = start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0, None);
+    harness.send(&[heartbeat, start], &[&charger.device, &driver]).await.unwrap();
+    harness.warp_to(start_ts + 600).await;
+    harness.stop(&charger, &driver, start_ts, start_ts + 600, 2_000, None).await.unwrap();
+    assert_eq!(harness.points(&driver.pubkey()).await, 6_000 * UNVERIFIED_POINTS_BPS / 10_000 + 6_000);
+}
@@ -0,0 +1,92 @@
+use solana_program::{instruction::InstructionError, native_token::LAMPORTS_PER_SOL};
+use solana_sdk::signature::Signer;
+
+use super::{assert_rejected, Harness};
+use crate::client::*;
+use crate::{ChargerStatus, FleetMemberAccount};
+
+#[tokio::test]
+async fn fleet_pays_up_to_the_session_cap_and_the_member_allowance() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let fleet_admin = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let fleet = find_fleet_address(&program_id, &fleet_admin.pubkey()).0;
+    let vault = find_fleet_vault_address(&program_id, &fleet).0;
+    let member = find_fleet_member_address(&program_id, &fleet, &driver.pubkey()).0;
+    let payment = Some(FleetPayment {admin: fleet_admin.pubkey(), points_to_fleet: false});
+
+    let create = create_fleet(&program_id, &fleet_admin.pubkey(), false, 500);
+    harness.send(std::slice::from_ref(&create), &[&fleet_admin]).await.unwrap();
+    assert_rejected(harness.resend(&[create], &[&fleet_admin]).await, InstructionError::AccountAlreadyInitialized);
+    harness.send(&[fund_fleet(&program_id, &driver.pubkey(), &fleet_admin.pubkey(), LAMPORTS_PER_SOL)], &[&driver])
+        .await.unwrap();
+    // the builders derive the fleet from its admin, so swap the signer in to act on someone else's fleet
+    let mut by_driver = set_fleet_member(&program_id, &fleet_admin.pubkey(), driver.pubkey(), true, 800);
+    by_driver.accounts[0].pubkey = driver.pubkey();
+    assert_rejected(harness.send(&[by_driver], &[&driver]).await, InstructionError::MissingRequiredSignature);
+    harness.send(&[set_fleet_member(&program_id, &fleet_admin.pubkey(), driver.pubkey(), true, 800)], &[&fleet_admin])
+        .await.unwrap();
+
+    // 600 lamports per session, the fleet covers 500 of the first and the 300 left of the allowance on the second
+    for (fleet_share, spent) in [(500, 500), (300, 800)] {
+        let start_ts = harness.now().await;
+        let heartbeat = set_charger_status(&program_id, &charger.device.pubkey(), &charger.address, ChargerStatus::Available);
+        let start = start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0,
+            Some(&fleet_admin.pubkey()));
+        harness.send(&[heartbeat, start], &[&charger.device, &driver]).await.unwrap();
+        harness.warp_to(start_ts + 600).await;
+        let vault_before = harness.lamports(&vault).await;
+        let operator_before = harness.lamports(&operator.pubkey()).await;
+        harness.stop(&charger, &driver, start_ts, start_ts + 600, 2_000, payment).await.unwrap();
+        assert_eq!(vault_before - harness.lamports(&vault).await, fleet_share);
+        assert_eq!(harness.lamports(&operator.pubkey()).await - operator_before, 600);
+        let membership: FleetMemberAccount = harness.account(&member).await;
+        assert_eq!(membership.spent_lamports, spent);
+    }
+
+    // an inactive member cannot start a fleet session, and only the fleet admin withdraws
+    harness.send(&[set_fleet_member(&program_id, &fleet_admin.pubkey(), driver.pubkey(), false, 800)], &[&fleet_admin])
+        .await.unwrap();
+    let start_ts = harness.now().await;
+    let start = start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0, Some(&fleet_admin.pubkey()));
+    let heartbeat = set_charger_status(&program_id, &charger.device.pubkey(), &charger.address, ChargerStatus::Available);
+    assert_rejected(harness.send(&[heartbeat, start], &[&charger.device, &driver]).await, InstructionError::IllegalOwner);
+    let withdraw = withdraw_fleet(&program_id, &fleet_admin.pubkey(), 100_000);
+    let mut by_driver = withdraw.clone();
+    by_driver.accounts[0].pubkey = driver.pubkey();
+    assert_rejected(harness.send(&[by_driver], &[&driver]).await, InstructionError::MissingRequiredSignature);
+    let admin_before = harness.lamports(&fleet_admin.pubkey()).await;
+    harness.send(&[withdraw], &[&fleet_admin]).await.unwrap();
+    assert_eq!(harness.lamports(&fleet_admin.pubkey()).await - admin_before, 100_000);
+}
+
+#[tokio::test]
+async fn fleet_sessions_can_credit_points_to_the_fleet_admin() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let fleet_admin = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    harness.give_points(&fleet_admin.pubkey(), 0);
+    harness.send(&[create_fleet(&program_id, &fleet_admin.pubkey(), true, 0),
+        fund_fleet(&program_id, &fleet_admin.pubkey(), &fleet_admin.pubkey(), LAMPORTS_PER_SOL),
+        set_fleet_member(&program_id, &fleet_admin.pubkey(), driver.pubkey(), true, LAMPORTS_PER_SOL)], &[&fleet_admin])
+        .await.unwrap();
+
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0,
+        Some(&fleet_admin.pubkey()))], &[&driver]).await.unwrap();
+    harness.warp_to(start_ts + 600).await;
+    let driver_before = harness.lamports(&driver.pubkey()).await;
+    harness.stop(&charger, &driver, start_ts, start_ts + 600, 2_000,
+        Some(FleetPayment {admin: fleet_admin.pubkey(), points_to_fleet: true})).await.unwrap();
+
+    // the fleet paid it all and earned the points
+    assert_eq!(harness.lamports(&driver.pubkey()).await, driver_before);
+    assert_eq!(harness.points(&fleet_admin.pubkey()).await, 1_500);
+    assert_eq!(harness.points(&driver.pubkey()).await, 0);
+}
@@ -0,0 +1,189 @@
+use solana_program::{instruction::InstructionError, rent::Rent};
+use solana_sdk::signature::{Keypair, Signer};
+
+use super::{assert_rejected, Harness};
+use crate::client::*;
+use crate::{BidAccount, ListingAccount, UserAccount};
+
+#[tokio::test]
+async fn bid_escrows_lamports_and_fills_from_sellers() {
+    let mut harness = Harness::new().await;
+    let buyer = harness.wallet().await;
+    let seller = harness.wallet().await;
+    let program_id = harness.program_id;
+    let bid = find_bid_address(&program_id, &buyer.pubkey()).0;
+    harness.give_points(&seller.pubkey(), 40);
+
+    for (amount, price) in [(0, 50), (100, 0)] {
+        assert_rejected(harness.send(&[create_bid(&program_id, &buyer.pubkey(), amount, price)], &[&buyer]).await,
+            InstructionError::InvalidArgument);
+    }
+    harness.send(&[create_bid(&program_id, &buyer.pubkey(), 100, 50)], &[&buyer]).await.unwrap();
+    // a live bid only tops up at its own price
+    assert_rejected(harness.send(&[create_bid(&program_id, &buyer.pubkey(), 50, 60)], &[&buyer]).await,
+        InstructionError::InvalidArgument);
+    harness.send(&[create_bid(&program_id, &buyer.pubkey(), 50, 50)], &[&buyer]).await.unwrap();
+    let bid_rent = Rent::default().minimum_balance(1 + 1 + 32 + 8 + 8);
+    assert_eq!(harness.lamports(&bid).await - bid_rent, 150 * 50);
+    assert_eq!(harness.account::<BidAccount>(&bid).await.amount_total, 150);
+
+    let fill = |amount| fill_bid(&program_id, &seller.pubkey(), &buyer.pubkey(), amount);
+    for amount in [0, 151] {
+        assert_rejected(harness.send(&[fill(amount)], &[&seller]).await, InstructionError::InvalidArgument);
+    }
+    assert_rejected(harness.send(&[fill(41)], &[&seller]).await, InstructionError::InsufficientFunds);
+    let seller_before = harness.lamports(&seller.pubkey()).await;
+    harness.send(&[fill(40)], &[&seller]).await.unwrap();
+    assert_eq!(harness.lamports(&seller.pubkey()).await - seller_before, 40 * 50);
+    assert_eq!(harness.points(&seller.pubkey()).await, 0);
+    assert_eq!(harness.points(&buyer.pubkey()).await, 40);
+
+    // only the bidder cancels, getting the escrow for the unfilled 110 points back
+    let mut by_seller = cancel_bid(&program_id, &buyer.pubkey());
+    by_seller.accounts[0].pubkey = seller.pubkey();
+    assert_rejected(harness.send(&[by_seller], &[&seller]).await, InstructionError::IllegalOwner);
+    let buyer_before = harness.lamports(&buyer.pubkey()).await;
+    harness.send(&[cancel_bid(&program_id, &buyer.pubkey())], &[&buyer]).await.unwrap();
+    assert_eq!(harness.lamports(&buyer.pubkey()).await - buyer_before, 110 * 50);
+    assert_eq!(harness.account::<BidAccount>(&bid).await.amount_total, 0);
+}
+
+/// A seller holding `points` and listing all of them at `price` lamports each
+async fn listed_seller(harness: &mut Harness, points: u64, price: u64) -> Keypair {
+    let seller = harness.wallet().await;
+    harness.give_points(&seller.pubkey(), points);
+    harness.send(&[create_listing(&harness.program_id, &seller.pubkey(), points, price, None)], &[&seller]).await.unwrap();
+    seller
+}
+
+#[tokio::test]
+async fn market_buy_fills_the_cheapest_listings_first() {
+    let mut harness = Harness::new().await;
+    let buyer = harness.wallet().await;
+    let program_id = harness.program_id;
+    let cheap = listed_seller(&mut harness, 100, 10).await;
+    let mid = listed_seller(&mut harness, 100, 20).await;
+    let dear = listed_seller(&mut harness, 100, 30).await;
+    let buy = |sellers: &[&Keypair], max_points, max_price| market_buy(&program_id, &buyer.pubkey(),
+        &sellers.iter().map(|seller| seller.pubkey()).collect::<Vec<_>>(), max_points, max_price);
+
+    assert_rejected(harness.send(&[buy(&[&cheap], 0, 30)], &[&buyer]).await, InstructionError::InvalidArgument);
+    assert_rejected(harness.send(&[buy(&[&mid, &cheap], 150, 30)], &[&buyer]).await, InstructionError::InvalidArgument);
+    // a listing above the limit fails the whole buy rather than skipping it
+    assert_rejected(harness.send(&[buy(&[&cheap, &mid, &dear], 250, 25)], &[&buyer]).await,
+        InstructionError::InvalidArgument);
+
+    let sellers = [cheap.pubkey(), mid.pubkey(), dear.pubkey()];
+    let mut before = Vec::new();
+    for seller in &sellers {
+        before.push(harness.lamports(seller).await);
+    }
+    harness.send(&[buy(&[&cheap, &mid, &dear], 150, 25)], &[&buyer]).await.unwrap();
+    for (seller, (before, paid)) in sellers.iter().zip(before.into_iter().zip([100 * 10, 50 * 20, 0])) {
+        assert_eq!(harness.lamports(seller).await - before, paid);
+    }
+    let bought: UserAccount = harness.account(&find_user_address(&program_id, &buyer.pubkey()).0).await;
+    assert_eq!(bought.amp_balance, 150);
+    let rest: ListingAccount = harness.account(&find_listing_address(&program_id, &mid.pubkey()).0).await;
+    assert_eq!(rest.amount_total, 50);
+
+    // sold out listings are skipped, nothing left at the price is an error
+    assert_rejected(harness.send(&[buy(&[&cheap], 10, 25)], &[&buyer]).await, InstructionError::InsufficientFunds);
+}
+
+#[tokio::test]
+async fn buy_from_listing_honours_the_price_limit_and_minimum_fill() {
+    let mut harness = Harness::new().await;
+    let buyer = harness.wallet().await;
+    let program_id = harness.program_id;
+    let seller = listed_seller(&mut harness, 50, 20).await;
+    let buy = |points, max_price, min_points| buy_from_listing(&program_id, &buyer.pubkey(), &seller.pubkey(), points,
+        max_price, min_points);
+
+    assert_rejected(harness.send(&[buy(10, 19, None)], &[&buyer]).await, InstructionError::InvalidArgument);
+    // all or nothing without min_points, otherwise at least min_points of what is left
+    assert_rejected(harness.send(&[buy(80, 20, None)], &[&buyer]).await, InstructionError::InvalidArgument);
+    assert_rejected(harness.send(&[buy(80, 20, Some(60))], &[&buyer]).await, InstructionError::InvalidArgument);
+
+    let seller_before = harness.lamports(&seller.pubkey()).await;
+    harness.send(&[buy(80, 20, Some(40))], &[&buyer]).await.unwrap();
+    assert_eq!(harness.lamports(&seller.pubkey()).await - seller_before, 50 * 20);
+    let bought: UserAccount = harness.account(&find_user_address(&program_id, &buyer.pubkey()).0).await;
+    assert_eq!(bought.amp_balance, 50);
+    assert_rejected(harness.send(&[buy(1, 20, Some(1))], &[&buyer]).await, InstructionError::InvalidArgument);
+}
+
+#[tokio::test]
+async fn expired_listing_stops_selling_and_returns_the_points() {
+    let mut harness = Harness::new().await;
+    let seller = harness.wallet().await;
+    let buyer = harness.wallet().await;
+    let cranker = harness.wallet().await;
+    let program_id = harness.program_id;
+    let listing = find_listing_address(&program_id, &seller.pubkey()).0;
+    harness.give_points(&seller.pubkey(), 100);
+    let now = harness.now().await;
+
+    assert_rejected(harness.send(&[create_listing(&program_id, &seller.pubkey(), 100, 10, Some(now))], &[&seller]).await,
+        InstructionError::InvalidArgument);
+    harness.send(&[create_listing(&program_id, &seller.pubkey(), 100, 10, Some(now + 600))], &[&seller]).await.unwrap();
+    // the rent goes to the wallet passed as the seller, which has to be the listing's
+    let expire = |seller_account: &Keypair| {
+        let mut ix = expire_listing(&program_id, &cranker.pubkey(), &seller.pubkey());
+        ix.accounts[3].pubkey = seller_account.pubkey();
+        ix
+    };
+    assert_rejected(harness.send(&[expire(&seller)], &[&cranker]).await, InstructionError::InvalidArgument);
+    harness.send(&[buy_from_listing(&program_id, &buyer.pubkey(), &seller.pubkey(), 30, 10, None)], &[&buyer]).await.unwrap();
+
+    harness.warp_to(now + 600).await;
+    assert_rejected(harness.send(&[buy_from_listing(&program_id, &buyer.pubkey(), &seller.pubkey(), 30, 10, None)], &[&buyer])
+        .await, InstructionError::InvalidArgument);
+    // MarketBuy passes over it
+    assert_rejected(harness.send(&[market_buy(&program_id, &buyer.pubkey(), &[seller.pubkey()], 30, 10)], &[&buyer]).await,
+        InstructionError::InsufficientFunds);
+
+    assert_rejected(harness.resend(&[expire(&buyer)], &[&cranker]).await, InstructionError::InvalidAccountData);
+    let rent = harness.lamports(&listing).await;
+    let seller_before = harness.lamports(&seller.pubkey()).await;
+    harness.resend(&[expire(&seller)], &[&cranker]).await.unwrap();
+    assert!(!harness.exists(&listing).await);
+    assert_eq!(harness.lamports(&seller.pubkey()).await - seller_before, rent);
+    assert_eq!(harness.points(&seller.pubkey()).await, 70);
+}
+
+#[tokio::test]
+async fn auction_price_decays_to_the_floor() {
+    let mut harness = Harness::new().await;
+    let seller = harness.wallet().await;
+    let buyer = harness.wallet().await;
+    let program_id = harness.program_id;
+    harness.give_points(&seller.pubkey(), 100);
+    let auction = |points, start_price, floor_price, decay_secs| create_auction_listing(&program_id, &seller.pubkey(), points,
+        start_price, floor_price, decay_secs, None);
+
+    for (points, start_price, floor_price, decay_secs) in [(0, 100, 20, 800), (100, 20, 100, 800), (100, 100, 20, 0)] {
+        assert_rejected(harness.send(&[auction(points, start_price, floor_price, decay_secs)], &[&seller]).await,
+            InstructionError::InvalidArgument);
+    }
+    let now = harness.now().await;
+    harness.send(&[auction(60, 100, 20, 800)], &[&seller]).await.unwrap();
+    // one auction or fixed price listing per seller at a time
+    assert_rejected(harness.send(&[auction(40, 100, 20, 400)], &[&seller]).await, InstructionError::AccountAlreadyInitialized);
+    assert_rejected(harness.send(&[create_listing(&program_id, &seller.pubkey(), 40, 50, None)], &[&seller]).await,
+        InstructionError::InvalidArgument);
+
+    // halfway through the decay it sells at 60
+    harness.warp_to(now + 400).await;
+    let buy = |points, max_price| buy_from_listing(&program_id, &buyer.pubkey(), &seller.pubkey(), points, max_price, None);
+    assert_rejected(harness.send(&[buy(10, 59)], &[&buyer]).await, InstructionError::InvalidArgument);
+    let seller_before = harness.lamports(&seller.pubkey()).await;
+    harness.send(&[buy(10, 60)], &[&buyer]).await.unwrap();
+    assert_eq!(harness.lamports(&seller.pubkey()).await - seller_before, 10 * 60);
+
+    // and stays at the floor once the decay is over
+    harness.warp_to(now + 10_000).await;
+    let seller_before = harness.lamports(&seller.pubkey()).await;
+    harness.send(&[buy(50, 20)], &[&buyer]).await.unwrap();
+    assert_eq!(harness.lamports(&seller.pubkey()).await - seller_before, 50 * 20);
+}
@@ -0,0 +1,83 @@
+use solana_program::{instruction::InstructionError, pubkey::Pubkey};
+use solana_sdk::signature::{Keypair, Signer};
+
+use super::{assert_rejected, through_partner, Harness};
+use crate::client::*;
+use crate::MAX_AWARD_REASON_LEN;
+
+#[tokio::test]
+async fn transfer_points_moves_balance_between_driver_accounts() {
+    let mut harness = Harness::new().await;
+    let sender = harness.wallet().await;
+    let recipient = harness.wallet().await;
+    let program_id = harness.program_id;
+    harness.give_points(&sender.pubkey(), 1_000);
+    let transfer = |to: &Keypair, amount| transfer_points(&program_id, &sender.pubkey(), &to.pubkey(), amount);
+
+    // the recipient needs a driver account of its own
+    assert_rejected(harness.send(&[transfer(&recipient, 400)], &[&sender]).await, InstructionError::IncorrectProgramId);
+    harness.give_points(&recipient.pubkey(), 0);
+    assert_rejected(harness.send(&[transfer(&recipient, 0)], &[&sender]).await, InstructionError::InvalidArgument);
+    assert_rejected(harness.send(&[transfer(&sender, 400)], &[&sender]).await, InstructionError::InvalidArgument);
+    assert_rejected(harness.send(&[transfer(&recipient, 1_001)], &[&sender]).await, InstructionError::InsufficientFunds);
+
+    harness.resend(&[transfer(&recipient, 400)], &[&sender]).await.unwrap();
+    assert_eq!(harness.points(&sender.pubkey()).await, 600);
+    assert_eq!(harness.points(&recipient.pubkey()).await, 400);
+}
+
+#[tokio::test]
+async fn partner_awards_points_within_its_budget() {
+    let mut harness = Harness::new().await;
+    let driver = harness.wallet().await;
+    let program_id = harness.program_id;
+    let partner_program = harness.partner_program;
+    let admin = harness.admin.insecure_clone();
+    let register = |signer: &Keypair, budget, period_secs, active| register_partner(&program_id, &signer.pubkey(),
+        &signer.pubkey(), partner_program, budget, period_secs, active);
+    let award = |amount, reason: &str| through_partner(&partner_program,
+        award_points(&program_id, &partner_program, &driver.pubkey(), amount, reason.into()));
+
+    assert_rejected(harness.send(&[register(&driver, 1_000, 86_400, true)], &[&driver]).await,
+        InstructionError::MissingRequiredSignature);
+    assert_rejected(harness.send(&[register(&admin, 1_000, 0, true)], &[&admin]).await, InstructionError::InvalidArgument);
+    harness.send(&[register(&admin, 1_000, 86_400, true)], &[&admin]).await.unwrap();
+
+    // the driver needs a driver account to be credited
+    assert_rejected(harness.send(&[award(600, "ride share")], &[]).await, InstructionError::IncorrectProgramId);
+    harness.give_points(&driver.pubkey(), 0);
+    assert_rejected(harness.send(&[award(0, "ride share")], &[]).await, InstructionError::InvalidArgument);
+    assert_rejected(harness.send(&[award(600, &"x".repeat(MAX_AWARD_REASON_LEN + 1))], &[]).await,
+        InstructionError::InvalidArgument);
+    harness.resend(&[award(600, "ride share")], &[]).await.unwrap();
+    assert_eq!(harness.points(&driver.pubkey()).await, 600);
+
+    // the budget is per period
+    assert_rejected(harness.send(&[award(500, "parking")], &[]).await, InstructionError::InsufficientFunds);
+    let now = harness.now().await;
+    harness.warp_to(now + 86_400).await;
+    harness.resend(&[award(500, "parking")], &[]).await.unwrap();
+    assert_eq!(harness.points(&driver.pubkey()).await, 1_100);
+
+    harness.send(&[register(&admin, 1_000, 86_400, false)], &[&admin]).await.unwrap();
+    assert_rejected(harness.send(&[award(100, "parking")], &[]).await, InstructionError::InvalidAccountData);
+}
+
+#[tokio::test]
+async fn award_points_needs_the_registered_program_signature() {
+    let mut harness = Harness::new().await;
+    let driver = harness.wallet().await;
+    let program_id = harness.program_id;
+    let admin = harness.admin.insecure_clone();
+    harness.give_points(&driver.pubkey(), 0);
+    // another program is the registered partner, the stand-in signs with its own authority
+    let registered = Pubkey::new_unique();
+    harness.send(&[register_partner(&program_id, &admin.pubkey(), &admin.pubkey(), registered, 1_000, 86_400, true)], &[&admin])
+        .await.unwrap();
+
+    let mut award = award_points(&program_id, &registered, &driver.pubkey(), 100, "ride share".into());
+    award.accounts[0].pubkey = find_partner_authority_address(&harness.partner_program).0;
+    assert_rejected(harness.send(&[through_partner(&harness.partner_program, award)], &[]).await,
+        InstructionError::MissingRequiredSignature);
+    assert_eq!(harness.points(&driver.pubkey()).await, 0);
+}
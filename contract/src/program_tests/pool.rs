@@ -0,0 +1,88 @@
+use solana_program::instruction::InstructionError;
+use solana_sdk::signature::Signer;
+
+use super::{assert_rejected, Harness};
+use crate::client::*;
+use crate::{pool_swap_quote, LpPositionAccount, PoolAccount, SwapDirection, MAX_POOL_FEE_BPS, MINIMUM_LIQUIDITY};
+
+#[tokio::test]
+async fn first_deposit_locks_the_minimum_liquidity() {
+    let mut harness = Harness::new().await;
+    let provider = harness.wallet().await;
+    let program_id = harness.program_id;
+    let admin = harness.admin.insecure_clone();
+    let pool = find_pool_address(&program_id).0;
+    harness.give_points(&provider.pubkey(), 10_000_000);
+
+    let by_provider = create_pool(&program_id, &provider.pubkey(), &provider.pubkey(), 30);
+    assert_rejected(harness.send(&[by_provider], &[&provider]).await, InstructionError::MissingRequiredSignature);
+    let too_dear = create_pool(&program_id, &admin.pubkey(), &admin.pubkey(), MAX_POOL_FEE_BPS + 1);
+    assert_rejected(harness.send(&[too_dear], &[&admin]).await, InstructionError::InvalidArgument);
+    harness.send(&[create_pool(&program_id, &admin.pubkey(), &admin.pubkey(), 30)], &[&admin]).await.unwrap();
+
+    // a dust first deposit would mint no more than the locked shares
+    assert_rejected(harness.send(&[add_liquidity(&program_id, &provider.pubkey(), 400, 100)], &[&provider]).await,
+        InstructionError::InvalidArgument);
+    harness.send(&[add_liquidity(&program_id, &provider.pubkey(), 4_000_000, 1_000_000)], &[&provider]).await.unwrap();
+    let position: LpPositionAccount = harness.account(&find_lp_address(&program_id, &pool, &provider.pubkey()).0).await;
+    assert_eq!(position.shares, 2_000_000 - MINIMUM_LIQUIDITY);
+    assert_eq!(harness.account::<PoolAccount>(&pool).await.total_shares, 2_000_000);
+
+    // withdrawing every share the provider holds leaves the locked part of the reserves behind
+    let withdraw = remove_liquidity(&program_id, &provider.pubkey(), position.shares, 0, 0);
+    harness.send(&[withdraw], &[&provider]).await.unwrap();
+    let drained: PoolAccount = harness.account(&pool).await;
+    assert_eq!((drained.amp_reserve, drained.sol_reserve, drained.total_shares), (2_000, 500, MINIMUM_LIQUIDITY));
+}
+
+#[tokio::test]
+async fn pool_deposits_withdrawals_and_swaps_respect_their_limits() {
+    let mut harness = Harness::new().await;
+    let first = harness.wallet().await;
+    let second = harness.wallet().await;
+    let trader = harness.wallet().await;
+    let program_id = harness.program_id;
+    let admin = harness.admin.insecure_clone();
+    let pool = find_pool_address(&program_id).0;
+    harness.send(&[create_pool(&program_id, &admin.pubkey(), &admin.pubkey(), 30)], &[&admin]).await.unwrap();
+    assert_rejected(harness.send(&[swap(&program_id, &trader.pubkey(), 10_000, 0, SwapDirection::SolToAmp)], &[&trader])
+        .await, InstructionError::InsufficientFunds);
+
+    harness.give_points(&first.pubkey(), 4_000_000);
+    harness.give_points(&second.pubkey(), 400_000);
+    harness.send(&[add_liquidity(&program_id, &first.pubkey(), 4_000_000, 1_000_000)], &[&first]).await.unwrap();
+    // later deposits follow the reserve ratio, 400k AMP needs 100k lamports
+    assert_rejected(harness.send(&[add_liquidity(&program_id, &second.pubkey(), 400_000, 99_999)], &[&second]).await,
+        InstructionError::InvalidArgument);
+    let second_before = harness.lamports(&second.pubkey()).await;
+    harness.send(&[add_liquidity(&program_id, &second.pubkey(), 400_000, 200_000)], &[&second]).await.unwrap();
+    let position = find_lp_address(&program_id, &pool, &second.pubkey()).0;
+    assert_eq!(harness.account::<LpPositionAccount>(&position).await.shares, 200_000);
+    // the LP account rent comes on top of the deposit
+    assert!(second_before - harness.lamports(&second.pubkey()).await > 100_000);
+
+    // swaps fail below min_amount_out and pay out the constant product quote otherwise
+    let quoted = pool_swap_quote(&harness.account::<PoolAccount>(&pool).await, 10_000, SwapDirection::SolToAmp);
+    let buy_amp = |min_amount_out| swap(&program_id, &trader.pubkey(), 10_000, min_amount_out, SwapDirection::SolToAmp);
+    assert_rejected(harness.send(&[buy_amp(quoted + 1)], &[&trader]).await, InstructionError::InvalidArgument);
+    harness.send(&[buy_amp(quoted)], &[&trader]).await.unwrap();
+    assert_eq!(harness.points(&trader.pubkey()).await, quoted);
+    let sell_amp = |amount_in| swap(&program_id, &trader.pubkey(), amount_in, 0, SwapDirection::AmpToSol);
+    assert_rejected(harness.send(&[sell_amp(quoted + 1)], &[&trader]).await, InstructionError::InsufficientFunds);
+    let quoted_sol = pool_swap_quote(&harness.account::<PoolAccount>(&pool).await, quoted, SwapDirection::AmpToSol);
+    let trader_before = harness.lamports(&trader.pubkey()).await;
+    harness.send(&[sell_amp(quoted)], &[&trader]).await.unwrap();
+    assert_eq!(harness.lamports(&trader.pubkey()).await - trader_before, quoted_sol);
+
+    // withdrawals fail below the minimums or past the position
+    let state: PoolAccount = harness.account(&pool).await;
+    let amp_out = (200_000u128 * state.amp_reserve as u128 / state.total_shares as u128) as u64;
+    let sol_out = (200_000u128 * state.sol_reserve as u128 / state.total_shares as u128) as u64;
+    let withdraw = |shares, min_amp, min_sol| remove_liquidity(&program_id, &second.pubkey(), shares, min_amp, min_sol);
+    for (shares, min_amp, min_sol) in [(0, 0, 0), (200_001, 0, 0), (200_000, amp_out + 1, 0), (200_000, 0, sol_out + 1)] {
+        assert_rejected(harness.send(&[withdraw(shares, min_amp, min_sol)], &[&second]).await,
+            InstructionError::InvalidArgument);
+    }
+    harness.send(&[withdraw(200_000, amp_out, sol_out)], &[&second]).await.unwrap();
+    assert_eq!(harness.points(&second.pubkey()).await, amp_out);
+}
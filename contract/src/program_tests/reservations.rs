@@ -0,0 +1,115 @@
+use solana_program::{instruction::InstructionError, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey};
+use solana_sdk::{account::Account, signature::Signer};
+
+use super::{assert_rejected, Harness};
+use crate::client::*;
+use crate::{ChargerStatus, ReservationAccount, MAX_RESERVATION_LEAD_SECS, MAX_RESERVATION_SECS, RESERVATION_DEPOSIT_LAMPORTS};
+
+#[tokio::test]
+async fn reserve_charger_refuses_an_offline_charger() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let offline = set_charger_status(&program_id, &operator.pubkey(), &charger.address, ChargerStatus::Offline);
+    harness.send(&[offline], &[&operator]).await.unwrap();
+
+    let now = harness.now().await;
+    let reserve = reserve_charger(&program_id, &driver.pubkey(), &charger.address, &operator.pubkey(), now + 600, 1_800);
+    assert_rejected(harness.send(&[reserve], &[&driver]).await, InstructionError::InvalidAccountData);
+    assert!(!harness.exists(&find_reservation_address(&program_id, &charger.address).0).await);
+}
+
+#[tokio::test]
+async fn reservation_holds_the_charger_for_its_driver_until_claimed() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let walk_up = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let reservation = find_reservation_address(&program_id, &charger.address).0;
+
+    let now = harness.now().await;
+    let operator_before = harness.lamports(&operator.pubkey()).await;
+    let reserve = reserve_charger(&program_id, &driver.pubkey(), &charger.address, &operator.pubkey(), now, 1_800);
+    harness.send(std::slice::from_ref(&reserve), &[&driver]).await.unwrap();
+    assert_eq!(harness.lamports(&operator.pubkey()).await - operator_before, RESERVATION_DEPOSIT_LAMPORTS);
+    let booked: ReservationAccount = harness.account(&reservation).await;
+    assert_eq!((booked.driver, booked.start_ts, booked.end_ts), (driver.pubkey(), now, now + 1_800));
+
+    // one reservation per charger, and nobody else may start in the window
+    assert_rejected(harness.resend(&[reserve], &[&driver]).await, InstructionError::AccountAlreadyInitialized);
+    let other = start_session(&program_id, &walk_up.pubkey(), &charger.address, now, None, 0, None);
+    assert_rejected(harness.send(&[other], &[&walk_up]).await, InstructionError::InvalidAccountData);
+
+    let claim = start_session(&program_id, &driver.pubkey(), &charger.address, now, None, 0, None);
+    harness.send(&[claim], &[&driver]).await.unwrap();
+    assert!(!harness.exists(&reservation).await);
+}
+
+#[tokio::test]
+async fn reserve_charger_rejects_windows_out_of_bounds() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let now = harness.now().await;
+
+    for (start, duration) in [(now, 0), (now, MAX_RESERVATION_SECS + 1), (now - 3_600, 600),
+        (now + MAX_RESERVATION_LEAD_SECS + 1, 600)] {
+        let reserve = reserve_charger(&program_id, &driver.pubkey(), &charger.address, &operator.pubkey(), start, duration);
+        assert_rejected(harness.send(&[reserve], &[&driver]).await, InstructionError::InvalidArgument);
+    }
+    let wrong_owner = reserve_charger(&program_id, &driver.pubkey(), &charger.address, &driver.pubkey(), now, 600);
+    assert_rejected(harness.send(&[wrong_owner], &[&driver]).await, InstructionError::InvalidAccountData);
+}
+
+#[tokio::test]
+async fn expire_reservation_returns_the_rent_once_the_window_is_over() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let cranker = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let reservation = find_reservation_address(&program_id, &charger.address).0;
+    let now = harness.now().await;
+    let reserve = reserve_charger(&program_id, &driver.pubkey(), &charger.address, &operator.pubkey(), now + 600, 1_800);
+    harness.send(&[reserve], &[&driver]).await.unwrap();
+
+    let expire = |reserver: &Pubkey| expire_reservation(&program_id, &cranker.pubkey(), &charger.address, reserver);
+    assert_rejected(harness.send(&[expire(&driver.pubkey())], &[&cranker]).await, InstructionError::InvalidArgument);
+
+    harness.warp_to(now + 600 + 1_800).await;
+    assert_rejected(harness.send(&[expire(&cranker.pubkey())], &[&cranker]).await, InstructionError::InvalidAccountData);
+    let rent = harness.lamports(&reservation).await;
+    let driver_before = harness.lamports(&driver.pubkey()).await;
+    harness.send(&[expire(&driver.pubkey())], &[&cranker]).await.unwrap();
+    assert!(!harness.exists(&reservation).await);
+    assert_eq!(harness.lamports(&driver.pubkey()).await - driver_before, rent);
+}
+
+#[tokio::test]
+async fn expire_reservation_only_closes_the_charger_reservation_pda() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let now = harness.now().await;
+
+    // a program-owned account laid out as an ended reservation, but not at the reservation PDA
+    let planted = Pubkey::new_unique();
+    let data = borsh::to_vec(&ReservationAccount {accountType: 6, is_initialized: true, driver: driver.pubkey(),
+        charger: charger.address, start_ts: now - 3_600, end_ts: now - 1_800, deposit_lamports: 0}).unwrap();
+    let account = Account {lamports: LAMPORTS_PER_SOL, data, owner: program_id, executable: false, rent_epoch: 0};
+    harness.context.set_account(&planted, &account.into());
+
+    let mut expire = expire_reservation(&program_id, &driver.pubkey(), &charger.address, &driver.pubkey());
+    expire.accounts[1].pubkey = planted;
+    assert_rejected(harness.send(&[expire], &[&driver]).await, InstructionError::InvalidSeeds);
+    assert!(harness.exists(&planted).await);
+}
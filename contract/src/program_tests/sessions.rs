@@ -0,0 +1,234 @@
+use solana_program::instruction::InstructionError;
+use solana_sdk::signature::{Keypair, Signer};
+
+use super::{assert_rejected, attestation, Harness};
+use crate::client::*;
+use crate::{ChargerAccount, ChargerStatus, SessionAccount, SESSION_REFUND_AFTER_SECS};
+
+#[tokio::test]
+async fn start_session_refuses_an_offline_charger() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let offline = set_charger_status(&program_id, &charger.device.pubkey(), &charger.address, ChargerStatus::Offline);
+    harness.send(&[offline], &[&charger.device]).await.unwrap();
+
+    let now = harness.now().await;
+    let start = start_session(&program_id, &driver.pubkey(), &charger.address, now, None, 0, None);
+    assert_rejected(harness.send(std::slice::from_ref(&start), &[&driver]).await, InstructionError::InvalidAccountData);
+
+    let available = set_charger_status(&program_id, &operator.pubkey(), &charger.address, ChargerStatus::Available);
+    harness.send(&[available], &[&operator]).await.unwrap();
+    harness.resend(&[start], &[&driver]).await.unwrap();
+}
+
+#[tokio::test]
+async fn idle_time_past_the_threshold_is_billed_per_started_minute() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let program_id = harness.program_id;
+    let device = Keypair::new();
+    // 5 minutes of grace, then 1000 lamports per started minute
+    let add = add_charger(&program_id, &operator.pubkey(), &harness.admin.pubkey(), "c1".into(), "Depot".into(),
+        "Pune".into(), "Bay 1".into(), 18.5, 73.8, 11.0, 10, 1, device.pubkey(), 300, 1_000, None);
+    harness.send(&[add], &[&operator]).await.unwrap();
+    let charger = find_charger_address(&program_id, "c1", &operator.pubkey()).0;
+
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger, start_ts, None, 0, None)], &[&driver]).await.unwrap();
+    let session = harness.session_address(&charger, &driver.pubkey(), start_ts);
+    harness.warp_to(start_ts + 600).await;
+    harness.send(&[report_charging_complete(&program_id, &device.pubkey(), &session, &charger)], &[&device]).await.unwrap();
+    let reported: SessionAccount = harness.account(&session).await;
+    assert_eq!(reported.charging_finished_ts, start_ts + 600);
+
+    // idle for 361 secs, 61 past the grace period bill as two minutes
+    let end_ts = start_ts + 600 + 361;
+    harness.warp_to(end_ts).await;
+    let operator_before = harness.lamports(&operator.pubkey()).await;
+    let stop = stop_session(&program_id, &driver.pubkey(), &driver.pubkey(), &charger, &operator.pubkey(), start_ts, end_ts,
+        6_600, None);
+    harness.send(&[attestation(&device, &session, 6_600, end_ts), stop], &[&driver]).await.unwrap();
+
+    let settled: SessionAccount = harness.account(&session).await;
+    assert_eq!(settled.idle_fee_lamports, 2_000);
+    assert_eq!(harness.lamports(&operator.pubkey()).await - operator_before, 600 + 2_000);
+    // points accrue for the 600 charging secs only, a quarter of them on an unverified charger
+    assert_eq!(settled.points_awarded, 1_500);
+}
+
+#[tokio::test]
+async fn report_charging_complete_takes_the_device_once_per_session() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let other = harness.add_charger(&operator, "c2").await;
+    let program_id = harness.program_id;
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0, None)], &[&driver])
+        .await.unwrap();
+    let session = harness.session_address(&charger.address, &driver.pubkey(), start_ts);
+
+    let by_operator = report_charging_complete(&program_id, &operator.pubkey(), &session, &charger.address);
+    assert_rejected(harness.send(&[by_operator], &[&operator]).await, InstructionError::IllegalOwner);
+    let wrong_charger = report_charging_complete(&program_id, &other.device.pubkey(), &session, &other.address);
+    assert_rejected(harness.send(&[wrong_charger], &[&other.device]).await, InstructionError::InvalidAccountData);
+
+    let report = report_charging_complete(&program_id, &charger.device.pubkey(), &session, &charger.address);
+    harness.send(std::slice::from_ref(&report), &[&charger.device]).await.unwrap();
+    assert_rejected(harness.resend(&[report], &[&charger.device]).await, InstructionError::InvalidAccountData);
+}
+
+#[tokio::test]
+async fn delegate_settles_from_the_escrow_and_refunds_the_rest() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let delegate = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, Some(delegate.pubkey()), 10_000,
+        None)], &[&driver]).await.unwrap();
+    let session = harness.session_address(&charger.address, &driver.pubkey(), start_ts);
+    let stop = |signer: &Keypair, end_ts| stop_session(&program_id, &signer.pubkey(), &driver.pubkey(), &charger.address,
+        &operator.pubkey(), start_ts, end_ts, 2_000, None);
+
+    // nobody else may stop it, and the escrow has to cover the whole price for the delegate
+    harness.warp_to(start_ts + 20_000).await;
+    let stranger = harness.wallet().await;
+    assert_rejected(harness.send(&[attestation(&charger.device, &session, 2_000, start_ts + 20_000),
+        stop(&stranger, start_ts + 20_000)], &[&stranger]).await, InstructionError::IllegalOwner);
+    assert_rejected(harness.send(&[attestation(&charger.device, &session, 2_000, start_ts + 20_000),
+        stop(&delegate, start_ts + 20_000)], &[&delegate]).await, InstructionError::InsufficientFunds);
+
+    let driver_before = harness.lamports(&driver.pubkey()).await;
+    let operator_before = harness.lamports(&operator.pubkey()).await;
+    harness.send(&[attestation(&charger.device, &session, 2_000, start_ts + 600), stop(&delegate, start_ts + 600)],
+        &[&delegate]).await.unwrap();
+    assert_eq!(harness.lamports(&operator.pubkey()).await - operator_before, 600);
+    assert_eq!(harness.lamports(&driver.pubkey()).await - driver_before, 9_400);
+    // the points still go to the driver
+    assert_eq!(harness.points(&driver.pubkey()).await, 1_500);
+}
+
+#[tokio::test]
+async fn refund_before_the_timeout_is_rejected() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, Some(Keypair::new().pubkey()), 10_000,
+        None)], &[&driver]).await.unwrap();
+    let session = harness.session_address(&charger.address, &driver.pubkey(), start_ts);
+    let refund = refund_session(&program_id, &driver.pubkey(), &charger.address, start_ts);
+
+    harness.warp_to(start_ts + SESSION_REFUND_AFTER_SECS - 1).await;
+    assert_rejected(harness.send(std::slice::from_ref(&refund), &[&driver]).await, InstructionError::InvalidArgument);
+
+    // once the window is over the driver gets the escrow and the rent back
+    harness.warp_to(start_ts + SESSION_REFUND_AFTER_SECS).await;
+    let held = harness.lamports(&session).await;
+    let driver_before = harness.lamports(&driver.pubkey()).await;
+    harness.resend(&[refund], &[&driver]).await.unwrap();
+    assert!(!harness.exists(&session).await);
+    assert_eq!(harness.lamports(&driver.pubkey()).await - driver_before, held);
+    let charger_account: ChargerAccount = harness.account(&charger.address).await;
+    assert_eq!(charger_account.open_sessions, 0);
+}
+
+#[tokio::test]
+async fn refund_after_the_operator_delivered_energy_settles_instead() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let delegate = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, Some(delegate.pubkey()), 10_000,
+        None)], &[&driver]).await.unwrap();
+    let session = harness.session_address(&charger.address, &driver.pubkey(), start_ts);
+    harness.warp_to(start_ts + 600).await;
+    harness.send(&[report_charging_complete(&program_id, &charger.device.pubkey(), &session, &charger.address)],
+        &[&charger.device]).await.unwrap();
+
+    // the driver walks off and tries to take the escrow back once the window is over
+    let end_ts = start_ts + 600;
+    let settle = |signer: &Keypair| [attestation(&charger.device, &session, 2_000, end_ts), stop_session(&program_id,
+        &signer.pubkey(), &driver.pubkey(), &charger.address, &operator.pubkey(), start_ts, end_ts, 2_000, None)];
+    assert_rejected(harness.send(&settle(&operator), &[&operator]).await, InstructionError::IllegalOwner);
+    harness.warp_to(start_ts + SESSION_REFUND_AFTER_SECS).await;
+    assert_rejected(harness.send(&[refund_session(&program_id, &driver.pubkey(), &charger.address, start_ts)], &[&driver])
+        .await, InstructionError::InvalidAccountData);
+
+    // the operator settles with the attested reading: paid for the energy, the driver gets the rest of the escrow
+    let driver_before = harness.lamports(&driver.pubkey()).await;
+    let operator_before = harness.lamports(&operator.pubkey()).await;
+    harness.resend(&settle(&operator), &[&operator]).await.unwrap();
+    assert_eq!(harness.lamports(&operator.pubkey()).await - operator_before, 600);
+    assert_eq!(harness.lamports(&driver.pubkey()).await - driver_before, 9_400);
+    let settled: SessionAccount = harness.account(&session).await;
+    assert!(settled.settled);
+    assert_eq!(settled.points_awarded, 1_500);
+}
+
+#[tokio::test]
+async fn charger_device_can_settle_a_stale_session() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, Some(Keypair::new().pubkey()), 10_000,
+        None)], &[&driver]).await.unwrap();
+    let session = harness.session_address(&charger.address, &driver.pubkey(), start_ts);
+    let end_ts = start_ts + 600;
+    harness.warp_to(start_ts + SESSION_REFUND_AFTER_SECS).await;
+
+    let operator_before = harness.lamports(&operator.pubkey()).await;
+    let stop = stop_session(&program_id, &charger.device.pubkey(), &driver.pubkey(), &charger.address, &operator.pubkey(),
+        start_ts, end_ts, 2_000, None);
+    harness.send(&[attestation(&charger.device, &session, 2_000, end_ts), stop], &[&charger.device]).await.unwrap();
+    assert_eq!(harness.lamports(&operator.pubkey()).await - operator_before, 600);
+    assert_rejected(harness.send(&[refund_session(&program_id, &driver.pubkey(), &charger.address, start_ts)], &[&driver])
+        .await, InstructionError::InvalidAccountData);
+}
+
+#[tokio::test]
+async fn emission_limits_cap_the_rate_and_the_epoch_budget() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    let admin = harness.admin.insecure_clone();
+    let limits = |signer: &Keypair, epoch_secs| set_emission_limits(&program_id, &signer.pubkey(), 5, epoch_secs, 1_000);
+
+    assert_rejected(harness.send(&[limits(&operator, 3_600)], &[&operator]).await, InstructionError::MissingRequiredSignature);
+    assert_rejected(harness.send(&[limits(&admin, 0)], &[&admin]).await, InstructionError::InvalidArgument);
+    harness.send(&[limits(&admin, 3_600)], &[&admin]).await.unwrap();
+    let too_fast = add_charger(&program_id, &operator.pubkey(), &admin.pubkey(), "c2".into(), "Depot".into(), "Pune".into(),
+        "Bay 2".into(), 18.5, 73.8, 11.0, 10, 1, charger.device.pubkey(), 0, 0, None);
+    assert_rejected(harness.send(&[too_fast], &[&operator]).await, InstructionError::InvalidArgument);
+
+    // 600 seconds at the capped 5 points per second, a quarter of it on an unverified charger: 750 points a session,
+    // the second one gets the 250 left of the epoch and the next epoch starts over
+    let epoch_start = harness.now().await;
+    for (start_ts, total) in [(epoch_start, 750), (epoch_start + 600, 1_000), (epoch_start + 3_600, 1_750)] {
+        harness.warp_to(start_ts).await;
+        let heartbeat = set_charger_status(&program_id, &charger.device.pubkey(), &charger.address, ChargerStatus::Available);
+        let start = start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0, None);
+        harness.send(&[heartbeat, start], &[&charger.device, &driver]).await.unwrap();
+        harness.warp_to(start_ts + 600).await;
+        harness.stop(&charger, &driver, start_ts, start_ts + 600, 2_000, None).await.unwrap();
+        assert_eq!(harness.points(&driver.pubkey()).await, total);
+    }
+}
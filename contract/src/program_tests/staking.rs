@@ -0,0 +1,75 @@
+use solana_program::instruction::InstructionError;
+use solana_sdk::signature::Signer;
+
+use super::{assert_rejected, attestation, Harness};
+use crate::client::*;
+use crate::{SessionAccount, StakeAccount, STAKE_TIERS, UNSTAKE_COOLDOWN_SECS};
+
+#[tokio::test]
+async fn stake_locks_points_for_the_tier_then_cools_down() {
+    let mut harness = Harness::new().await;
+    let driver = harness.wallet().await;
+    let program_id = harness.program_id;
+    harness.give_points(&driver.pubkey(), 50_000);
+    let stake_address = find_stake_address(&program_id, &driver.pubkey()).0;
+
+    for (amount, tier) in [(0, 0), (1_000, STAKE_TIERS.len() as u8)] {
+        assert_rejected(harness.send(&[stake(&program_id, &driver.pubkey(), amount, tier)], &[&driver]).await,
+            InstructionError::InvalidArgument);
+    }
+    assert_rejected(harness.send(&[stake(&program_id, &driver.pubkey(), 50_001, 0)], &[&driver]).await,
+        InstructionError::InsufficientFunds);
+
+    let now = harness.now().await;
+    harness.send(&[stake(&program_id, &driver.pubkey(), 40_000, 1)], &[&driver]).await.unwrap();
+    let staked: StakeAccount = harness.account(&stake_address).await;
+    assert_eq!((staked.amount, staked.tier, staked.lock_until), (40_000, 1, now + STAKE_TIERS[1].0));
+    assert_eq!(harness.points(&driver.pubkey()).await, 10_000);
+    assert_rejected(harness.send(&[stake(&program_id, &driver.pubkey(), 1_000, 0)], &[&driver]).await,
+        InstructionError::InvalidArgument);
+
+    assert_rejected(harness.send(&[unstake(&program_id, &driver.pubkey())], &[&driver]).await,
+        InstructionError::InvalidArgument);
+    harness.warp_to(staked.lock_until).await;
+    harness.resend(&[unstake(&program_id, &driver.pubkey())], &[&driver]).await.unwrap();
+    // a cooling stake takes no top-ups and pays out only after the cool-down
+    assert_rejected(harness.send(&[stake(&program_id, &driver.pubkey(), 1_000, 2)], &[&driver]).await,
+        InstructionError::InvalidAccountData);
+    assert_rejected(harness.resend(&[unstake(&program_id, &driver.pubkey())], &[&driver]).await,
+        InstructionError::InvalidArgument);
+    harness.warp_to(staked.lock_until + UNSTAKE_COOLDOWN_SECS).await;
+    harness.resend(&[unstake(&program_id, &driver.pubkey())], &[&driver]).await.unwrap();
+
+    assert_eq!(harness.points(&driver.pubkey()).await, 50_000);
+    let unstaked: StakeAccount = harness.account(&stake_address).await;
+    assert_eq!((unstaked.amount, unstaked.unstake_requested_at), (0, 0));
+    assert_rejected(harness.resend(&[unstake(&program_id, &driver.pubkey())], &[&driver]).await,
+        InstructionError::InsufficientFunds);
+}
+
+#[tokio::test]
+async fn stake_boosts_session_points() {
+    let mut harness = Harness::new().await;
+    let operator = harness.wallet().await;
+    let driver = harness.wallet().await;
+    let charger = harness.add_charger(&operator, "c1").await;
+    let program_id = harness.program_id;
+    // the full 180 day tier boost, +50%
+    harness.give_points(&driver.pubkey(), 100_000);
+    harness.send(&[stake(&program_id, &driver.pubkey(), 100_000, 2)], &[&driver]).await.unwrap();
+
+    let start_ts = harness.now().await;
+    harness.send(&[start_session(&program_id, &driver.pubkey(), &charger.address, start_ts, None, 0, None)], &[&driver])
+        .await.unwrap();
+    let end_ts = start_ts + 600;
+    harness.warp_to(end_ts).await;
+    let session = harness.session_address(&charger.address, &driver.pubkey(), start_ts);
+    let stop = stop_session(&program_id, &driver.pubkey(), &driver.pubkey(), &charger.address, &operator.pubkey(), start_ts,
+        end_ts, 2_000, None);
+    harness.send(&[attestation(&charger.device, &session, 2_000, end_ts), stop], &[&driver]).await.unwrap();
+
+    // 600 s at 10 points/sec, a quarter of it on an unverified charger, then the boost
+    let settled: SessionAccount = harness.account(&session).await;
+    assert_eq!(settled.points_awarded, 2_250);
+    assert_eq!(harness.points(&driver.pubkey()).await, 2_250);
+}
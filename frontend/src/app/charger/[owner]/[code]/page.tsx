@@ -6,7 +6,7 @@ import { useParams } from "next/navigation";
 import { useEffect, useMemo, useState } from "react";
 import { useConnection, useWallet } from "@solana/wallet-adapter-react";
 import { PublicKey, Transaction } from "@solana/web3.js";
-import { ChargerAccount, DriverAccount, fetchChargerByPda, fetchDriver, findChargerPda, findDriverPda, findListingPda, findSessionPda, ixCancelListing, ixCreateListing, ixStartSession, ixStopSession } from "@/lib/program";
+import { ChargerAccount, DriverAccount, fetchChargerByPda, fetchDeviceAttestation, fetchDriver, findChargerPda, findDriverPda, findListingPda, findSessionPda, ixCancelListing, ixCreateListing, ixDeviceAttestation, ixStartSession, ixStopSession } from "@/lib/program";
 import { useUIStore } from "@/lib/uiStore";
 
 export default function ChargerDetailPage() {
@@ -98,13 +98,16 @@ export default function ChargerDetailPage() {
   }
 
   async function onStop() {
-    if (!publicKey || !driverPda || !chargerPda || !startTs) return;
+    if (!publicKey || !driverPda || !chargerPda || !startTs || !charger) return;
     try {
       setBusy(true);
-      const now = Math.floor(Date.now() / 1000);
-      setEndTs(now);
-      const ix = ixStopSession({ user: publicKey, sessionPda: findSessionPda(chargerPda, driverPda, startTs)[0], driverPda, chargerPda, chargerOwner: ownerPk, endTs: now });
-      const tx = new Transaction().add(ix);
+      const sessionPda = findSessionPda(chargerPda, driverPda, startTs)[0];
+      // the charger signs the final meter reading and end time
+      const { energyWh, endTs: attestedEndTs, signature } = await fetchDeviceAttestation(sessionPda);
+      setEndTs(attestedEndTs);
+      const attestIx = ixDeviceAttestation({ devicePubkey: new PublicKey(charger.device_pubkey), sessionPda, energyWh, endTs: attestedEndTs, signature });
+      const ix = ixStopSession({ user: publicKey, sessionPda, driverPda, chargerPda, chargerOwner: ownerPk, endTs: attestedEndTs, energyWh });
+      const tx = new Transaction().add(attestIx, ix);
       const sig = await sendTransaction(tx, connection);
       await connection.confirmTransaction(sig, "finalized");
       setTxSig(sig);
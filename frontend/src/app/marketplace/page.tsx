@@ -4,7 +4,7 @@ import { useEffect, useState } from "react";
 import { Nav } from "@/components/ui/Nav";
 import { useWallet, useConnection } from "@solana/wallet-adapter-react";
 import { PublicKey, Transaction } from "@solana/web3.js";
-import { ListingAccount, DriverAccount, fetchListings, fetchDriver, findDriverPda, findListingPda, findUserPda, ixBuyFromListing, ixCreateListing, ixCancelListing } from "@/lib/program";
+import { ListingAccount, DriverAccount, currentListingPrice, fetchListings, fetchDriver, findDriverPda, findListingPda, findUserPda, ixBuyFromListing, ixCreateListing, ixCancelListing } from "@/lib/program";
 import { useUIStore } from "@/lib/uiStore";
 import { Spinner } from "@/components/ui/Spinner";
 import { WalletNotConnectedError } from "@/components/ui/WalletNotConnectedError";
@@ -71,7 +71,7 @@ export default function MarketplacePage() {
       const sellerPk = new PublicKey(listings[selected].data.seller);
       const [listingPda] = findListingPda(sellerPk);
       const [userPda] = findUserPda(publicKey);
-      const ix = ixBuyFromListing({ buyer: publicKey, userPda, listingPda, sellerPubkey: sellerPk, buyPoints: buyAmount });
+      const ix = ixBuyFromListing({ buyer: publicKey, userPda, listingPda, sellerPubkey: sellerPk, buyPoints: buyAmount, maxPricePerPointLamports: currentListingPrice(listings[selected].data) });
       const tx = new Transaction().add(ix);
       const sig = await sendTransaction(tx, connection);
       await connection.confirmTransaction(sig, "finalized");
@@ -258,7 +258,7 @@ export default function MarketplacePage() {
                         <div className="flex justify-between items-center p-1.5 bg-gray-700/30 rounded-md">
                           <span className="text-gray-300 text-xs">Lamports per AMP Point</span>
                           <div className="text-right">
-                            <div className="text-sm font-semibold text-gray-200 leading-none">{formatBig(l.data.price_per_point_lamports)}</div>
+                            <div className="text-sm font-semibold text-gray-200 leading-none">{formatBig(currentListingPrice(l.data))}</div>
                             <div className="text-[11px] text-gray-400 leading-none">lamports</div>
                           </div>
                         </div>
@@ -398,12 +398,12 @@ export default function MarketplacePage() {
                     </div>
                     <div>
                       <div className="text-gray-400 text-xs">Lamports per AMP Point</div>
-                      <div className="text-gray-200 font-bold text-base">{formatBig(listings[selected].data.price_per_point_lamports)} lamports</div>
+                      <div className="text-gray-200 font-bold text-base">{formatBig(currentListingPrice(listings[selected].data))} lamports</div>
                     </div>
                   </div>
                   {/* <div className="mt-2.5 text-sm">
                     <div className="text-gray-400 text-xs">SOL per Point</div>
-                    <div className="text-gray-200 font-bold text-base">≈ {lamportsToSolString(listings[selected].data.price_per_point_lamports)} SOL</div>
+                    <div className="text-gray-200 font-bold text-base">≈ {lamportsToSolString(currentListingPrice(listings[selected].data))} SOL</div>
                   </div> */}
                 </div>
 
@@ -435,11 +435,11 @@ export default function MarketplacePage() {
                       <div className="flex justify-between items-center mb-2">
                         <span className="text-gray-300 text-sm">Total Cost</span>
                         <span className="text-xl font-bold text-gray-200">
-                          {buyAmount > 0 ? lamportsToSolString(BigInt(listings[selected].data.price_per_point_lamports) * BigInt(buyAmount)) : "0"} SOL
+                          {buyAmount > 0 ? lamportsToSolString(BigInt(currentListingPrice(listings[selected].data)) * BigInt(buyAmount)) : "0"} SOL
                         </span>
                       </div>
                       <div className="text-xs text-gray-400">
-                        {buyAmount} points × {lamportsToSolString(listings[selected].data.price_per_point_lamports)} SOL
+                        {buyAmount} points × {lamportsToSolString(currentListingPrice(listings[selected].data))} SOL
                       </div>
                     </div>
                   </div>
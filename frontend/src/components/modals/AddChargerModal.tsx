@@ -4,7 +4,7 @@ import { useUIStore } from "@/lib/uiStore";
 import { useWallet, useConnection } from "@solana/wallet-adapter-react";
 import { useMemo, useState } from "react";
 import { PublicKey, Transaction } from "@solana/web3.js";
-import { findChargerPda, ixAddCharger } from "@/lib/program";
+import { MAX_CHARGER_POWER_KW, fetchState, findChargerPda, ixAddCharger } from "@/lib/program";
 import { WalletNotConnectedError } from "@/components/ui/WalletNotConnectedError";
 
 export function AddChargerModal() {
@@ -24,6 +24,10 @@ export function AddChargerModal() {
   const [powerKw, setPowerKw] = useState(7.2);
   const [ratePts, setRatePts] = useState(10);
   const [priceLamportsPerSec, setPriceLamportsPerSec] = useState(1000);
+  const [deviceKey, setDeviceKey] = useState("");
+  // 0 threshold = no idle fee; past it the driver pays per started minute between charging finished and StopSession
+  const [idleThresholdSecs, setIdleThresholdSecs] = useState(0);
+  const [idleFeePerMinLamports, setIdleFeePerMinLamports] = useState(0);
   const [busy, setBusy] = useState(false);
   const [txSig, setTxSig] = useState<string | null>(null);
 
@@ -32,13 +36,32 @@ export function AddChargerModal() {
     return findChargerPda(code, publicKey)[0];
   }, [publicKey, code]);
 
+  // the device signs meter attestations, so it has to be a real ed25519 key rather than a PDA
+  const devicePubkey = useMemo(() => {
+    try {
+      const key = new PublicKey(deviceKey);
+      return PublicKey.isOnCurve(key.toBytes()) ? key : null;
+    } catch {
+      return null;
+    }
+  }, [deviceKey]);
+  const powerKwValid = Number.isFinite(powerKw) && powerKw > 0 && powerKw <= MAX_CHARGER_POWER_KW;
+  const idleValid = Number.isInteger(idleThresholdSecs) && idleThresholdSecs >= 0
+    && Number.isInteger(idleFeePerMinLamports) && idleFeePerMinLamports >= 0;
+  const formError = deviceKey && !devicePubkey ? "Device key is not a valid ed25519 public key."
+    : !powerKwValid ? `Power must be above 0 and at most ${MAX_CHARGER_POWER_KW} kW.`
+    : !idleValid ? "Idle threshold and fee must be whole, non-negative numbers."
+    : null;
+
   if (!open) return null;
 
   async function onRegister() {
-    if (!publicKey || !chargerPda) return;
+    if (!publicKey || !chargerPda || !devicePubkey || formError) return;
     try {
       setBusy(true);
-      const admin = new PublicKey("3shLPzr2Dd4d8XShBMrcUnUUoRTf1iEmDDaTXLiBLAC3");
+      const state = await fetchState(connection);
+      if (!state) throw new Error("Program state is not initialized");
+      const admin = new PublicKey(state.admin);
       const ix = ixAddCharger({
         payer: publicKey,
         chargerPda,
@@ -53,6 +76,9 @@ export function AddChargerModal() {
           power_kw: powerKw,
           rate_points_per_sec: ratePts,
           price_per_sec_lamports: priceLamportsPerSec,
+          device_pubkey: devicePubkey,
+          idle_threshold_secs: idleThresholdSecs,
+          idle_fee_per_min_lamports: idleFeePerMinLamports,
         },
       });
       const tx = new Transaction().add(ix);
@@ -141,6 +167,17 @@ export function AddChargerModal() {
               />
             </label>
 
+            {/* Device Key */}
+            <label className="block">
+              <span className="text-xs text-gray-400 mb-1.5 block">Charger Device Public Key</span>
+              <input 
+                className="w-full rounded-lg px-3.5 py-2.5 bg-white/10 border border-white/20 text-white placeholder:text-gray-400 focus:outline-none focus:ring-2 focus:ring-emerald-500/40 focus:border-emerald-500/40 transition-all duration-300" 
+                placeholder="Key the charger signs meter readings with"
+                value={deviceKey} 
+                onChange={e => setDeviceKey(e.target.value.trim())} 
+              />
+            </label>
+
             {/* City, Latitude, Longitude */}
             <div className="grid gap-3.5 grid-cols-1 md:grid-cols-3">
               <label className="block">
@@ -211,6 +248,36 @@ export function AddChargerModal() {
               </label>
             </div>
 
+            {/* Idle Fee */}
+            <div className="grid gap-3.5 grid-cols-1 md:grid-cols-2">
+              <label className="block">
+                <span className="text-xs text-gray-400 mb-1.5 block">Idle Grace Period: sec (0 = no idle fee)</span>
+                <input 
+                  type="number" 
+                  min={0}
+                  className="w-full rounded-lg px-3.5 py-2.5 bg-white/10 border border-white/20 text-white placeholder:text-gray-400 focus:outline-none focus:ring-2 focus:ring-emerald-500/40 focus:border-emerald-500/40 transition-all duration-300" 
+                  placeholder="0"
+                  value={idleThresholdSecs} 
+                  onChange={e => setIdleThresholdSecs(parseInt(e.target.value) || 0)} 
+                />
+              </label>
+              <label className="block">
+                <span className="text-xs text-gray-400 mb-1.5 block">Idle Fee: lamports/min</span>
+                <input 
+                  type="number" 
+                  min={0}
+                  className="w-full rounded-lg px-3.5 py-2.5 bg-white/10 border border-white/20 text-white placeholder:text-gray-400 focus:outline-none focus:ring-2 focus:ring-emerald-500/40 focus:border-emerald-500/40 transition-all duration-300" 
+                  placeholder="0"
+                  value={idleFeePerMinLamports} 
+                  onChange={e => setIdleFeePerMinLamports(parseInt(e.target.value) || 0)} 
+                />
+              </label>
+            </div>
+
+            {formError && (
+              <p className="text-sm text-red-400">{formError}</p>
+            )}
+
             {/* PDA Info */}
             {/* {chargerPda && (
               <div className="p-3.5 bg-white/5 rounded-lg border border-white/10">
@@ -235,7 +302,7 @@ export function AddChargerModal() {
               </div>
               
               <button 
-                disabled={!publicKey || !chargerPda || busy} 
+                disabled={!publicKey || !chargerPda || !devicePubkey || !!formError || busy} 
                 onClick={onRegister} 
                 className="group/btn relative px-5 py-3 bg-gray-700 text-white font-semibold rounded-lg hover:bg-gray-600 transition-all duration-300 transform hover:scale-105 border border-gray-600/30 disabled:opacity-50 disabled:cursor-not-allowed cursor-pointer"
               >
@@ -1,4 +1,8 @@
-export const PROGRAM_ID = "9oMQzz6sMnnSZ9sDeb5pi8gNyV568qfo4FEGR3uDsyuC";
+export const PROGRAM_ID =
+  process.env.NEXT_PUBLIC_PROGRAM_ID || "9oMQzz6sMnnSZ9sDeb5pi8gNyV568qfo4FEGR3uDsyuC";
+
+// charger device service that signs the final meter reading of a session, StopSession needs it
+export const ATTESTATION_URL = process.env.NEXT_PUBLIC_ATTESTATION_URL || "";
 
 export const CLUSTER = process.env.NEXT_PUBLIC_SOLANA_CLUSTER || "devnet";
 
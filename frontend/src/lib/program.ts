@@ -1,6 +1,6 @@
-import { Connection, PublicKey, SystemProgram, TransactionInstruction } from "@solana/web3.js";
+import { Connection, Ed25519Program, PublicKey, SystemProgram, SYSVAR_INSTRUCTIONS_PUBKEY, TransactionInstruction } from "@solana/web3.js";
 import * as borsh from "borsh";
-import { PROGRAM_ID } from "./config";
+import { ATTESTATION_URL, PROGRAM_ID } from "./config";
 
 export const PROGRAM_PUBKEY = new PublicKey(PROGRAM_ID);
 
@@ -16,16 +16,36 @@ const chargerAddSchema: borsh.Schema = {
     power_kw: "f32",
     rate_points_per_sec: "u64",
     price_per_sec_lamports: "u64",
+    device_pubkey: { array: { type: "u8", len: 32 } },
+    idle_threshold_secs: "u64",
+    idle_fee_per_min_lamports: "u64",
   },
 };
 
-const sessionIxSchema: borsh.Schema = { struct: { time: "i64" } };
+const startSessionIxSchema: borsh.Schema = {
+  struct: {
+    start_ts: "i64",
+    delegate: { option: { array: { type: "u8", len: 32 } } },
+    prepaid_lamports: "u64",
+  },
+};
+
+const stopSessionIxSchema: borsh.Schema = { struct: { end_ts: "i64", energy_wh: "u64" } };
 
 const createListingIxSchema: borsh.Schema = {
-  struct: { amount_points: "u64", price_per_point_lamports: "u64" },
+  struct: { amount_points: "u64", price_per_point_lamports: "u64", expires_at: { option: "i64" } },
 };
 
-const buyListingIxSchema: borsh.Schema = { struct: { buy_points: "u64" } };
+const buyListingIxSchema: borsh.Schema = {
+  struct: { buy_amount_points: "u64", max_price_per_point_lamports: "u64", min_points: { option: "u64" } },
+};
+
+// Account schemas below mirror the full Rust layouts field for field; unit enums are a single u8 index
+const MAX_TARIFF_BANDS = 8;
+
+const tariffBandSchema: borsh.Schema = {
+  struct: { start_hour: "u8", end_hour: "u8", price_multiplier_bps: "u16", points_multiplier_bps: "u16" },
+};
 
 const chargerAccountSchema: borsh.Schema = {
   struct: {
@@ -41,9 +61,37 @@ const chargerAccountSchema: borsh.Schema = {
     power_kw: "f32",
     rate_points_per_sec: "u64",
     price_per_sec_lamports: "u64",
+    device_pubkey: { array: { type: "u8", len: 32 } },
+    status: "u8",
+    last_heartbeat: "i64",
+    idle_threshold_secs: "u64",
+    idle_fee_per_min_lamports: "u64",
+    tariff_band_count: "u8",
+    tariff_bands: { array: { type: tariffBandSchema, len: MAX_TARIFF_BANDS } },
+    bond_lamports: "u64",
+    slash_count: "u8",
+    verified: "bool",
+    verified_at: "i64",
+    verified_by: { array: { type: "u8", len: 32 } },
+    closing_requested_at: "i64",
+    open_sessions: "u32",
   },
 };
 
+export enum ChargerStatus {
+  Available = 0,
+  Charging = 1,
+  Faulted = 2,
+  Offline = 3,
+}
+
+export type TariffBand = {
+  start_hour: number;
+  end_hour: number; // exclusive, below start_hour wraps past midnight
+  price_multiplier_bps: number;
+  points_multiplier_bps: number;
+};
+
 export type ChargerAccount = {
   account_type: number;
   is_initialized: boolean;
@@ -57,6 +105,20 @@ export type ChargerAccount = {
   power_kw: number;
   rate_points_per_sec: bigint;
   price_per_sec_lamports: bigint;
+  device_pubkey: Uint8Array; // 32, signs the meter attestation StopSession checks
+  status: ChargerStatus;
+  last_heartbeat: bigint;
+  idle_threshold_secs: bigint; // 0 = no idle fee
+  idle_fee_per_min_lamports: bigint;
+  tariff_band_count: number;
+  tariff_bands: TariffBand[]; // only the first tariff_band_count are in use
+  bond_lamports: bigint;
+  slash_count: number;
+  verified: boolean; // unverified chargers earn a quarter of their points rate
+  verified_at: bigint;
+  verified_by: Uint8Array; // 32
+  closing_requested_at: bigint; // 0 = open for sessions
+  open_sessions: number;
 };
 
 export async function fetchChargers(connection: Connection) {
@@ -87,6 +149,8 @@ export async function fetchChargerByPda(connection: Connection, chargerPda: Publ
   }
 }
 
+const LISTING_ACCOUNT_SIZE = 1 + 1 + 32 + 8 + 8 + 8 + 1 + 8 + 8 + 8 + 8;
+
 const listingAccountSchema: borsh.Schema = {
   struct: {
     account_type: "u8",
@@ -94,28 +158,66 @@ const listingAccountSchema: borsh.Schema = {
     seller: { array: { type: "u8", len: 32 } },
     amount_total: "u64",
     price_per_point_lamports: "u64",
+    expires_at: "i64",
+    kind: "u8",
+    auction_start_price: "u64",
+    auction_floor_price: "u64",
+    auction_start_ts: "i64",
+    auction_decay_secs: "i64",
   },
 };
 
+export enum ListingKind {
+  FixedPrice = 0,
+  DutchAuction = 1,
+}
+
 export type ListingAccount = {
   account_type: number;
   is_initialized: boolean;
   seller: Uint8Array;
   amount_total: bigint;
-  price_per_point_lamports: bigint;
+  price_per_point_lamports: bigint; // unused for auctions
+  expires_at: bigint; // 0 = never
+  kind: ListingKind;
+  auction_start_price: bigint;
+  auction_floor_price: bigint;
+  auction_start_ts: bigint;
+  auction_decay_secs: bigint;
 };
 
+function unixNow() {
+  return BigInt(Math.floor(Date.now() / 1000));
+}
+
+// Same as the program: expired from expires_at on, until the seller cancels or anyone runs ExpireListing
+export function isListingExpired(listing: ListingAccount, now: bigint = unixNow()) {
+  return listing.expires_at !== BigInt(0) && now >= listing.expires_at;
+}
+
+// Price per point at `now`; Dutch auctions decay linearly from the start price to the floor
+export function currentListingPrice(listing: ListingAccount, now: bigint = unixNow()) {
+  if (listing.kind !== ListingKind.DutchAuction) return listing.price_per_point_lamports;
+  const decaySecs = listing.auction_decay_secs > BigInt(1) ? listing.auction_decay_secs : BigInt(1);
+  let elapsed = now - listing.auction_start_ts;
+  if (elapsed < BigInt(0)) elapsed = BigInt(0);
+  if (elapsed > decaySecs) elapsed = decaySecs;
+  const drop = (listing.auction_start_price - listing.auction_floor_price) * elapsed / decaySecs;
+  return listing.auction_start_price - drop;
+}
+
+// Live listings only: expired ones still hold points but BuyFromListing rejects them
 export async function fetchListings(connection: Connection) {
-  // ListingAccount has fixed size: 49 bytes; filter client-side to avoid RPC quirks
+  // ListingAccount has a fixed size of LISTING_ACCOUNT_SIZE bytes; filter client-side to avoid RPC quirks
   const accounts = await connection.getProgramAccounts(PROGRAM_PUBKEY);
+  const now = unixNow();
   const items: Array<{ pubkey: PublicKey; data: ListingAccount }> = [];
   for (const acc of accounts) {
     try {
-      // new layout adds 1-byte account_type at the beginning; minimum size now 50 bytes
-      if (acc.account.data.length < 50) continue;
-      const buf = acc.account.data.subarray(0, 50);
-      const data = borsh.deserialize(listingAccountSchema, buf) as ListingAccount;
-      if (data && data.account_type === 4 && data.is_initialized && data.amount_total > BigInt(0)) {
+      if (acc.account.data.length !== LISTING_ACCOUNT_SIZE) continue;
+      const data = borsh.deserialize(listingAccountSchema, acc.account.data) as ListingAccount;
+      if (data && data.account_type === 4 && data.is_initialized && data.amount_total > BigInt(0)
+        && !isListingExpired(data, now)) {
         items.push({ pubkey: acc.pubkey, data });
       }
     } catch {
@@ -175,7 +277,108 @@ export async function fetchUser(connection: Connection, userPda: PublicKey): Pro
   }
 }
 
+const stateAccountSchema: borsh.Schema = {
+  struct: {
+    accountType: "u8",
+    is_initialized: "bool",
+    admin: { array: { type: "u8", len: 32 } },
+    pending_admin: { array: { type: "u8", len: 32 } },
+    registration_fee: "u64",
+    fee_mint: { array: { type: "u8", len: 32 } },
+    max_rate_points_per_sec: "u64",
+    epoch_secs: "i64",
+    epoch_budget: "u64",
+    epoch_start: "i64",
+    epoch_issued: "u64",
+    paused: "bool",
+    registration_paused: "bool",
+    sessions_paused: "bool",
+    marketplace_paused: "bool",
+  },
+};
+
+export type StateAccount = {
+  accountType: number;
+  is_initialized: boolean;
+  admin: Uint8Array; // receives registration fees, AddCharger must pass it
+  pending_admin: Uint8Array; // all zero = no transfer in progress
+  registration_fee: bigint;
+  fee_mint: Uint8Array; // all zero = fee in lamports
+  max_rate_points_per_sec: bigint;
+  epoch_secs: bigint;
+  epoch_budget: bigint;
+  epoch_start: bigint;
+  epoch_issued: bigint;
+  paused: boolean;
+  registration_paused: boolean;
+  sessions_paused: boolean;
+  marketplace_paused: boolean;
+};
+
+export async function fetchState(connection: Connection): Promise<StateAccount | null> {
+  const info = await connection.getAccountInfo(findStatePda()[0]);
+  if (!info?.data) return null;
+  try {
+    const data = borsh.deserialize(stateAccountSchema, info.data) as StateAccount;
+    if (data.accountType !== 8 || !data.is_initialized) return null;
+    return data;
+  } catch {
+    return null;
+  }
+}
+
+export type DeviceAttestation = {
+  energyWh: bigint;
+  endTs: number;
+  signature: Uint8Array; // 64, by the charger device over session ‖ energy_wh ‖ end_ts
+};
+
+// The charger's device service signs the final meter reading for a session
+export async function fetchDeviceAttestation(sessionPda: PublicKey): Promise<DeviceAttestation> {
+  if (!ATTESTATION_URL) throw new Error("NEXT_PUBLIC_ATTESTATION_URL is not set");
+  const res = await fetch(`${ATTESTATION_URL}/${sessionPda.toBase58()}`);
+  if (!res.ok) throw new Error(`Charger attestation failed: ${res.status}`);
+  const body = (await res.json()) as { energy_wh: string | number; end_ts: number; signature: string };
+  return {
+    energyWh: BigInt(body.energy_wh),
+    endTs: body.end_ts,
+    signature: Uint8Array.from(Buffer.from(body.signature, "base64")),
+  };
+}
+
 // ----- PDA helpers -----
+export function findStatePda() {
+  return PublicKey.findProgramAddressSync([Buffer.from("charge2earn_state")], PROGRAM_PUBKEY);
+}
+
+export function findChargerVaultPda(chargerPda: PublicKey) {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("charger_vault1"), chargerPda.toBuffer()],
+    PROGRAM_PUBKEY
+  );
+}
+
+export function findFeeWaiverPda(operator: PublicKey) {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("fee_waiver1"), operator.toBuffer()],
+    PROGRAM_PUBKEY
+  );
+}
+
+export function findReservationPda(chargerPda: PublicKey) {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("reservation1"), chargerPda.toBuffer()],
+    PROGRAM_PUBKEY
+  );
+}
+
+export function findStakePda(owner: PublicKey) {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("stake1"), owner.toBuffer()],
+    PROGRAM_PUBKEY
+  );
+}
+
 export function findChargerPda(code: string, owner: PublicKey) {
   return PublicKey.findProgramAddressSync(
     [Buffer.from("charger1"), Buffer.from(code), owner.toBuffer()],
@@ -191,9 +394,10 @@ export function findDriverPda(owner: PublicKey) {
 }
 
 export function findSessionPda(chargerPda: PublicKey, driverPda: PublicKey, startTs: number) {
-  const serialized = borsh.serialize(sessionIxSchema, { time: BigInt(startTs) });
+  const serialized = Buffer.alloc(8);
+  serialized.writeBigInt64LE(BigInt(startTs));
   return PublicKey.findProgramAddressSync(
-    [Buffer.from("session1"), chargerPda.toBuffer(), driverPda.toBuffer(), Buffer.from(serialized)],
+    [Buffer.from("session1"), chargerPda.toBuffer(), driverPda.toBuffer(), serialized],
     PROGRAM_PUBKEY
   );
 }
@@ -205,6 +409,27 @@ export function findListingPda(seller: PublicKey) {
   );
 }
 
+export function findFleetPda(admin: PublicKey) {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("fleet1"), admin.toBuffer()],
+    PROGRAM_PUBKEY
+  );
+}
+
+export function findFleetVaultPda(fleetPda: PublicKey) {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("fleet_vault1"), fleetPda.toBuffer()],
+    PROGRAM_PUBKEY
+  );
+}
+
+export function findFleetMemberPda(fleetPda: PublicKey, driver: PublicKey) {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("fleet_member1"), fleetPda.toBuffer(), driver.toBuffer()],
+    PROGRAM_PUBKEY
+  );
+}
+
 export function findUserPda(user: PublicKey) {
   return PublicKey.findProgramAddressSync(
     [Buffer.from("user1"), user.toBuffer()],
@@ -213,6 +438,8 @@ export function findUserPda(user: PublicKey) {
 }
 
 // ----- Instruction builders -----
+export const MAX_CHARGER_POWER_KW = 1000; // AddCharger rejects a rated power of 0 or above this
+
 export function ixAddCharger(params: {
   payer: PublicKey;
   chargerPda: PublicKey;
@@ -227,6 +454,9 @@ export function ixAddCharger(params: {
     power_kw: number;
     rate_points_per_sec: bigint | number;
     price_per_sec_lamports: bigint | number;
+    device_pubkey: PublicKey;
+    idle_threshold_secs: bigint | number; // 0 disables idle fees
+    idle_fee_per_min_lamports: bigint | number;
   };
 }) {
   const data = Buffer.concat([
@@ -236,9 +466,13 @@ export function ixAddCharger(params: {
         ...params.data,
         rate_points_per_sec: BigInt(params.data.rate_points_per_sec),
         price_per_sec_lamports: BigInt(params.data.price_per_sec_lamports),
+        device_pubkey: params.data.device_pubkey.toBytes(),
+        idle_threshold_secs: BigInt(params.data.idle_threshold_secs),
+        idle_fee_per_min_lamports: BigInt(params.data.idle_fee_per_min_lamports),
       })
     ),
   ]);
+  // registration fee in lamports; an SPL fee mint also needs the payer/admin token accounts and the token program
   return new TransactionInstruction({
     programId: PROGRAM_PUBKEY,
     keys: [
@@ -246,6 +480,9 @@ export function ixAddCharger(params: {
       { pubkey: params.chargerPda, isSigner: false, isWritable: true },
       { pubkey: params.admin, isSigner: false, isWritable: true },
       { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: findStatePda()[0], isSigner: false, isWritable: false },
+      { pubkey: findChargerVaultPda(params.chargerPda)[0], isSigner: false, isWritable: true },
+      { pubkey: findFeeWaiverPda(params.payer)[0], isSigner: false, isWritable: false },
     ],
     data,
   });
@@ -257,40 +494,94 @@ export function ixStartSession(params: {
   sessionPda: PublicKey;
   chargerPda: PublicKey;
   startTs: number;
+  delegate?: PublicKey | null;
+  prepaidLamports?: bigint | number;
 }) {
-  const payload = Buffer.from(borsh.serialize(sessionIxSchema, { time: BigInt(params.startTs) }));
+  const payload = Buffer.from(
+    borsh.serialize(startSessionIxSchema, {
+      start_ts: BigInt(params.startTs),
+      delegate: params.delegate ? params.delegate.toBytes() : null,
+      prepaid_lamports: BigInt(params.prepaidLamports ?? 0),
+    })
+  );
   return new TransactionInstruction({
     programId: PROGRAM_PUBKEY,
     keys: [
-      { pubkey: params.user, isSigner: true, isWritable: false },
+      { pubkey: params.user, isSigner: true, isWritable: true },
       { pubkey: params.driverPda, isSigner: false, isWritable: true },
       { pubkey: params.sessionPda, isSigner: false, isWritable: true },
-      { pubkey: params.chargerPda, isSigner: false, isWritable: false },
+      { pubkey: params.chargerPda, isSigner: false, isWritable: true },
       { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: findReservationPda(params.chargerPda)[0], isSigner: false, isWritable: true },
+      { pubkey: findStatePda()[0], isSigner: false, isWritable: false },
     ],
     data: Buffer.concat([Buffer.from([1]), payload]),
   });
 }
 
+// Ed25519 precompile instruction carrying the device signature; StopSession must come right after it
+export function ixDeviceAttestation(params: {
+  devicePubkey: PublicKey;
+  sessionPda: PublicKey;
+  energyWh: bigint | number;
+  endTs: number;
+  signature: Uint8Array;
+}) {
+  const message = Buffer.alloc(48);
+  params.sessionPda.toBuffer().copy(message, 0);
+  message.writeBigUInt64LE(BigInt(params.energyWh), 32);
+  message.writeBigInt64LE(BigInt(params.endTs), 40);
+  return Ed25519Program.createInstructionWithPublicKey({
+    publicKey: params.devicePubkey.toBytes(),
+    message,
+    signature: params.signature,
+    instructionIndex: 0xffff, // key, signature and message all live in this instruction
+  });
+}
+
+// user signs: the driver, the session delegate, or the charger operator/device once past the refund window.
+// driver is the session's driver wallet (defaults to user); fleet is set for sessions started with a fleet.
 export function ixStopSession(params: {
   user: PublicKey;
+  driver?: PublicKey;
   sessionPda: PublicKey;
   driverPda: PublicKey;
   chargerPda: PublicKey;
   chargerOwner: PublicKey;
   endTs: number;
+  energyWh: bigint | number;
+  fleet?: { admin: PublicKey; pointsToFleet: boolean } | null;
 }) {
-  const payload = Buffer.from(borsh.serialize(sessionIxSchema, { time: BigInt(params.endTs) }));
+  const driver = params.driver ?? params.user;
+  const payload = Buffer.from(
+    borsh.serialize(stopSessionIxSchema, { end_ts: BigInt(params.endTs), energy_wh: BigInt(params.energyWh) })
+  );
+  const keys = [
+    { pubkey: params.user, isSigner: true, isWritable: true },
+    { pubkey: params.sessionPda, isSigner: false, isWritable: true },
+    { pubkey: params.driverPda, isSigner: false, isWritable: true },
+    { pubkey: params.chargerPda, isSigner: false, isWritable: true },
+    { pubkey: params.chargerOwner, isSigner: false, isWritable: true },
+    { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+    { pubkey: SYSVAR_INSTRUCTIONS_PUBKEY, isSigner: false, isWritable: false },
+    { pubkey: findStakePda(driver)[0], isSigner: false, isWritable: false },
+    { pubkey: driver, isSigner: false, isWritable: true },
+    { pubkey: findStatePda()[0], isSigner: false, isWritable: true },
+  ];
+  if (params.fleet) {
+    const [fleetPda] = findFleetPda(params.fleet.admin);
+    keys.push(
+      { pubkey: fleetPda, isSigner: false, isWritable: false },
+      { pubkey: findFleetMemberPda(fleetPda, driver)[0], isSigner: false, isWritable: true },
+      { pubkey: findFleetVaultPda(fleetPda)[0], isSigner: false, isWritable: true },
+    );
+    if (params.fleet.pointsToFleet) {
+      keys.push({ pubkey: findDriverPda(params.fleet.admin)[0], isSigner: false, isWritable: true });
+    }
+  }
   return new TransactionInstruction({
     programId: PROGRAM_PUBKEY,
-    keys: [
-      { pubkey: params.user, isSigner: true, isWritable: false },
-      { pubkey: params.sessionPda, isSigner: false, isWritable: true },
-      { pubkey: params.driverPda, isSigner: false, isWritable: true },
-      { pubkey: params.chargerPda, isSigner: false, isWritable: true },
-      { pubkey: params.chargerOwner, isSigner: false, isWritable: true },
-      { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
-    ],
+    keys,
     data: Buffer.concat([Buffer.from([2]), payload]),
   });
 }
@@ -301,20 +592,23 @@ export function ixCreateListing(params: {
   listingPda: PublicKey;
   amountPoints: number | bigint;
   pricePerPointLamports: number | bigint;
+  expiresAt?: number | null; // unix ts, omitted = never expires
 }) {
   const payload = Buffer.from(
     borsh.serialize(createListingIxSchema, {
       amount_points: BigInt(params.amountPoints),
       price_per_point_lamports: BigInt(params.pricePerPointLamports),
+      expires_at: params.expiresAt ? BigInt(params.expiresAt) : null,
     })
   );
   return new TransactionInstruction({
     programId: PROGRAM_PUBKEY,
     keys: [
-      { pubkey: params.seller, isSigner: true, isWritable: false },
+      { pubkey: params.seller, isSigner: true, isWritable: true },
       { pubkey: params.driverPda, isSigner: false, isWritable: true },
       { pubkey: params.listingPda, isSigner: false, isWritable: true },
       { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: findStatePda()[0], isSigner: false, isWritable: false },
     ],
     data: Buffer.concat([Buffer.from([3]), payload]),
   });
@@ -326,16 +620,25 @@ export function ixBuyFromListing(params: {
   listingPda: PublicKey;
   sellerPubkey: PublicKey;
   buyPoints: number | bigint;
+  maxPricePerPointLamports: number | bigint; // slippage guard against the listing's current price
+  minPoints?: number | bigint | null; // accept a partial fill down to this many points
 }) {
-  const payload = Buffer.from(borsh.serialize(buyListingIxSchema, { buy_points: BigInt(params.buyPoints) }));
+  const payload = Buffer.from(
+    borsh.serialize(buyListingIxSchema, {
+      buy_amount_points: BigInt(params.buyPoints),
+      max_price_per_point_lamports: BigInt(params.maxPricePerPointLamports),
+      min_points: params.minPoints == null ? null : BigInt(params.minPoints),
+    })
+  );
   return new TransactionInstruction({
     programId: PROGRAM_PUBKEY,
     keys: [
-      { pubkey: params.buyer, isSigner: true, isWritable: false },
+      { pubkey: params.buyer, isSigner: true, isWritable: true },
       { pubkey: params.userPda, isSigner: false, isWritable: true },
       { pubkey: params.listingPda, isSigner: false, isWritable: true },
       { pubkey: params.sellerPubkey, isSigner: false, isWritable: true },
       { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: findStatePda()[0], isSigner: false, isWritable: false },
     ],
     data: Buffer.concat([Buffer.from([4]), payload]),
   });